@@ -1,13 +1,18 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use std::fs::{self, create_dir_all, read_dir, remove_file, write};
-use std::path::PathBuf;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::Mutex;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use rusqlite::{params, Connection};
-use serde::Deserialize;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rusqlite::{params, Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use serde_json::{json, Value};
 use tauri::{Manager, State};
 
@@ -17,17 +22,115 @@ struct AppState {
     db: Mutex<Option<Connection>>,
     db_path: PathBuf,
     backup_dir: PathBuf,
+    image_dir: PathBuf,
+    // Release builds have no console, so failed calls are kept here for GET /diagnostics/errors.
+    errors: Mutex<VecDeque<Value>>,
+    // Every print route sends through this, so tests can swap the spooler for a recorder.
+    transport: Box<dyn PrintTransport>,
+    // One-time token from POST /app/restart/token, so a stray call can't relaunch mid-sale.
+    restart_token: Mutex<Option<(String, Instant)>>,
 }
 
-fn with_db<F, R>(state: &AppState, f: F) -> Result<R, String>
+const MAX_RECENT_ERRORS: usize = 50;
+const RESTART_TOKEN_TTL: Duration = Duration::from_secs(60);
+
+// What the frontend receives on failure: `{ code, message }`. Plain strings from handlers are
+// input problems (Validation); SQLite and I/O failures are Internal; not-found and uniqueness
+// clashes are built explicitly where they happen.
+#[derive(Debug, Clone, PartialEq)]
+enum ApiError {
+    NotFound(String),
+    Validation(String),
+    Conflict(String),
+    Internal(String),
+}
+
+impl ApiError {
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Validation(_) => "validation",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            ApiError::NotFound(m) | ApiError::Validation(m) | ApiError::Conflict(m) | ApiError::Internal(m) => m,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl Serialize for ApiError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        json!({ "code": self.code(), "message": self.message() }).serialize(serializer)
+    }
+}
+
+// Bare strings are messages about the caller's input. Database and environment failures must
+// arrive as rusqlite errors or go through `internal`, so the UI can tell the two apart.
+impl From<String> for ApiError {
+    fn from(message: String) -> Self { ApiError::Validation(message) }
+}
+
+impl From<&str> for ApiError {
+    fn from(message: &str) -> Self { ApiError::Validation(message.to_string()) }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(e: rusqlite::Error) -> Self { ApiError::Internal(e.to_string()) }
+}
+
+// For `.map_err(internal("Backup copy"))` on I/O and other environment failures.
+fn internal<E: std::fmt::Display>(context: &'static str) -> impl Fn(E) -> ApiError {
+    move |e| ApiError::Internal(format!("{}: {}", context, e))
+}
+
+fn record_error(state: &AppState, method: &str, path: &str, error: &ApiError) {
+    if let Ok(mut errors) = state.errors.lock() {
+        if errors.len() >= MAX_RECENT_ERRORS { errors.pop_front(); }
+        errors.push_back(json!({ "at": simple_ts(), "method": method, "path": path, "code": error.code(), "error": error.message() }));
+    }
+}
+
+fn with_db<F, R>(state: &AppState, f: F) -> Result<R, ApiError>
 where
-    F: FnOnce(&Connection) -> Result<R, String>,
+    F: FnOnce(&Connection) -> Result<R, ApiError>,
 {
-    let guard = state.db.lock().map_err(|e| e.to_string())?;
-    let conn = guard.as_ref().ok_or("Database not available")?;
+    let guard = state.db.lock().map_err(|_| ApiError::Internal("Database lock poisoned".to_string()))?;
+    let conn = guard.as_ref().ok_or_else(|| ApiError::Internal("Database not available".to_string()))?;
     f(conn)
 }
 
+const HEALTH_LOCK_TIMEOUT_MS: u64 = 2_000;
+
+// Polls the mutex instead of blocking so a wedged handler shows up as unhealthy rather than hanging the probe.
+fn db_health(state: &AppState) -> Result<(), ApiError> {
+    let started = std::time::Instant::now();
+    let guard = loop {
+        match state.db.try_lock() {
+            Ok(g) => break g,
+            Err(std::sync::TryLockError::Poisoned(_)) => return Err(ApiError::Internal("Database lock poisoned".to_string())),
+            Err(std::sync::TryLockError::WouldBlock) => {
+                if started.elapsed().as_millis() as u64 >= HEALTH_LOCK_TIMEOUT_MS {
+                    return Err(ApiError::Internal(format!("Database busy for over {} ms", HEALTH_LOCK_TIMEOUT_MS)));
+                }
+                std::thread::sleep(std::time::Duration::from_millis(25));
+            }
+        }
+    };
+    let conn = guard.as_ref().ok_or_else(|| ApiError::Internal("Database not available".to_string()))?;
+    conn.query_row("SELECT 1", [], |r| r.get::<_, i64>(0)).map_err(internal("Query failed"))?;
+    Ok(())
+}
+
 // -- schema -------------------------------------------------------------------
 
 const SCHEMA: &str = r#"
@@ -43,7 +146,8 @@ CREATE TABLE IF NOT EXISTS settings (
 CREATE TABLE IF NOT EXISTS categories (
   id INTEGER PRIMARY KEY AUTOINCREMENT,
   name TEXT NOT NULL UNIQUE,
-  is_active INTEGER NOT NULL DEFAULT 1
+  is_active INTEGER NOT NULL DEFAULT 1,
+  sort_order INTEGER NOT NULL DEFAULT 0
 );
 CREATE TABLE IF NOT EXISTS products (
   id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -52,6 +156,13 @@ CREATE TABLE IF NOT EXISTS products (
   category_id INTEGER,
   price_cents INTEGER NOT NULL,
   is_available INTEGER NOT NULL DEFAULT 1,
+  image_path TEXT,
+  tax_rate_bps INTEGER NOT NULL DEFAULT 0,
+  stock_qty INTEGER,
+  low_stock_threshold INTEGER NOT NULL DEFAULT 0,
+  cost_cents INTEGER NOT NULL DEFAULT 0,
+  deleted_at TEXT,
+  barcode TEXT,
   created_at TEXT NOT NULL DEFAULT (datetime('now','localtime')),
   updated_at TEXT NOT NULL DEFAULT (datetime('now','localtime')),
   FOREIGN KEY (category_id) REFERENCES categories(id)
@@ -59,7 +170,7 @@ CREATE TABLE IF NOT EXISTS products (
 CREATE INDEX IF NOT EXISTS idx_products_name ON products(name);
 CREATE INDEX IF NOT EXISTS idx_products_available ON products(is_available);
 CREATE INDEX IF NOT EXISTS idx_products_category ON products(category_id);
-CREATE UNIQUE INDEX IF NOT EXISTS idx_products_item_no ON products(item_no) WHERE item_no IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_products_barcode ON products(barcode) WHERE barcode IS NOT NULL;
 CREATE TABLE IF NOT EXISTS bills (
   id INTEGER PRIMARY KEY AUTOINCREMENT,
   bill_no TEXT NOT NULL UNIQUE,
@@ -69,8 +180,21 @@ CREATE TABLE IF NOT EXISTS bills (
     payment_mode TEXT NOT NULL DEFAULT 'cash',
     split_cash_cents INTEGER NOT NULL DEFAULT 0,
     split_online_cents INTEGER NOT NULL DEFAULT 0,
+  tip_cents INTEGER NOT NULL DEFAULT 0,
+  surcharge_cents INTEGER NOT NULL DEFAULT 0,
+  amount_tendered_cents INTEGER,
+  change_cents INTEGER,
+  is_training INTEGER NOT NULL DEFAULT 0,
+  cashier TEXT,
+  table_no TEXT,
+  total_tax_cents INTEGER NOT NULL DEFAULT 0,
+  customer_id INTEGER,
+  discount_before_tax INTEGER NOT NULL DEFAULT 1,
+  opened_at TEXT,
   total_cents INTEGER NOT NULL,
-  created_at TEXT NOT NULL DEFAULT (datetime('now','localtime'))
+  created_at TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+  voided_at TEXT,
+  void_reason TEXT
 );
 CREATE INDEX IF NOT EXISTS idx_bills_created_at ON bills(created_at);
 CREATE INDEX IF NOT EXISTS idx_bills_bill_no ON bills(bill_no);
@@ -82,15 +206,183 @@ CREATE TABLE IF NOT EXISTS bill_items (
   unit_price_cents INTEGER NOT NULL,
   qty INTEGER NOT NULL,
   line_total_cents INTEGER NOT NULL,
+  is_comp INTEGER NOT NULL DEFAULT 0,
+  comp_reason TEXT,
+  discount_cents INTEGER NOT NULL DEFAULT 0,
+  tax_rate_bps INTEGER NOT NULL DEFAULT 0,
+  tax_cents INTEGER NOT NULL DEFAULT 0,
+  cost_cents INTEGER NOT NULL DEFAULT 0,
   FOREIGN KEY (bill_id) REFERENCES bills(id) ON DELETE CASCADE,
   FOREIGN KEY (product_id) REFERENCES products(id)
 );
 CREATE INDEX IF NOT EXISTS idx_bill_items_bill_id ON bill_items(bill_id);
 CREATE INDEX IF NOT EXISTS idx_bill_items_product_id ON bill_items(product_id);
+CREATE TABLE IF NOT EXISTS stock_adjustments (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  product_id INTEGER NOT NULL,
+  delta INTEGER NOT NULL,
+  reason TEXT,
+  created_at TEXT NOT NULL,
+  FOREIGN KEY (product_id) REFERENCES products(id)
+);
+CREATE TABLE IF NOT EXISTS product_price_history (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  product_id INTEGER NOT NULL,
+  old_price_cents INTEGER NOT NULL,
+  new_price_cents INTEGER NOT NULL,
+  changed_at TEXT NOT NULL,
+  FOREIGN KEY (product_id) REFERENCES products(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_price_history_product ON product_price_history(product_id);
+CREATE TABLE IF NOT EXISTS bill_payments (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  bill_id INTEGER NOT NULL,
+  method TEXT NOT NULL,
+  amount_cents INTEGER NOT NULL,
+  FOREIGN KEY (bill_id) REFERENCES bills(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_bill_payments_bill_id ON bill_payments(bill_id);
+CREATE TABLE IF NOT EXISTS customers (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name TEXT NOT NULL,
+  phone TEXT NOT NULL UNIQUE,
+  created_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS audit_log (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  action TEXT NOT NULL,
+  entity TEXT NOT NULL,
+  entity_id INTEGER,
+  detail TEXT,
+  created_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_audit_log_action ON audit_log(action);
+CREATE TABLE IF NOT EXISTS held_orders (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  label TEXT NOT NULL,
+  payload TEXT NOT NULL,
+  created_at TEXT NOT NULL
+);
 INSERT OR IGNORE INTO settings(key, value) VALUES ('bill_seq', '0');
 INSERT OR IGNORE INTO settings(key, value) VALUES ('discount_rate_bps', '0');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('require_discount_reason', '0');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('max_void_age_hours', '0');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('receipt_condensed_overflow', '0');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('card_surcharge_bps', '0');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('default_printer', 'Rugtek printer');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('backup_retention_count', '30');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('backup_compress', '0');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('receipt_width', '48');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('receipt_qr_enabled', '0');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('receipt_qr_base_url', '');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('cash_drawer_enabled', '0');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('bill_prefix', 'MNE');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('bill_pad_width', '6');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('bill_no_date', '0');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('bill_seq_reset', 'never');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('max_discount_bps', '5000');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('undo_window_minutes', '5');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('cut_mode', 'full');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('currency_symbol', 'Rs');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('thousands_separator', '');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('receipt_logo_path', '');
+INSERT OR IGNORE INTO settings(key, value) VALUES ('discount_before_tax', '1');
 "#;
 
+type Migration = fn(&Connection) -> rusqlite::Result<()>;
+
+// Append-only, one entry per feature in the order they shipped: entry N upgrades a
+// database from schema_version N to N+1. Columns go here (plus the CREATE in SCHEMA);
+// new tables only need SCHEMA.
+const MIGRATIONS: &[Migration] = &[
+    |c| {
+        add_column(c, "products", "item_no", "INTEGER")?;
+        add_column(c, "bills", "payment_mode", "TEXT NOT NULL DEFAULT 'cash'")?;
+        add_column(c, "bills", "split_cash_cents", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column(c, "bills", "split_online_cents", "INTEGER NOT NULL DEFAULT 0")
+    },
+    |c| add_column(c, "bills", "discount_before_tax", "INTEGER NOT NULL DEFAULT 1"),
+    |c| {
+        add_column(c, "bill_items", "is_comp", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column(c, "bill_items", "comp_reason", "TEXT")
+    },
+    |c| add_column(c, "products", "image_path", "TEXT"),
+    |c| add_column(c, "bills", "tip_cents", "INTEGER NOT NULL DEFAULT 0"),
+    |c| add_column(c, "bills", "surcharge_cents", "INTEGER NOT NULL DEFAULT 0"),
+    |c| {
+        add_column(c, "bills", "voided_at", "TEXT")?;
+        add_column(c, "bills", "void_reason", "TEXT")
+    },
+    |c| add_column(c, "bill_items", "discount_cents", "INTEGER NOT NULL DEFAULT 0"),
+    |c| add_column(c, "bills", "opened_at", "TEXT"),
+    |c| {
+        add_column(c, "bills", "amount_tendered_cents", "INTEGER")?;
+        add_column(c, "bills", "change_cents", "INTEGER")
+    },
+    |c| add_column(c, "bills", "is_training", "INTEGER NOT NULL DEFAULT 0"),
+    |c| add_column(c, "bills", "cashier", "TEXT"),
+    |c| add_column(c, "bills", "table_no", "TEXT"),
+    |c| {
+        add_column(c, "bills", "total_tax_cents", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column(c, "bill_items", "tax_rate_bps", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column(c, "bill_items", "tax_cents", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column(c, "products", "tax_rate_bps", "INTEGER NOT NULL DEFAULT 0")
+    },
+    |c| add_column(c, "products", "stock_qty", "INTEGER"),
+    |c| add_column(c, "products", "low_stock_threshold", "INTEGER NOT NULL DEFAULT 0"),
+    |c| {
+        add_column(c, "products", "cost_cents", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column(c, "bill_items", "cost_cents", "INTEGER NOT NULL DEFAULT 0")
+    },
+    |c| add_column(c, "products", "deleted_at", "TEXT"),
+    |c| add_column(c, "products", "barcode", "TEXT"),
+    |c| add_column(c, "categories", "sort_order", "INTEGER NOT NULL DEFAULT 0"),
+    |c| add_column(c, "bills", "customer_id", "INTEGER"),
+];
+
+// Table names are formatted into the SQL, so only these are accepted.
+const MIGRATED_TABLES: &[&str] = &["products", "categories", "bills", "bill_items"];
+
+// Idempotent, because databases from before schema_version may already have some of these.
+// A missing table is left to SCHEMA, which creates it with every column.
+fn add_column(conn: &Connection, table: &str, column: &str, decl: &str) -> rusqlite::Result<()> {
+    if !MIGRATED_TABLES.contains(&table) {
+        return Err(rusqlite::Error::InvalidParameterName(format!("unknown table {table}")));
+    }
+    let (cols, exists): (i64, bool) = conn.query_row(
+        &format!("SELECT COUNT(*), COALESCE(SUM(name = ?1), 0) > 0 FROM pragma_table_info('{}')", table),
+        params![column],
+        |r| Ok((r.get(0)?, r.get(1)?)),
+    )?;
+    if cols > 0 && !exists {
+        conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, decl))?;
+    }
+    Ok(())
+}
+
+// A brand-new file gets every column from SCHEMA, so it starts at the latest version.
+fn migrate(conn: &Connection) -> Result<(), String> {
+    let fresh: bool = conn.query_row("SELECT NOT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'products')", [], |r| r.get(0))
+        .map_err(|e| e.to_string())?;
+    conn.execute_batch("CREATE TABLE IF NOT EXISTS settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);")
+        .map_err(|e| format!("Schema init failed: {e}"))?;
+    if fresh {
+        set_setting(conn, "schema_version", &MIGRATIONS.len().to_string());
+        return Ok(());
+    }
+    let current = get_setting(conn, "schema_version", "0").parse::<usize>().unwrap_or(0);
+    for (i, step) in MIGRATIONS.iter().enumerate().skip(current) {
+        let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        step(&tx).map_err(|e| format!("Migration {} failed: {e}", i + 1))?;
+        tx.execute(
+            "INSERT INTO settings(key,value) VALUES('schema_version',?1) ON CONFLICT(key) DO UPDATE SET value=excluded.value",
+            params![(i + 1).to_string()],
+        ).map_err(|e| format!("Migration {} failed: {e}", i + 1))?;
+        tx.commit().map_err(|e| format!("Migration {} failed: {e}", i + 1))?;
+    }
+    Ok(())
+}
+
 fn init_db(path: &PathBuf) -> Result<Connection, String> {
     let conn = Connection::open(path).map_err(|e| format!("Cannot open DB: {e}"))?;
     conn.execute_batch(
@@ -101,20 +393,76 @@ fn init_db(path: &PathBuf) -> Result<Connection, String> {
         PRAGMA foreign_keys = ON;
         PRAGMA busy_timeout = 5000;
         PRAGMA wal_autocheckpoint = 1000;
-        PRAGMA cache_size = -20000;
-        PRAGMA mmap_size = 268435456;
         ",
     )
     .map_err(|e| format!("DB pragma init failed: {e}"))?;
-    let _ = conn.execute_batch("ALTER TABLE products ADD COLUMN item_no INTEGER;");
-    let _ = conn.execute_batch("ALTER TABLE bills ADD COLUMN payment_mode TEXT NOT NULL DEFAULT 'cash';");
-    let _ = conn.execute_batch("ALTER TABLE bills ADD COLUMN split_cash_cents INTEGER NOT NULL DEFAULT 0;");
-    let _ = conn.execute_batch("ALTER TABLE bills ADD COLUMN split_online_cents INTEGER NOT NULL DEFAULT 0;");
+    migrate(&conn)?;
     conn.execute_batch(SCHEMA).map_err(|e| format!("Schema init failed: {e}"))?;
+    // Legacy DBs may predate the unique item_no index; duplicates are reassigned (and audited) before it is created.
+    repair_item_nos(&conn).map_err(|e| e.to_string())?;
+    conn.execute_batch(ITEM_NO_INDEX).map_err(|e| format!("Schema init failed: {e}"))?;
+    apply_tuning_pragmas(&conn).map_err(|e| e.to_string())?;
     let _ = conn.execute_batch("PRAGMA optimize;");
     Ok(conn)
 }
 
+const LARGE_DB_BYTES: u64 = 256 * 1024 * 1024;
+
+// Main file plus WAL, since an un-checkpointed WAL can be most of the bloat.
+fn db_disk_size(db_path: &PathBuf) -> u64 {
+    let wal = PathBuf::from(format!("{}-wal", db_path.to_string_lossy()));
+    [db_path, &wal].iter().map(|p| fs::metadata(p).map(|m| m.len()).unwrap_or(0)).sum()
+}
+
+const ITEM_NO_INDEX: &str = "CREATE UNIQUE INDEX IF NOT EXISTS idx_products_item_no ON products(item_no) WHERE item_no IS NOT NULL;";
+
+fn repair_item_nos(conn: &Connection) -> Result<Vec<Value>, ApiError> {
+    let mut stmt = conn.prepare(
+        "SELECT p.id, p.item_no FROM products p
+         WHERE p.item_no IS NOT NULL
+           AND p.id > (SELECT MIN(q.id) FROM products q WHERE q.item_no = p.item_no)
+         ORDER BY p.id"
+    )?;
+    let dups: Vec<(i64, i64)> = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?.filter_map(|r| r.ok()).collect();
+    if dups.is_empty() { return Ok(vec![]); }
+
+    let tx = conn.unchecked_transaction()?;
+    let mut changes = Vec::new();
+    for (id, old) in dups {
+        let mx: i64 = tx.query_row("SELECT COALESCE(MAX(item_no), 0) FROM products", [], |r| r.get(0)).unwrap_or(0);
+        let new_no = if mx < 9999 { Some(mx + 1) } else { None };
+        tx.execute("UPDATE products SET item_no = ?1, updated_at = datetime('now','localtime') WHERE id = ?2", params![new_no, id])?;
+        let change = json!({ "id": id, "old_item_no": old, "new_item_no": new_no });
+        audit(&tx, "product.item_no_repair", "product", Some(id), change.clone())?;
+        changes.push(change);
+    }
+    tx.commit()?;
+    Ok(changes)
+}
+
+// Tunable per machine (not exported): busy_timeout 100..60000 ms,
+// cache 1..256 MB, mmap 0..1024 MB (0 disables memory mapping).
+const TUNING_SETTINGS: &[(&str, &str)] = &[
+    ("busy_timeout_ms", "5000"),
+    ("cache_size_kb", "20000"),
+    ("mmap_size_mb", "256"),
+];
+
+fn apply_tuning_pragmas(conn: &Connection) -> Result<(), ApiError> {
+    let get = |key: &str| {
+        let fallback = TUNING_SETTINGS.iter().find(|(k, _)| *k == key).map(|(_, d)| *d).unwrap_or("0");
+        get_setting(conn, key, fallback).parse::<i64>().unwrap_or(0)
+    };
+    let busy = get("busy_timeout_ms");
+    let cache_kb = get("cache_size_kb");
+    let mmap_mb = get("mmap_size_mb");
+    conn.execute_batch(&format!(
+        "PRAGMA busy_timeout = {}; PRAGMA cache_size = -{}; PRAGMA mmap_size = {};",
+        busy, cache_kb, mmap_mb * 1024 * 1024
+    ))
+    .map_err(internal("DB pragma init failed"))
+}
+
 // -- helpers ------------------------------------------------------------------
 
 fn get_setting(conn: &Connection, key: &str, fallback: &str) -> String {
@@ -131,14 +479,472 @@ fn set_setting(conn: &Connection, key: &str, value: &str) {
     );
 }
 
+// card, upi and other are specific non-cash methods; online is the till's catch-all for them.
+const PAYMENT_MODES: &[&str] = &["cash", "card", "upi", "other", "online", "split"];
+
+// Settings that travel between terminals via /settings/export and /settings/import.
+// Machine-specific (backup_path) and internal (bill_seq) keys are deliberately absent.
+const EXPORTABLE_SETTINGS: &[&str] = &[
+    "discount_rate_bps",
+    "require_discount_reason",
+    "max_void_age_hours",
+    "backup_interval_minutes",
+    "backup_retention_count",
+    "backup_compress",
+    "receipt_condensed_overflow",
+    "receipt_header_lines",
+    "receipt_width",
+    "receipt_qr_enabled",
+    "receipt_qr_base_url",
+    "cash_drawer_enabled",
+    "card_surcharge_bps",
+    "default_printer",
+    "bill_prefix",
+    "bill_pad_width",
+    "bill_no_date",
+    "bill_seq_reset",
+    "max_discount_bps",
+    "undo_window_minutes",
+    "cut_mode",
+    "currency_symbol",
+    "thousands_separator",
+    "receipt_logo_path",
+    "discount_before_tax",
+];
+
+fn normalize_setting(key: &str, value: &Value) -> Result<String, String> {
+    let raw = match value {
+        Value::String(s) => s.trim().to_string(),
+        Value::Bool(b) => if *b { "1".to_string() } else { "0".to_string() },
+        Value::Number(n) => n.to_string(),
+        Value::Array(_) => value.to_string(),
+        _ => return Err("unsupported value".to_string()),
+    };
+    let int_in = |lo: i64, hi: i64| {
+        raw.parse::<i64>().ok().filter(|n| (lo..=hi).contains(n)).map(|n| n.to_string())
+            .ok_or_else(|| format!("must be an integer between {} and {}", lo, hi))
+    };
+    let flag = || match raw.as_str() {
+        "1" | "true" => Ok("1".to_string()),
+        "0" | "false" => Ok("0".to_string()),
+        _ => Err("must be a boolean".to_string()),
+    };
+    match key {
+        "discount_rate_bps" => int_in(0, 10_000),
+        "max_discount_bps" => int_in(0, 10_000),
+        "undo_window_minutes" => int_in(0, 1_440),
+        "receipt_logo_path" => {
+            let ext = Path::new(&raw).extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+            if raw.is_empty() || (raw.len() <= 500 && matches!(ext.as_deref(), Some("png" | "bmp"))) { Ok(raw.clone()) } else { Err("must be empty or a .png/.bmp path".to_string()) }
+        }
+        "currency_symbol" => if !raw.is_empty() && raw.chars().count() <= 4 { Ok(raw.clone()) } else { Err("must be 1 to 4 characters".to_string()) },
+        // Strings are trimmed above, so a space separator is stored as the word "space".
+        "thousands_separator" => match raw.as_str() {
+            "" | "," | "." | "'" | "space" => Ok(raw.clone()),
+            _ => Err("must be empty, ',', '.', ''' or 'space'".to_string()),
+        },
+        "cut_mode" => match raw.as_str() {
+            "full" | "partial" | "none" => Ok(raw.clone()),
+            _ => Err("must be full, partial or none".to_string()),
+        },
+        "require_discount_reason" => flag(),
+        "discount_before_tax" => flag(),
+        "max_void_age_hours" => int_in(0, 87_600),
+        "backup_interval_minutes" => int_in(1, 525_600),
+        "backup_retention_count" => int_in(1, 1_000),
+        "backup_compress" => flag(),
+        "receipt_condensed_overflow" => flag(),
+        "receipt_qr_enabled" => flag(),
+        "cash_drawer_enabled" => flag(),
+        "receipt_qr_base_url" => if raw.len() > 500 { Err("must be at most 500 characters".to_string()) } else { Ok(raw.clone()) },
+        "receipt_width" => match raw.as_str() {
+            "32" | "48" => Ok(raw.clone()),
+            _ => Err("must be 32 (58mm) or 48 (80mm)".to_string()),
+        },
+        "card_surcharge_bps" => int_in(0, 1_000),
+        "default_printer" => if raw.is_empty() || raw.len() > 200 { Err("must be a printer name".to_string()) } else { Ok(raw.clone()) },
+        "bill_prefix" => if !raw.is_empty() && raw.len() <= 10 && raw.chars().all(|c| c.is_ascii_alphanumeric()) { Ok(raw.to_uppercase()) } else { Err("must be 1 to 10 letters or digits".to_string()) },
+        "bill_pad_width" => int_in(1, 10),
+        "bill_no_date" => flag(),
+        "bill_seq_reset" => match raw.as_str() {
+            "never" | "daily" => Ok(raw.clone()),
+            _ => Err("must be never or daily".to_string()),
+        },
+        "busy_timeout_ms" => int_in(100, 60_000),
+        "cache_size_kb" => int_in(1_024, 262_144),
+        "mmap_size_mb" => int_in(0, 1_024),
+        "receipt_header_lines" => parse_header_lines(&raw).map(|_| raw.clone()).ok_or_else(|| "must be a list of { text, align, bold, large } lines".to_string()),
+        _ => Err("unknown setting".to_string()),
+    }
+}
+
+fn export_settings(conn: &Connection) -> Value {
+    let mut out = serde_json::Map::new();
+    for key in EXPORTABLE_SETTINGS {
+        if let Ok(v) = conn.query_row("SELECT value FROM settings WHERE key = ?1", params![key], |r| r.get::<_, String>(0)) {
+            out.insert(key.to_string(), Value::String(v));
+        }
+    }
+    Value::Object(out)
+}
+
+// Only EXPORTABLE_SETTINGS travel, so counters, paths and per-machine tuning stay behind.
+// The logo setting is a local file path, which means nothing on another machine.
+const JSON_EXPORT_SKIP_SETTINGS: &[&str] = &["receipt_logo_path"];
+
+fn json_export_setting(key: &str) -> bool {
+    EXPORTABLE_SETTINGS.contains(&key) && !JSON_EXPORT_SKIP_SETTINGS.contains(&key)
+}
+const JSON_EXPORT_FORMAT: i64 = 1;
+
+// Every column by name, so the document follows the schema without a field list to maintain.
+fn rows_as_json(conn: &Connection, sql: &str) -> Result<Vec<Value>, ApiError> {
+    use rusqlite::types::ValueRef;
+    let mut stmt = conn.prepare(sql)?;
+    let names: Vec<String> = stmt.column_names().iter().map(|n| n.to_string()).collect();
+    let rows = stmt.query_map([], |r| {
+        let mut obj = serde_json::Map::new();
+        for (i, name) in names.iter().enumerate() {
+            let v = match r.get_ref(i)? {
+                ValueRef::Null | ValueRef::Blob(_) => Value::Null,
+                ValueRef::Integer(n) => json!(n),
+                ValueRef::Real(f) => json!(f),
+                ValueRef::Text(t) => json!(String::from_utf8_lossy(t)),
+            };
+            obj.insert(name.clone(), v);
+        }
+        Ok(Value::Object(obj))
+    })?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+fn export_json(conn: &Connection) -> Result<Value, ApiError> {
+    let mut settings = serde_json::Map::new();
+    for row in rows_as_json(conn, "SELECT key, value FROM settings ORDER BY key")? {
+        let key = row["key"].as_str().unwrap_or_default().to_string();
+        if json_export_setting(&key) {
+            settings.insert(key, row["value"].clone());
+        }
+    }
+    let mut items_by_bill: std::collections::HashMap<i64, Vec<Value>> = std::collections::HashMap::new();
+    for item in rows_as_json(conn, "SELECT * FROM bill_items ORDER BY bill_id, id")? {
+        items_by_bill.entry(item["bill_id"].as_i64().unwrap_or(0)).or_default().push(item);
+    }
+    let mut payments_by_bill: std::collections::HashMap<i64, Vec<Value>> = std::collections::HashMap::new();
+    for payment in rows_as_json(conn, "SELECT * FROM bill_payments ORDER BY bill_id, id")? {
+        payments_by_bill.entry(payment["bill_id"].as_i64().unwrap_or(0)).or_default().push(payment);
+    }
+    let mut bills = rows_as_json(conn, "SELECT * FROM bills ORDER BY id")?;
+    for bill in bills.iter_mut() {
+        let id = bill["id"].as_i64().unwrap_or(0);
+        bill["items"] = json!(items_by_bill.remove(&id).unwrap_or_default());
+        bill["payments"] = json!(payments_by_bill.remove(&id).unwrap_or_default());
+    }
+    // Held orders are open tickets on this till and stock/price history is this terminal's own
+    // trail; products already carry the current stock and price, so neither travels.
+    Ok(json!({
+        "format": JSON_EXPORT_FORMAT,
+        "schema_version": get_setting(conn, "schema_version", "0").parse::<i64>().unwrap_or(0),
+        "exported_at": chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        "settings": settings,
+        "categories": rows_as_json(conn, "SELECT * FROM categories ORDER BY id")?,
+        "products": rows_as_json(conn, "SELECT * FROM products ORDER BY id")?,
+        "customers": rows_as_json(conn, "SELECT * FROM customers ORDER BY id")?,
+        "bills": bills,
+    }))
+}
+
+fn table_columns(conn: &Connection, table: &str) -> Result<Vec<String>, ApiError> {
+    let mut stmt = conn.prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?;
+    let cols = stmt.query_map([], |r| r.get::<_, String>(0))?.filter_map(|r| r.ok()).collect();
+    Ok(cols)
+}
+
+// Writes the keys of `row` that are real columns, so exports from older or newer schemas still load.
+// `skip` drops columns (ids in merge mode); `overrides` replaces remapped foreign keys.
+fn insert_json_row(conn: &Connection, table: &str, cols: &[String], row: &Value, skip: &[&str], overrides: &[(&str, Value)]) -> rusqlite::Result<i64> {
+    use rusqlite::types::Value as Sql;
+    let mut names = Vec::new();
+    let mut vals = Vec::new();
+    for c in cols {
+        if skip.contains(&c.as_str()) { continue; }
+        let v = match overrides.iter().find(|(k, _)| *k == c) {
+            Some((_, v)) => v,
+            None => match row.get(c) { Some(v) => v, None => continue },
+        };
+        vals.push(match v {
+            Value::Null => Sql::Null,
+            Value::Bool(b) => Sql::Integer(*b as i64),
+            Value::Number(n) => n.as_i64().map(Sql::Integer).unwrap_or_else(|| Sql::Real(n.as_f64().unwrap_or(0.0))),
+            Value::String(s) => Sql::Text(s.clone()),
+            other => Sql::Text(other.to_string()),
+        });
+        names.push(c.as_str());
+    }
+    let marks: Vec<String> = (1..=names.len()).map(|i| format!("?{}", i)).collect();
+    conn.execute(&format!("INSERT INTO {}({}) VALUES({})", table, names.join(","), marks.join(",")), rusqlite::params_from_iter(vals.iter()))?;
+    Ok(conn.last_insert_rowid())
+}
+
+// Replace wipes catalogue, customers and bills and keeps the document's ids. Merge keeps local data:
+// categories match by name, products by item_no + name (else name alone), customers by phone, and any bill_no or
+// item_no clash aborts the whole import.
+fn import_json(conn: &Connection, doc: &Value, replace: bool) -> Result<Value, ApiError> {
+    let format = doc["format"].as_i64().ok_or("Not a JSON export (format missing)")?;
+    if format > JSON_EXPORT_FORMAT { return Err(format!("Export format {} is newer than this app supports", format).into()); }
+    let empty = vec![];
+    let categories = doc["categories"].as_array().unwrap_or(&empty);
+    let products = doc["products"].as_array().unwrap_or(&empty);
+    let customers = doc["customers"].as_array().unwrap_or(&empty);
+    let bills = doc["bills"].as_array().unwrap_or(&empty);
+
+    let tx = conn.unchecked_transaction()?;
+    if replace {
+        // Held orders carry product ids from the old catalogue, so they go too.
+        tx.execute_batch("DELETE FROM bills; DELETE FROM held_orders; DELETE FROM stock_adjustments; DELETE FROM products; DELETE FROM categories; DELETE FROM customers;")?;
+    }
+    let id_skip: &[&str] = if replace { &[] } else { &["id"] };
+
+    let mut settings = 0;
+    let mut skipped = Vec::new();
+    if let Some(map) = doc["settings"].as_object() {
+        let sql = if replace {
+            "INSERT INTO settings(key,value) VALUES(?1,?2) ON CONFLICT(key) DO UPDATE SET value=excluded.value"
+        } else {
+            "INSERT OR IGNORE INTO settings(key,value) VALUES(?1,?2)"
+        };
+        // Same checks as /settings/import; a bad value is reported rather than failing the import.
+        for (k, v) in map {
+            if !json_export_setting(k) {
+                skipped.push(json!({ "key": k, "reason": "not importable" }));
+                continue;
+            }
+            match normalize_setting(k, v) {
+                Ok(v) => settings += tx.execute(sql, params![k, v])?,
+                Err(reason) => skipped.push(json!({ "key": k, "reason": reason })),
+            }
+        }
+    }
+
+    let cols = table_columns(&tx, "categories")?;
+    let mut cat_map = std::collections::HashMap::new();
+    let mut categories_added = 0;
+    for c in categories {
+        let name = c["name"].as_str().unwrap_or_default();
+        let existing: Option<i64> = if replace { None } else { tx.query_row("SELECT id FROM categories WHERE name = ?1", params![name], |r| r.get(0)).ok() };
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                categories_added += 1;
+                insert_json_row(&tx, "categories", &cols, c, id_skip, &[])
+                    .map_err(|e| if e.to_string().contains("UNIQUE") { ApiError::Conflict(format!("A category named '{}' already exists", name)) } else { e.into() })?
+            }
+        };
+        if let Some(old) = c["id"].as_i64() { cat_map.insert(old, id); }
+    }
+
+    let cols = table_columns(&tx, "products")?;
+    let mut product_map = std::collections::HashMap::new();
+    let mut products_added = 0;
+    for p in products {
+        let name = p["name"].as_str().unwrap_or_default();
+        let mut id = None;
+        if !replace {
+            if let Some(no) = p["item_no"].as_i64() {
+                if let Ok((local_id, local_name)) = tx.query_row("SELECT id, name FROM products WHERE item_no = ?1", params![no], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?))) {
+                    if !local_name.eq_ignore_ascii_case(name) {
+                        return Err(ApiError::Conflict(format!("Item No {} already in use by '{}' (import has '{}')", no, local_name, name)));
+                    }
+                    id = Some(local_id);
+                }
+            }
+            // Products without an item_no (or whose number is free here) match on name instead.
+            if id.is_none() {
+                id = tx.query_row("SELECT id FROM products WHERE name = ?1 COLLATE NOCASE ORDER BY id LIMIT 1", params![name], |r| r.get(0)).ok();
+            }
+        }
+        let id = match id {
+            Some(id) => id,
+            None => {
+                let cat = p["category_id"].as_i64().and_then(|c| cat_map.get(&c).copied());
+                products_added += 1;
+                insert_json_row(&tx, "products", &cols, p, id_skip, &[("category_id", json!(cat))])
+                    .map_err(|e| match product_write_err(e) {
+                        ApiError::Conflict(m) if m == "Item No already in use" => ApiError::Conflict(format!("Item No {} already in use", p["item_no"])),
+                        other => other,
+                    })?
+            }
+        };
+        if let Some(old) = p["id"].as_i64() { product_map.insert(old, id); }
+    }
+
+    let cols = table_columns(&tx, "customers")?;
+    let mut customer_map = std::collections::HashMap::new();
+    let mut customers_added = 0;
+    for c in customers {
+        let existing: Option<i64> = if replace { None } else { tx.query_row("SELECT id FROM customers WHERE phone = ?1", params![c["phone"].as_str()], |r| r.get(0)).ok() };
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                customers_added += 1;
+                insert_json_row(&tx, "customers", &cols, c, id_skip, &[])?
+            }
+        };
+        if let Some(old) = c["id"].as_i64() { customer_map.insert(old, id); }
+    }
+
+    let cols = table_columns(&tx, "bills")?;
+    let item_cols = table_columns(&tx, "bill_items")?;
+    let payment_cols = table_columns(&tx, "bill_payments")?;
+    let mut items_added = 0;
+    let mut payments_added = 0;
+    for b in bills {
+        let bill_no = b["bill_no"].as_str().unwrap_or_default();
+        let taken: bool = tx.query_row("SELECT EXISTS(SELECT 1 FROM bills WHERE bill_no = ?1)", params![bill_no], |r| r.get(0)).unwrap_or(false);
+        if taken { return Err(ApiError::Conflict(format!("Bill No {} already exists", bill_no))); }
+        // A customer missing from the document (older exports) leaves the bill unlinked.
+        let customer = b["customer_id"].as_i64().and_then(|c| customer_map.get(&c).copied());
+        let bill_id = insert_json_row(&tx, "bills", &cols, b, id_skip, &[("customer_id", json!(customer))])?;
+        for it in b["items"].as_array().unwrap_or(&empty) {
+            let pid = it["product_id"].as_i64().and_then(|p| product_map.get(&p).copied())
+                .ok_or_else(|| format!("Bill No {} references product {} which is not in the import", bill_no, it["product_id"]))?;
+            insert_json_row(&tx, "bill_items", &item_cols, it, id_skip, &[("bill_id", json!(bill_id)), ("product_id", json!(pid))])?;
+            items_added += 1;
+        }
+        for pay in b["payments"].as_array().unwrap_or(&empty) {
+            insert_json_row(&tx, "bill_payments", &payment_cols, pay, id_skip, &[("bill_id", json!(bill_id))])?;
+            payments_added += 1;
+        }
+    }
+
+    let counts = json!({
+        "settings": settings, "categories": categories_added, "products": products_added, "customers": customers_added,
+        "bills": bills.len(), "bill_items": items_added, "bill_payments": payments_added,
+    });
+    audit(&tx, "data.import_json", "database", None, json!({ "mode": if replace { "replace" } else { "merge" }, "counts": counts }))?;
+    tx.commit()?;
+    Ok(json!({ "ok": true, "imported": counts, "skipped_settings": skipped }))
+}
+
+// Inactive categories keep their products working; pickers pass `active_only` to hide them.
+fn list_categories(conn: &Connection, active_only: bool) -> Result<Vec<Value>, ApiError> {
+    let filter = if active_only { "WHERE c.is_active = 1" } else { "" };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT c.id, c.name, c.is_active, (SELECT COUNT(*) FROM products p WHERE p.category_id = c.id) FROM categories c {} ORDER BY c.sort_order, c.name",
+        filter
+    ))?;
+    let rows: Vec<Value> = stmt.query_map([], |r| Ok(json!({ "id": r.get::<_, i64>(0)?, "name": r.get::<_, String>(1)?, "is_active": r.get::<_, i64>(2)?, "product_count": r.get::<_, i64>(3)? })))?.filter_map(|r| r.ok()).collect();
+    Ok(rows)
+}
+
+// No-op when the price is unchanged, so callers can record on every product write.
+fn record_price_change(conn: &Connection, product_id: i64, old: i64, new: i64) -> Result<(), ApiError> {
+    if old == new { return Ok(()); }
+    conn.execute(
+        "INSERT INTO product_price_history(product_id,old_price_cents,new_price_cents,changed_at) VALUES(?1,?2,?3,datetime('now','localtime'))",
+        params![product_id, old, new],
+    )?;
+    Ok(())
+}
+
+// Pass the open transaction where there is one so the entry commits or rolls back with the change.
+fn audit(conn: &Connection, action: &str, entity: &str, entity_id: Option<i64>, detail: Value) -> Result<(), ApiError> {
+    conn.execute(
+        "INSERT INTO audit_log(action,entity,entity_id,detail,created_at) VALUES(?1,?2,?3,?4,datetime('now','localtime'))",
+        params![action, entity, entity_id, detail.to_string()],
+    )?;
+    Ok(())
+}
+
+// Keeps digits and a leading '+', so "98765 43210" and "98765-43210" are the same customer.
+fn normalize_phone(raw: &str) -> Option<String> {
+    let t = raw.trim();
+    let digits: String = t.chars().filter(|c| c.is_ascii_digit()).collect();
+    if !(6..=15).contains(&digits.len()) { return None; }
+    Some(if t.starts_with('+') { format!("+{}", digits) } else { digits })
+}
+
+fn customer_row(r: &rusqlite::Row) -> rusqlite::Result<Value> {
+    Ok(json!({ "id": r.get::<_, i64>(0)?, "name": r.get::<_, String>(1)?, "phone": r.get::<_, String>(2)?, "created_at": r.get::<_, String>(3)? }))
+}
+
+// PREFIX-000123, or PREFIX-20240115-000123 with the date segment on.
+fn format_bill_no(prefix: &str, date: Option<&str>, seq: i64, pad: usize) -> String {
+    match date {
+        Some(d) => format!("{}-{}-{:0pad$}", prefix, d, seq, pad = pad),
+        None => format!("{}-{:0pad$}", prefix, seq, pad = pad),
+    }
+}
+
 fn resolve_category_id(conn: &Connection, name: &str) -> Option<i64> {
     if name.is_empty() { return None; }
-    let _ = conn.execute("INSERT OR IGNORE INTO categories(name) VALUES (?1)", params![name]);
+    let _ = conn.execute(
+        "INSERT OR IGNORE INTO categories(name, sort_order) VALUES (?1, (SELECT COALESCE(MAX(sort_order), 0) + 1 FROM categories))",
+        params![name],
+    );
     conn.query_row("SELECT id FROM categories WHERE name = ?1", params![name], |r| {
         r.get::<_, i64>(0)
     }).ok()
 }
 
+// 0 (the default) means bills of any age may be corrected. There is no override: the app has no
+// user accounts, so anything a client could send to bypass this could be sent by anyone.
+fn check_bill_age(conn: &Connection, bill_id: i64) -> Result<(), ApiError> {
+    let max_hours: f64 = get_setting(conn, "max_void_age_hours", "0").parse().unwrap_or(0.0);
+    let age_hours: f64 = conn
+        .query_row("SELECT (julianday('now','localtime') - julianday(created_at)) * 24.0 FROM bills WHERE id = ?1", params![bill_id], |r| r.get(0))
+        .map_err(|_| ApiError::NotFound("Bill not found".to_string()))?;
+    if max_hours > 0.0 && age_hours > max_hours {
+        return Err(format!("Bill is older than {} hours and can no longer be changed", max_hours).into());
+    }
+    Ok(())
+}
+
+// All stored and transported money is integer cents. Endpoints that take
+// rupees name the field `_rupees` and convert here exactly once. Rounding
+// goes through thousandths first so 0.1 + 0.2 or 1.005 land on the cent a
+// person would expect rather than on float noise.
+fn rupees_to_cents(rupees: f64) -> i64 {
+    ((rupees * 1000.0).round() / 10.0).round() as i64
+}
+
+// For LIKE patterns used with ESCAPE '\': a typed % or _ matches itself, not anything.
+fn like_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+// Integer basis-point share of an amount, rounded half away from zero.
+fn bps_of(amount: i64, bps: i64) -> i64 {
+    let p = amount * bps;
+    if p >= 0 { (p + 5_000) / 10_000 } else { -((-p + 5_000) / 10_000) }
+}
+
+// Bill discount and per-line tax for `(line_total, tax_rate_bps)` lines; each line's tax is rounded on its own.
+// The discount is always subtotal x rate and the bill total is subtotal - discount + tax (+ tip, surcharge).
+// Discount before tax (default): line tax = (line - line x rate) x tax rate, so tax is charged on the discounted price.
+// Tax before discount: line tax = line x tax rate, so tax is charged on the list price.
+fn discount_and_tax(lines: &[(i64, i64)], dr: i64, discount_before_tax: bool) -> (i64, Vec<i64>) {
+    let subtotal: i64 = lines.iter().map(|l| l.0).sum();
+    let taxable = |lt: i64| if discount_before_tax { lt - bps_of(lt, dr) } else { lt };
+    (bps_of(subtotal, dr), lines.iter().map(|&(lt, rate)| bps_of(taxable(lt), rate)).collect())
+}
+
+// Prefers `<field>_cents`, falling back to `<field>_rupees`.
+fn money_field(b: &Value, field: &str) -> Option<i64> {
+    b[format!("{}_cents", field)].as_i64()
+        .or_else(|| b[format!("{}_rupees", field)].as_f64().map(rupees_to_cents))
+}
+
+// Rs 1,00,000 per item is far beyond any menu price; anything above is a typo.
+const MAX_PRICE_CENTS: i64 = 10_000_000;
+
+fn price_field(b: &Value) -> Result<i64, String> {
+    let price = money_field(b, "price").ok_or("price_cents required")?;
+    if price < 0 { return Err("price_cents cannot be negative".to_string()); }
+    if price > MAX_PRICE_CENTS { return Err(format!("price_cents cannot exceed {}", MAX_PRICE_CENTS)); }
+    Ok(price)
+}
+
 fn parse_qs(path: &str) -> (String, std::collections::HashMap<String, String>) {
     let mut map = std::collections::HashMap::new();
     let parts: Vec<&str> = path.splitn(2, '?').collect();
@@ -156,22 +962,24 @@ fn parse_qs(path: &str) -> (String, std::collections::HashMap<String, String>) {
     (base, map)
 }
 
+// Decodes to bytes first so multibyte UTF-8 sequences (%C3%A9) reassemble.
 fn percent_decode(input: &str) -> String {
-    let mut result = String::new();
+    let mut bytes: Vec<u8> = Vec::with_capacity(input.len());
     let mut chars = input.chars();
     while let Some(c) = chars.next() {
         if c == '%' {
             let hi = chars.next().unwrap_or('0');
             let lo = chars.next().unwrap_or('0');
             let byte = u8::from_str_radix(&format!("{}{}", hi, lo), 16).unwrap_or(b'?');
-            result.push(byte as char);
+            bytes.push(byte);
         } else if c == '+' {
-            result.push(' ');
+            bytes.push(b' ');
         } else {
-            result.push(c);
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
         }
     }
-    result
+    String::from_utf8_lossy(&bytes).into_owned()
 }
 
 fn to_date_only(value: &str) -> Option<String> {
@@ -183,6 +991,187 @@ fn to_date_only(value: &str) -> Option<String> {
     }
 }
 
+// Expands `YYYY-MM` into its first and last day.
+fn month_bounds(value: &str) -> Option<(String, String)> {
+    let t = value.trim();
+    if t.len() != 7 || !t.is_ascii() || t.as_bytes()[4] != b'-' { return None; }
+    let y: i64 = t[0..4].parse().ok()?;
+    let m: u32 = t[5..7].parse().ok()?;
+    let last = match m {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if (y % 4 == 0 && y % 100 != 0) || y % 400 == 0 => 29,
+        2 => 28,
+        _ => return None,
+    };
+    Some((format!("{}-01", t), format!("{}-{:02}", t, last)))
+}
+
+// Explicit start/end win over `month`, which wins over the default of today.
+fn query_dates(qs: &std::collections::HashMap<String, String>) -> (Option<String>, Option<String>) {
+    let month = qs.get("month").and_then(|v| month_bounds(v));
+    let start = qs.get("start").and_then(|v| to_date_only(v)).or_else(|| month.as_ref().map(|m| m.0.clone()));
+    let end = qs.get("end").and_then(|v| to_date_only(v)).or_else(|| month.map(|m| m.1));
+    (start, end)
+}
+
+fn report_range(conn: &Connection, qs: &std::collections::HashMap<String, String>) -> (String, String) {
+    let today = conn
+        .query_row("SELECT date('now','localtime')", [], |r| r.get::<_, String>(0))
+        .unwrap_or_else(|_| "1970-01-01".to_string());
+    let (start, end) = query_dates(qs);
+    let mut start = start.unwrap_or(today);
+    let mut end = end.unwrap_or_else(|| start.clone());
+    if start > end {
+        std::mem::swap(&mut start, &mut end);
+    }
+    (format!("{} 00:00:00", start), format!("{} 23:59:59", end))
+}
+
+// report_range quietly falls back to today; endpoints that chart or export a range reject typos instead.
+fn validate_range_params(qs: &std::collections::HashMap<String, String>) -> Result<(), String> {
+    for key in ["start", "end"] {
+        if let Some(v) = qs.get(key) {
+            to_date_only(v).ok_or_else(|| format!("Invalid {} date, expected YYYY-MM-DD", key))?;
+        }
+    }
+    Ok(())
+}
+
+// RFC-4180 style: quoted fields may hold commas, newlines and doubled quotes.
+// Returns each record with the 1-based line it starts on.
+fn parse_csv(text: &str) -> Vec<(usize, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut fields: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1usize;
+    let mut start_line = 1usize;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => { chars.next(); field.push('"'); }
+                '"' => in_quotes = false,
+                '\n' => { line += 1; field.push(c); }
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' => in_quotes = true,
+            ',' => fields.push(std::mem::take(&mut field)),
+            '\r' => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                if fields.iter().any(|f| !f.trim().is_empty()) {
+                    records.push((start_line, std::mem::take(&mut fields)));
+                }
+                fields.clear();
+                line += 1;
+                start_line = line;
+            }
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    if fields.iter().any(|f| !f.trim().is_empty()) {
+        records.push((start_line, fields));
+    }
+    records
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') || value.contains('\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Training bills are left out of the list, like every revenue figure, unless `include_training=1` is passed.
+fn list_bills(conn: &Connection, qs: &std::collections::HashMap<String, String>) -> Result<Value, ApiError> {
+    let page: i64 = qs.get("page").and_then(|v| v.parse().ok()).unwrap_or(1).max(1);
+    let limit: i64 = qs.get("limit").and_then(|v| v.parse().ok()).unwrap_or(10).max(1).min(100);
+    let bnq = qs.get("bill_no").cloned().unwrap_or_default();
+    let cashier = qs.get("cashier").map(|s| s.trim().to_string()).unwrap_or_default();
+    let table_no = qs.get("table_no").map(|s| s.trim().to_string()).unwrap_or_default();
+    let (start, end) = query_dates(qs);
+
+    let mut wc = Vec::new();
+    let mut bv: Vec<String> = Vec::new();
+    if !bnq.is_empty() { wc.push("bill_no LIKE ?".to_string()); bv.push(format!("%{}%", bnq)); }
+    if !cashier.is_empty() { wc.push("cashier = ?".to_string()); bv.push(cashier.clone()); }
+    if !table_no.is_empty() { wc.push("table_no = ?".to_string()); bv.push(table_no.clone()); }
+    if let Some(c) = qs.get("customer_id").filter(|c| !c.is_empty()) { wc.push("customer_id = ?".to_string()); bv.push(c.clone()); }
+    // IN (subquery) rather than a join so a bill with the product on several lines is listed once.
+    if let Some(p) = qs.get("product_id").filter(|p| !p.is_empty()) { wc.push("id IN (SELECT bill_id FROM bill_items WHERE product_id = ?)".to_string()); bv.push(p.clone()); }
+    if let Some(p) = qs.get("product_name").map(|p| p.trim()).filter(|p| !p.is_empty()) { wc.push("id IN (SELECT bill_id FROM bill_items WHERE product_name LIKE ?)".to_string()); bv.push(format!("%{}%", p)); }
+    if let Some(s) = &start { wc.push("created_at >= ?".to_string()); bv.push(format!("{} 00:00:00", s)); }
+    if let Some(e) = &end { wc.push("created_at <= ?".to_string()); bv.push(format!("{} 23:59:59", e)); }
+    let training = training_clause(qs, "is_training");
+    if !training.is_empty() { wc.push(training.trim_start_matches(" AND ").to_string()); }
+
+    let wsql = if wc.is_empty() { String::new() } else { format!("WHERE {}", wc.join(" AND ")) };
+
+    let csql = format!("SELECT COUNT(*) FROM bills {}", wsql);
+    let mut cs = conn.prepare(&csql)?;
+    let cparams: Vec<&dyn rusqlite::types::ToSql> = bv.iter().map(|v| v as &dyn rusqlite::types::ToSql).collect();
+    let total: i64 = cs.query_row(cparams.as_slice(), |r| r.get(0)).unwrap_or(0);
+
+    let dsql = format!("SELECT id,bill_no,subtotal_cents,discount_rate_bps,discount_cents,payment_mode,split_cash_cents,split_online_cents,total_cents,created_at,tip_cents,surcharge_cents,voided_at,void_reason,is_training,cashier,table_no FROM bills {} ORDER BY created_at DESC LIMIT ? OFFSET ?", wsql);
+    let mut ds = conn.prepare(&dsql)?;
+    let offset = (page - 1) * limit;
+    let mut ap: Vec<Box<dyn rusqlite::types::ToSql>> = bv.iter().map(|v| Box::new(v.clone()) as Box<dyn rusqlite::types::ToSql>).collect();
+    ap.push(Box::new(limit));
+    ap.push(Box::new(offset));
+    let pr: Vec<&dyn rusqlite::types::ToSql> = ap.iter().map(|v| v.as_ref()).collect();
+
+    let rows: Vec<Value> = ds.query_map(pr.as_slice(), |r| Ok(json!({ "id": r.get::<_, i64>(0)?, "bill_no": r.get::<_, String>(1)?, "subtotal_cents": r.get::<_, i64>(2)?, "discount_rate_bps": r.get::<_, i64>(3)?, "discount_cents": r.get::<_, i64>(4)?, "payment_mode": r.get::<_, String>(5)?, "split_cash_cents": r.get::<_, i64>(6)?, "split_online_cents": r.get::<_, i64>(7)?, "total_cents": r.get::<_, i64>(8)?, "created_at": r.get::<_, String>(9)?, "tip_cents": r.get::<_, i64>(10)?, "surcharge_cents": r.get::<_, i64>(11)?, "voided": r.get::<_, Option<String>>(12)?.is_some(), "voided_at": r.get::<_, Option<String>>(12)?, "void_reason": r.get::<_, Option<String>>(13)?, "is_training": r.get::<_, i64>(14)? != 0, "cashier": r.get::<_, Option<String>>(15)?, "table_no": r.get::<_, Option<String>>(16)? })))?.filter_map(|r| r.ok()).collect();
+    Ok(json!({ "rows": rows, "total": total }))
+}
+
+fn training_clause(qs: &std::collections::HashMap<String, String>, col: &str) -> String {
+    if qs.get("include_training").map(|v| v == "1" || v == "true").unwrap_or(false) {
+        String::new()
+    } else {
+        format!(" AND {} = 0", col)
+    }
+}
+
+// Shared `?format=csv` handling for report endpoints. The header comes from
+// the first row's keys; `_cents` columns are written as rupees.
+fn rows_to_csv(rows: &[Value]) -> String {
+    let keys: Vec<String> = match rows.first().and_then(|r| r.as_object()) {
+        Some(obj) => obj.keys().cloned().collect(),
+        None => return String::new(),
+    };
+    let header: Vec<String> = keys.iter().map(|k| csv_escape(k.strip_suffix("_cents").unwrap_or(k))).collect();
+    let mut csv = header.join(",") + "\r\n";
+    for row in rows {
+        let fields: Vec<String> = keys
+            .iter()
+            .map(|k| match &row[k.as_str()] {
+                Value::Null => String::new(),
+                Value::String(s) => csv_escape(s),
+                Value::Number(n) if k.ends_with("_cents") => cents_to_rs(n.as_i64().unwrap_or(0)),
+                other => csv_escape(&other.to_string()),
+            })
+            .collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+fn report_response(qs: &std::collections::HashMap<String, String>, rows: &[Value], full: Value) -> Value {
+    if qs.get("format").map(|f| f.eq_ignore_ascii_case("csv")).unwrap_or(false) {
+        json!({ "csv": rows_to_csv(rows) })
+    } else {
+        full
+    }
+}
+
 // -- receipt formatting -------------------------------------------------------
 
 #[derive(Debug, Deserialize)]
@@ -202,95 +1191,595 @@ struct ReceiptPayload {
     subtotal_cents: i32,
     discount_rate_bps: i32,
     discount_cents: i32,
+    #[serde(default)]
+    tip_cents: i32,
+    #[serde(default)]
+    surcharge_cents: i32,
     total_cents: i32,
+    // One entry per GST rate, as returned in `taxes` by GET /bills/{id}.
+    #[serde(default)]
+    taxes: Vec<ReceiptTax>,
+    // Reprints leave this empty so they never pop the cash drawer.
+    #[serde(default)]
+    payment_mode: String,
+    #[serde(default)]
+    reprint: bool,
+    #[serde(default = "default_true")]
+    discount_before_tax: bool,
     items: Vec<ReceiptItem>,
 }
 
-fn cents_to_rs(cents: i32) -> String { format!("{:.2}", (cents as f64) / 100.0) }
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReceiptTax {
+    rate_bps: i32,
+    tax_cents: i32,
+}
+
+fn cents_to_rs(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    let abs = cents.unsigned_abs();
+    format!("{}{}.{:02}", sign, abs / 100, abs % 100)
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Currency {
+    symbol: String,
+    separator: String,
+}
+
+// Word-like symbols ("Rs", "USD") get a space before the amount; "$" and "Rs." don't.
+// An empty symbol gives the bare grouped amount used in the receipt's item columns.
+fn format_money(cents: i64, cur: &Currency) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    let abs = cents.unsigned_abs();
+    let digits = (abs / 100).to_string();
+    let mut whole = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) { whole.push_str(&cur.separator); }
+        whole.push(c);
+    }
+    let gap = if !cur.symbol.is_empty() && cur.symbol.chars().all(|c| c.is_ascii_alphabetic()) { " " } else { "" };
+    format!("{}{}{}{}.{:02}", sign, cur.symbol, gap, whole, abs % 100)
+}
+
+// Combining marks and zero-width characters print on top of the previous
+// column, so they must not count towards receipt alignment.
+fn is_zero_width(c: char) -> bool {
+    matches!(c as u32,
+        0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x20D0..=0x20FF | 0xFE20..=0xFE2F
+        | 0x200B..=0x200F | 0xFEFF
+        | 0x0B82 | 0x0BC0 | 0x0BCD)
+}
+
+fn display_width(value: &str) -> usize { value.chars().filter(|c| !is_zero_width(*c)).count() }
 
 fn pad_right(value: &str, width: usize) -> String {
-    if value.len() >= width { return value.to_string(); }
-    format!("{}{}", value, " ".repeat(width - value.len()))
+    let len = display_width(value);
+    if len >= width { return value.to_string(); }
+    format!("{}{}", value, " ".repeat(width - len))
 }
 
 fn pad_left(value: &str, width: usize) -> String {
-    if value.len() >= width { return value.to_string(); }
-    format!("{}{}", " ".repeat(width - value.len()), value)
+    let len = display_width(value);
+    if len >= width { return value.to_string(); }
+    format!("{}{}", " ".repeat(width - len), value)
 }
 
-fn fit_text(value: &str, width: usize) -> String { value.trim().chars().take(width).collect() }
+fn fit_text(value: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut used = 0;
+    for c in value.trim().chars() {
+        if !is_zero_width(c) {
+            if used == width { break; }
+            used += 1;
+        }
+        out.push(c);
+    }
+    out
+}
 
 fn line_two_col(left: &str, right: &str, w: usize) -> String {
-    if right.len() >= w { return fit_text(right, w); }
-    let lr = w.saturating_sub(right.len() + 1);
+    let rw = display_width(right);
+    if rw >= w { return fit_text(right, w); }
+    let lr = w.saturating_sub(rw + 1);
     let lt = fit_text(left, lr);
-    let sp = w.saturating_sub(lt.len() + right.len());
+    let sp = w.saturating_sub(display_width(&lt) + rw);
     format!("{}{}{}", lt, " ".repeat(sp), right)
 }
 
 fn sep(w: usize) -> String { "-".repeat(w) }
 
-fn format_receipt(payload: &ReceiptPayload) -> String {
-    let w = 48usize;
-    let mut l: Vec<String> = Vec::new();
-    l.push(sep(w));
-    l.push(line_two_col(&format!("Bill: {}", payload.bill_no), &payload.printed_at, w));
-    l.push(sep(w));
-    l.push(format!("{} {} {} {}", pad_right("Item", 20), pad_left("Qty", 4), pad_left("Rate", 9), pad_left("Amount", 12)));
-    l.push(sep(w));
-    for it in &payload.items {
-        let n = fit_text(&it.name, 20);
-        l.push(format!("{} {} {} {}", pad_right(&n, 20), pad_left(&it.qty.to_string(), 4), pad_left(&cents_to_rs(it.unit_price_cents), 9), pad_left(&cents_to_rs(it.line_total_cents), 12)));
-    }
-    l.push(sep(w));
-    l.push(line_two_col("Subtotal", &format!("Rs {}", cents_to_rs(payload.subtotal_cents)), w));
-    l.push(line_two_col(&format!("Discount ({:.2}%)", (payload.discount_rate_bps as f64) / 100.0), &format!("-Rs {}", cents_to_rs(payload.discount_cents)), w));
-    l.push(line_two_col("TOTAL", &format!("Rs {}", cents_to_rs(payload.total_cents)), w));
-    l.push(sep(w));
-    l.join("\r\n")
+// Font A fits 48 columns on 80mm paper and 32 on 58mm; condensed font B
+// (ESC M 1) fits 56 and 42 respectively.
+const RECEIPT_WIDTH: usize = 48;
+const NARROW_WIDTH: usize = 32;
+
+fn condensed_width(w: usize) -> usize { if w < RECEIPT_WIDTH { 42 } else { 56 } }
+
+// Qty/rate/amount widths; the item name gets whatever is left after them and
+// the three separating spaces.
+fn numeric_columns(w: usize) -> (usize, usize, usize) {
+    if w < RECEIPT_WIDTH { (3, 7, 8) } else { (4, 9, 12) }
 }
 
-fn ps_escape(s: &str) -> String { s.replace('\'', "''").replace('"', "`\"") }
+fn name_width(w: usize) -> usize {
+    let (q, r, a) = numeric_columns(w);
+    w.saturating_sub(q + r + a + 3)
+}
 
-fn do_print(printer: &str, payload: &ReceiptPayload) -> Result<(), String> {
-    let receipt = format_receipt(payload);
-    let ms = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| e.to_string())?.as_millis();
-    let tmp = std::env::temp_dir().join(format!("meateat_{}.txt", ms));
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct HeaderLine {
+    text: String,
+    #[serde(default = "default_header_align")]
+    align: String,
+    #[serde(default)]
+    bold: bool,
+    #[serde(default)]
+    large: bool,
+}
 
-    // ESC/POS raw bytes: init, center header, left body, bottom feed, then cut.
-    let mut raw: Vec<u8> = Vec::new();
-    raw.extend_from_slice(&[0x1B, 0x40]); // ESC @ initialize
-    raw.extend_from_slice(&[0x1B, 0x61, 0x01]); // ESC a 1 (center)
-    // Prominent branding: double-width + double-height + emphasized
-    raw.extend_from_slice(&[0x1D, 0x21, 0x11]); // GS ! n -> double width & double height
-    raw.extend_from_slice(&[0x1B, 0x45, 0x01]); // ESC E 1 -> emphasize on
-    raw.extend_from_slice(b"Meet & Eat Cafe\r\n");
-    raw.extend_from_slice(&[0x1B, 0x45, 0x00]); // emphasize off
-    raw.extend_from_slice(&[0x1D, 0x21, 0x00]); // ensure normal size
-    raw.extend_from_slice(b"11, Tank Street, Thudiyalur Road,\r\n");
-    raw.extend_from_slice(b"VN Pudar, Chinnavedampatti,\r\n");
-    raw.extend_from_slice(b"Saravanapatti, Coimbatore - 641035.\r\n");
-    raw.extend_from_slice(b"ph: 8925541808, 7743908902\r\n");
-    // Slightly bold slogan
-    raw.extend_from_slice(&[0x1B, 0x45, 0x01]);
-    raw.extend_from_slice(b"Fresh Food | Fast Service\r\n");
-    raw.extend_from_slice(&[0x1B, 0x45, 0x00]);
-    raw.extend_from_slice(&[0x1B, 0x61, 0x00]); // ESC a 0 (left)
+fn default_header_align() -> String { "center".to_string() }
 
-    raw.extend_from_slice(receipt.as_bytes());
+fn default_header() -> Vec<HeaderLine> {
+    let line = |text: &str, bold: bool, large: bool| HeaderLine { text: text.to_string(), align: default_header_align(), bold, large };
+    vec![
+        line("Meet & Eat Cafe", true, true),
+        line("11, Tank Street, Thudiyalur Road,", false, false),
+        line("VN Pudar, Chinnavedampatti,", false, false),
+        line("Saravanapatti, Coimbatore - 641035.", false, false),
+        line("ph: 8925541808, 7743908902", false, false),
+        line("Fresh Food | Fast Service", true, false),
+    ]
+}
 
-    // Centered thank-you line (printed after body)
-    raw.extend_from_slice(&[0x1B, 0x61, 0x01]);
+fn parse_header_lines(raw: &str) -> Option<Vec<HeaderLine>> {
+    let lines: Vec<HeaderLine> = serde_json::from_str(raw).ok()?;
+    let valid = !lines.is_empty() && lines.iter().all(|l| matches!(l.align.as_str(), "left" | "center" | "right"));
+    if valid { Some(lines) } else { None }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum CutMode {
+    Full,
+    Partial,
+    None,
+}
+
+impl CutMode {
+    fn from_setting(value: &str) -> Self {
+        match value {
+            "partial" => CutMode::Partial,
+            "none" => CutMode::None,
+            _ => CutMode::Full,
+        }
+    }
+
+    // GS V m n (function B): feed n lines, then cut. Continuous rolls get no command at all.
+    fn bytes(self) -> &'static [u8] {
+        match self {
+            CutMode::Full => &[0x1D, 0x56, 0x41, 0x03],
+            CutMode::Partial => &[0x1D, 0x56, 0x42, 0x03],
+            CutMode::None => &[],
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct PrintConfig {
+    width: usize,
+    condensed_overflow: bool,
+    cash_drawer: bool,
+    cut: CutMode,
+    currency: Currency,
+    logo_path: Option<String>,
+    qr_template: Option<String>,
+    header: Vec<HeaderLine>,
+}
+
+// Printer code pages have no rupee sign; sent as UTF-8 it comes out as three junk characters.
+fn printable_symbol(symbol: &str) -> String {
+    symbol.replace('₹', "Rs.")
+}
+
+fn load_print_config(conn: &Connection) -> PrintConfig {
+    let width = match get_setting(conn, "receipt_width", "48").as_str() {
+        "32" => NARROW_WIDTH,
+        _ => RECEIPT_WIDTH,
+    };
+    let qr_template = Some(get_setting(conn, "receipt_qr_base_url", ""))
+        .filter(|t| get_setting(conn, "receipt_qr_enabled", "0") == "1" && !t.trim().is_empty());
+    PrintConfig {
+        width,
+        condensed_overflow: get_setting(conn, "receipt_condensed_overflow", "0") == "1",
+        cash_drawer: get_setting(conn, "cash_drawer_enabled", "0") == "1",
+        cut: CutMode::from_setting(&get_setting(conn, "cut_mode", "full")),
+        currency: Currency {
+            symbol: printable_symbol(&get_setting(conn, "currency_symbol", "Rs")),
+            separator: match get_setting(conn, "thousands_separator", "").as_str() {
+                "space" => " ".to_string(),
+                s => s.to_string(),
+            },
+        },
+        logo_path: Some(get_setting(conn, "receipt_logo_path", "")).filter(|p| !p.trim().is_empty()),
+        qr_template,
+        header: parse_header_lines(&get_setting(conn, "receipt_header_lines", "")).unwrap_or_else(default_header),
+    }
+}
+
+// Explicit name, then the last printer that worked, then the configured default.
+fn resolve_printer(conn: &Connection, requested: Option<&str>) -> String {
+    if let Some(p) = requested.map(str::trim).filter(|p| !p.is_empty()) {
+        return p.to_string();
+    }
+    let last = get_setting(conn, "last_printer", "");
+    if !last.trim().is_empty() { return last; }
+    get_setting(conn, "default_printer", "Rugtek printer")
+}
+
+fn format_receipt(payload: &ReceiptPayload, w: usize, cur: &Currency) -> String {
+    let money = |cents: i64| format_money(cents, cur);
+    // The item columns are too narrow for the symbol but group digits the same way as the totals.
+    let bare = Currency { symbol: String::new(), separator: cur.separator.clone() };
+    let column = |cents: i64| format_money(cents, &bare);
+    let nw = name_width(w);
+    let (qw, rw, aw) = numeric_columns(w);
+    let mut l: Vec<String> = Vec::new();
+    if payload.reprint {
+        l.push(format!("{:^1$}", "*** REPRINT ***", w).trim_end().to_string());
+    }
+    l.push(sep(w));
+    let bill = format!("Bill: {}", payload.bill_no);
+    if display_width(&bill) + display_width(&payload.printed_at) < w {
+        l.push(line_two_col(&bill, &payload.printed_at, w));
+    } else {
+        // 58mm paper can't fit both, and truncating the bill number is worse than an extra line.
+        l.push(bill);
+        l.push(payload.printed_at.clone());
+    }
+    l.push(sep(w));
+    l.push(format!("{} {} {} {}", pad_right("Item", nw), pad_left("Qty", qw), pad_left("Rate", rw), pad_left("Amount", aw)));
+    l.push(sep(w));
+    for it in &payload.items {
+        let n = fit_text(&it.name, nw);
+        // A zero line total on a priced item is a comp; make that explicit for the customer.
+        let amount = if it.line_total_cents == 0 && it.unit_price_cents > 0 { "COMP".to_string() } else { column(it.line_total_cents as i64) };
+        l.push(format!("{} {} {} {}", pad_right(&n, nw), pad_left(&it.qty.to_string(), qw), pad_left(&column(it.unit_price_cents as i64), rw), pad_left(&amount, aw)));
+    }
+    l.push(sep(w));
+    l.push(line_two_col("Subtotal", &money(payload.subtotal_cents as i64), w));
+    // The lines follow the order the bill was computed in, so the customer can check the arithmetic.
+    let discount = line_two_col(&format!("Discount ({:.2}%)", (payload.discount_rate_bps as f64) / 100.0), &format!("-{}", money(payload.discount_cents as i64)), w);
+    if payload.discount_before_tax { l.push(discount.clone()); }
+    for t in payload.taxes.iter().filter(|t| t.tax_cents > 0) {
+        let (cgst, sgst) = split_gst(t.tax_cents as i64);
+        let half = (t.rate_bps as f64) / 200.0;
+        l.push(line_two_col(&format!("CGST ({:.2}%)", half), &money(cgst), w));
+        l.push(line_two_col(&format!("SGST ({:.2}%)", half), &money(sgst), w));
+    }
+    if !payload.discount_before_tax { l.push(discount); }
+    if payload.tip_cents > 0 {
+        l.push(line_two_col("Tip", &money(payload.tip_cents as i64), w));
+    }
+    if payload.surcharge_cents > 0 {
+        l.push(line_two_col("Card Surcharge", &money(payload.surcharge_cents as i64), w));
+    }
+    l.push(line_two_col("TOTAL", &money(payload.total_cents as i64), w));
+    l.push(sep(w));
+    l.join("\r\n")
+}
+
+#[cfg(windows)]
+fn ps_escape(s: &str) -> String { s.replace('\'', "''").replace('"', "`\"") }
+
+fn build_header(raw: &mut Vec<u8>, header: &[HeaderLine]) {
+    for line in header {
+        let align = match line.align.as_str() { "left" => 0x00, "right" => 0x02, _ => 0x01 };
+        raw.extend_from_slice(&[0x1B, 0x61, align]); // ESC a n
+        if line.large { raw.extend_from_slice(&[0x1D, 0x21, 0x11]); } // GS ! n -> double width & double height
+        if line.bold { raw.extend_from_slice(&[0x1B, 0x45, 0x01]); } // ESC E 1 -> emphasize on
+        raw.extend_from_slice(line.text.as_bytes());
+        raw.extend_from_slice(b"\r\n");
+        if line.bold { raw.extend_from_slice(&[0x1B, 0x45, 0x00]); } // emphasize off
+        if line.large { raw.extend_from_slice(&[0x1D, 0x21, 0x00]); } // back to normal size
+    }
+    raw.extend_from_slice(&[0x1B, 0x61, 0x00]); // ESC a 0 (left)
+}
+
+// `{bill_no}` in the template is replaced; a template without it gets the bill number appended.
+fn qr_url(template: &str, bill_no: &str) -> String {
+    let t = template.trim();
+    if t.contains("{bill_no}") { t.replace("{bill_no}", bill_no) } else { format!("{}{}", t, bill_no) }
+}
+
+// ESC/POS GS ( k: select model 2, module size, error correction M, store the data, print it.
+fn build_qr(raw: &mut Vec<u8>, data: &str) {
+    let bytes = data.as_bytes();
+    let len = bytes.len() + 3;
+    if len > 7092 { return; }
+    raw.extend_from_slice(&[0x1B, 0x61, 0x01]); // center
+    raw.extend_from_slice(&[0x1D, 0x28, 0x6B, 0x04, 0x00, 0x31, 0x41, 0x32, 0x00]); // model 2
+    raw.extend_from_slice(&[0x1D, 0x28, 0x6B, 0x03, 0x00, 0x31, 0x43, 0x06]); // module size 6
+    raw.extend_from_slice(&[0x1D, 0x28, 0x6B, 0x03, 0x00, 0x31, 0x45, 0x31]); // error correction M
+    raw.extend_from_slice(&[0x1D, 0x28, 0x6B, (len & 0xFF) as u8, (len >> 8) as u8, 0x31, 0x50, 0x30]); // store
+    raw.extend_from_slice(bytes);
+    raw.extend_from_slice(&[0x1D, 0x28, 0x6B, 0x03, 0x00, 0x31, 0x51, 0x30]); // print
+    raw.extend_from_slice(b"\r\n");
+    raw.extend_from_slice(&[0x1B, 0x61, 0x00]);
+}
+
+// Printable dots across the head: 576 on 80mm paper, 384 on 58mm.
+fn logo_max_dots(w: usize) -> u32 {
+    if w <= NARROW_WIDTH { 384 } else { 576 }
+}
+
+// GS v 0 m xL xH yL yH d1...dk: one bit per dot, MSB first, rows padded to whole bytes.
+fn raster_bytes(img: &image::GrayImage) -> Vec<u8> {
+    let (w, h) = img.dimensions();
+    let row_bytes = w.div_ceil(8);
+    let mut raw = vec![0x1D, 0x76, 0x30, 0x00, (row_bytes & 0xFF) as u8, (row_bytes >> 8) as u8, (h & 0xFF) as u8, (h >> 8) as u8];
+    for y in 0..h {
+        for bx in 0..row_bytes {
+            let mut byte = 0u8;
+            for bit in 0..8 {
+                let x = bx * 8 + bit;
+                if x < w && img.get_pixel(x, y)[0] < 128 { byte |= 0x80 >> bit; }
+            }
+            raw.push(byte);
+        }
+    }
+    raw
+}
+
+// Scaled down to the paper width; transparent pixels count as white paper.
+fn load_logo(path: &str, max_dots: u32) -> Result<Vec<u8>, ApiError> {
+    let img = image::open(path).map_err(internal("Logo"))?.to_rgba8();
+    let mut gray = image::GrayImage::new(img.width(), img.height());
+    for (x, y, p) in img.enumerate_pixels() {
+        let [r, g, b, a] = p.0;
+        let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+        let v = 255 - (255 - luma) * a as u32 / 255;
+        gray.put_pixel(x, y, image::Luma([v as u8]));
+    }
+    if gray.width() > max_dots {
+        let h = (gray.height() as u64 * max_dots as u64 / gray.width() as u64).max(1) as u32;
+        gray = image::imageops::resize(&gray, max_dots, h, image::imageops::FilterType::Triangle);
+    }
+    Ok(raster_bytes(&gray))
+}
+
+// ESC p 0 t1 t2: pulse drawer pin 2 (the RJ11 kick-out port) for 25ms on / 250ms off.
+const CASH_DRAWER_PULSE: [u8; 5] = [0x1B, 0x70, 0x00, 0x19, 0xFA];
+
+fn uses_condensed(payload: &ReceiptPayload, cfg: &PrintConfig) -> bool {
+    cfg.condensed_overflow && payload.items.iter().any(|it| display_width(it.name.trim()) > name_width(cfg.width))
+}
+
+fn receipt_body(payload: &ReceiptPayload, cfg: &PrintConfig) -> String {
+    format_receipt(payload, if uses_condensed(payload, cfg) { condensed_width(cfg.width) } else { cfg.width }, &cfg.currency)
+}
+
+fn build_receipt_bytes(payload: &ReceiptPayload, cfg: &PrintConfig) -> Vec<u8> {
+    let condensed = uses_condensed(payload, cfg);
+    let receipt = receipt_body(payload, cfg);
+
+    // ESC/POS raw bytes: init, header, left body, bottom feed, then cut.
+    let mut raw: Vec<u8> = Vec::new();
+    raw.extend_from_slice(&[0x1B, 0x40]); // ESC @ initialize
+    // A missing or unreadable logo must never stop the receipt; the text header still prints.
+    if let Some(logo) = cfg.logo_path.as_deref().and_then(|p| load_logo(p, logo_max_dots(cfg.width)).ok()) {
+        raw.extend_from_slice(&[0x1B, 0x61, 0x01]); // center
+        raw.extend_from_slice(&logo);
+        raw.extend_from_slice(&[0x1B, 0x61, 0x00]);
+    }
+    build_header(&mut raw, &cfg.header);
+
+    if condensed { raw.extend_from_slice(&[0x1B, 0x4D, 0x01]); } // ESC M 1 -> font B
+    raw.extend_from_slice(receipt.as_bytes());
+    if condensed { raw.extend_from_slice(&[0x1B, 0x4D, 0x00]); } // back to font A
+
+    if let Some(t) = &cfg.qr_template {
+        raw.extend_from_slice(b"\r\n");
+        build_qr(&mut raw, &qr_url(t, &payload.bill_no));
+    }
+
+    // Centered thank-you line (printed after body)
+    raw.extend_from_slice(&[0x1B, 0x61, 0x01]);
     raw.extend_from_slice(b"Thank you. Visit again!\r\n");
     raw.extend_from_slice(&[0x1B, 0x61, 0x00]);
 
     raw.extend_from_slice(b"\r\n\r\n\r\n"); // bottom margin
-    raw.extend_from_slice(&[0x1D, 0x56, 0x41, 0x03]); // GS V A n (cut after feed)
+    // Split bills take cash too, so they open the drawer as well.
+    if cfg.cash_drawer && matches!(payload.payment_mode.as_str(), "cash" | "split") {
+        raw.extend_from_slice(&CASH_DRAWER_PULSE);
+    }
+    raw.extend_from_slice(cfg.cut.bytes());
+    raw
+}
+
+// Diagnostic slip for checking the printer, paper width and cutter after a roll change.
+fn build_test_bytes(printer: &str, printed_at: &str, w: usize, cut: CutMode) -> Vec<u8> {
+    let charset: String = (0x20u8..0x7F).map(|b| b as char).collect();
+    let mut raw: Vec<u8> = vec![0x1B, 0x40];
+    raw.extend_from_slice(&[0x1B, 0x61, 0x01, 0x1B, 0x45, 0x01]);
+    raw.extend_from_slice(b"PRINTER TEST\r\n");
+    raw.extend_from_slice(&[0x1B, 0x45, 0x00, 0x1B, 0x61, 0x00]);
+    let mut body = vec![sep(w), line_two_col("Printer", printer, w), line_two_col("Time", printed_at, w), sep(w)];
+    let chars: Vec<char> = charset.chars().collect();
+    body.extend(chars.chunks(w.max(1)).map(|c| c.iter().collect::<String>()));
+    body.push((0..w).map(|i| char::from(b'0' + ((i + 1) % 10) as u8)).collect());
+    body.push(sep(w));
+    raw.extend_from_slice(body.join("\r\n").as_bytes());
+    raw.extend_from_slice(b"\r\n\r\n\r\n");
+    raw.extend_from_slice(cut.bytes());
+    raw
+}
+
+// Rebuilds a stored bill as printed: original timestamp, no payment mode so the drawer stays shut.
+fn receipt_from_bill(conn: &Connection, id: i64) -> Result<ReceiptPayload, ApiError> {
+    let (bill_no, created_at, subtotal, dr, dc, tip, surcharge, total, voided, discount_before_tax) = conn
+        .query_row(
+            "SELECT bill_no,created_at,subtotal_cents,discount_rate_bps,discount_cents,tip_cents,surcharge_cents,total_cents,voided_at,discount_before_tax FROM bills WHERE id=?1",
+            params![id],
+            |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, i32>(2)?, r.get::<_, i32>(3)?, r.get::<_, i32>(4)?, r.get::<_, i32>(5)?, r.get::<_, i32>(6)?, r.get::<_, i32>(7)?, r.get::<_, Option<String>>(8)?, r.get::<_, i64>(9)? != 0)),
+        )
+        .map_err(|_| ApiError::NotFound("Bill not found".to_string()))?;
+    if voided.is_some() { return Err("Cannot reprint a voided bill".into()); }
+    let mut stmt = conn.prepare("SELECT product_name,qty,unit_price_cents,line_total_cents FROM bill_items WHERE bill_id=?1 ORDER BY id")?;
+    let items: Vec<ReceiptItem> = stmt
+        .query_map(params![id], |r| Ok(ReceiptItem { name: r.get(0)?, qty: r.get(1)?, unit_price_cents: r.get(2)?, line_total_cents: r.get(3)? }))?
+        .filter_map(|r| r.ok()).collect();
+    let mut stmt = conn.prepare("SELECT tax_rate_bps, SUM(tax_cents) FROM bill_items WHERE bill_id=?1 AND tax_rate_bps > 0 GROUP BY tax_rate_bps ORDER BY tax_rate_bps")?;
+    let taxes: Vec<ReceiptTax> = stmt
+        .query_map(params![id], |r| Ok(ReceiptTax { rate_bps: r.get(0)?, tax_cents: r.get(1)? }))?
+        .filter_map(|r| r.ok()).collect();
+    Ok(ReceiptPayload {
+        bill_no,
+        printed_at: created_at,
+        subtotal_cents: subtotal,
+        discount_rate_bps: dr,
+        discount_cents: dc,
+        tip_cents: tip,
+        surcharge_cents: surcharge,
+        total_cents: total,
+        taxes,
+        payment_mode: String::new(),
+        reprint: true,
+        discount_before_tax,
+        items,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct KotItem {
+    #[serde(alias = "product_name")]
+    name: String,
+    qty: i32,
+    #[serde(default)]
+    note: String,
+}
+
+// Kitchen ticket: double-size names and quantities only. Prices never reach the kitchen.
+fn build_kot_bytes(items: &[KotItem], table: Option<&str>, order: Option<&str>, printed_at: &str, w: usize, cut: CutMode) -> Vec<u8> {
+    let big_w = w / 2; // GS ! 0x11 doubles the glyph width
+    let mut raw: Vec<u8> = vec![0x1B, 0x40];
+    raw.extend_from_slice(&[0x1B, 0x61, 0x01, 0x1D, 0x21, 0x11, 0x1B, 0x45, 0x01]);
+    raw.extend_from_slice(b"KITCHEN\r\n");
+    raw.extend_from_slice(&[0x1B, 0x45, 0x00, 0x1D, 0x21, 0x00, 0x1B, 0x61, 0x00]);
+    let mut head = vec![sep(w)];
+    if let Some(t) = table { head.push(line_two_col("Table", t, w)); }
+    if let Some(o) = order { head.push(line_two_col("Order", o, w)); }
+    head.push(line_two_col("Time", printed_at, w));
+    head.push(sep(w));
+    raw.extend_from_slice(head.join("\r\n").as_bytes());
+    raw.extend_from_slice(b"\r\n");
+    raw.extend_from_slice(&[0x1D, 0x21, 0x11]);
+    for it in items {
+        let qty = format!("{} x ", it.qty);
+        let name = fit_text(it.name.trim(), big_w.saturating_sub(qty.len()).max(1));
+        raw.extend_from_slice(format!("{}{}\r\n", qty, name).as_bytes());
+        if !it.note.trim().is_empty() {
+            raw.extend_from_slice(format!("  ({})\r\n", fit_text(it.note.trim(), big_w.saturating_sub(4).max(1))).as_bytes());
+        }
+    }
+    raw.extend_from_slice(&[0x1D, 0x21, 0x00]);
+    raw.extend_from_slice(sep(w).as_bytes());
+    raw.extend_from_slice(b"\r\n\r\n\r\n");
+    raw.extend_from_slice(cut.bytes());
+    raw
+}
+
+// Quantity changes between two KOTs of the same order, keyed by name and note. Additions come
+// first in current order; lines that went down or disappeared follow as "CANCEL" lines.
+fn kot_delta(previous: &[KotItem], current: &[KotItem]) -> Vec<KotItem> {
+    let key = |it: &KotItem| (it.name.trim().to_string(), it.note.trim().to_string());
+    let mut keys: Vec<(String, String)> = Vec::new();
+    for it in current.iter().chain(previous) {
+        if !keys.contains(&key(it)) { keys.push(key(it)); }
+    }
+    let total = |set: &[KotItem], k: &(String, String)| -> i32 { set.iter().filter(|it| key(it) == *k).map(|it| it.qty).sum() };
+    let changes: Vec<(String, String, i32)> = keys.into_iter()
+        .map(|k| { let d = total(current, &k) - total(previous, &k); (k.0, k.1, d) })
+        .filter(|c| c.2 != 0)
+        .collect();
+    let added = changes.iter().filter(|c| c.2 > 0).map(|(name, note, d)| KotItem { name: name.clone(), qty: *d, note: note.clone() });
+    let cancelled = changes.iter().filter(|c| c.2 < 0).map(|(name, note, d)| KotItem { name: format!("CANCEL {}", name), qty: -d, note: note.clone() });
+    added.chain(cancelled).collect()
+}
+
+// Where finished ESC/POS buffers go; the spooler in the app, a recorder in tests.
+trait PrintTransport: Send + Sync {
+    fn send(&self, printer: &str, data: &[u8]) -> Result<(), ApiError>;
+}
+
+// The OS print queue: winspool on Windows, CUPS `lp` elsewhere.
+struct SpoolerTransport;
+
+impl PrintTransport for SpoolerTransport {
+    fn send(&self, printer: &str, data: &[u8]) -> Result<(), ApiError> {
+        send_raw(printer, data)
+    }
+}
+
+const MAX_COPIES: usize = 5;
+
+// Only the first copy kicks the cash drawer. With `labelled`, the first copy is the
+// customer's and the rest are marked for the merchant.
+fn do_print(transport: &dyn PrintTransport, printer: &str, payload: &ReceiptPayload, cfg: &PrintConfig, copies: usize, labelled: bool) -> Result<(), ApiError> {
+    let first = build_receipt_bytes(payload, cfg);
+    let rest = if copies > 1 { build_receipt_bytes(payload, &PrintConfig { cash_drawer: false, ..cfg.clone() }) } else { Vec::new() };
+    for n in 0..copies.clamp(1, MAX_COPIES) {
+        let body = if n == 0 { &first } else { &rest };
+        if labelled {
+            let label = if n == 0 { "CUSTOMER COPY" } else { "MERCHANT COPY" };
+            let mut raw = vec![0x1B, 0x40, 0x1B, 0x61, 0x01, 0x1B, 0x45, 0x01]; // init, center, bold
+            raw.extend_from_slice(label.as_bytes());
+            raw.extend_from_slice(b"\r\n");
+            raw.extend_from_slice(&[0x1B, 0x45, 0x00, 0x1B, 0x61, 0x00]);
+            raw.extend_from_slice(body);
+            transport.send(printer, &raw)?;
+        } else {
+            transport.send(printer, body)?;
+        }
+    }
+    Ok(())
+}
+
+fn send_raw(printer: &str, raw: &[u8]) -> Result<(), ApiError> {
+    let ms = SystemTime::now().duration_since(UNIX_EPOCH).map_err(internal("Clock"))?.as_millis();
+    let tmp = std::env::temp_dir().join(format!("meateat_{}.txt", ms));
+
+    write(&tmp, raw).map_err(internal("Write receipt"))?;
+    let result = print_file(printer.trim(), &tmp);
+    let _ = remove_file(&tmp);
+    result
+}
+
+// CUPS takes the same ESC/POS bytes untouched with `-o raw`.
+#[cfg(not(windows))]
+fn lp_args(printer: &str, file: &Path) -> Vec<String> {
+    vec!["-d".to_string(), printer.to_string(), "-o".to_string(), "raw".to_string(), file.to_string_lossy().to_string()]
+}
 
-    write(&tmp, raw).map_err(|e| format!("Write receipt: {e}"))?;
+#[cfg(not(windows))]
+fn print_file(printer: &str, file: &Path) -> Result<(), ApiError> {
+    let out = Command::new("lp").args(lp_args(printer, file)).output().map_err(internal("Print failed"))?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr).to_string();
+        return Err(ApiError::Internal(format!("Print on '{}': {}", printer, stderr)));
+    }
+    Ok(())
+}
 
-    let p_esc = ps_escape(&tmp.to_string_lossy());
-    let pr_esc = ps_escape(printer.trim());
+#[cfg(windows)]
+fn print_file(printer: &str, file: &Path) -> Result<(), ApiError> {
+    let p_esc = ps_escape(&file.to_string_lossy());
+    let pr_esc = ps_escape(printer);
 
     // Use Win32 raw printing to send text directly to the thermal printer.
     // This bypasses the GDI driver so the printer uses its own built-in font
@@ -344,25 +1833,193 @@ if (-not $ok) {{ throw "Raw print failed for printer '{pr_esc}'" }}
         .arg("-ExecutionPolicy").arg("Bypass")
         .arg("-Command").arg(&cmd)
         .output()
-        .map_err(|e| format!("Print failed: {e}"))?;
+        .map_err(internal("Print failed"))?;
 
-    let _ = remove_file(&tmp);
     if !out.status.success() {
         let stderr = String::from_utf8_lossy(&out.stderr).to_string();
-        return Err(format!("Print on '{}': {}", printer, stderr));
+        return Err(ApiError::Internal(format!("Print on '{}': {}", printer, stderr)));
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn list_printers() -> Result<Vec<String>, ApiError> {
+    let out = Command::new("powershell")
+        .arg("-NoProfile").arg("-WindowStyle").arg("Hidden")
+        .arg("-ExecutionPolicy").arg("Bypass")
+        .arg("-Command").arg("Get-Printer | Select-Object -ExpandProperty Name")
+        .output()
+        .map_err(internal("List printers"))?;
+    if !out.status.success() {
+        return Err(ApiError::Internal(format!("List printers: {}", String::from_utf8_lossy(&out.stderr).trim())));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect())
+}
+
+// `lpstat -a` prints "<queue> accepting requests since ..." per printer; the queue name is what `lp -d` takes.
+#[cfg(not(windows))]
+fn list_printers() -> Result<Vec<String>, ApiError> {
+    let out = Command::new("lpstat").arg("-a").output().map_err(internal("List printers"))?;
+    if !out.status.success() {
+        return Err(ApiError::Internal(format!("List printers: {}", String::from_utf8_lossy(&out.stderr).trim())));
+    }
+    Ok(parse_lpstat(&String::from_utf8_lossy(&out.stdout)))
+}
+
+#[cfg(not(windows))]
+fn parse_lpstat(out: &str) -> Vec<String> {
+    out.lines().filter_map(|l| l.split_whitespace().next()).map(str::to_string).collect()
+}
+
+// -- product images -----------------------------------------------------------
+
+const MAX_IMAGE_BYTES: u64 = 2 * 1024 * 1024;
+
+fn image_ext(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(&[0x89, b'P', b'N', b'G']) { Some("png") }
+    else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) { Some("jpg") }
+    else if bytes.starts_with(b"GIF8") { Some("gif") }
+    else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" { Some("webp") }
+    else { None }
+}
+
+fn remove_product_images(dir: &Path, id: i64) {
+    for ext in ["png", "jpg", "gif", "webp"] {
+        let _ = remove_file(dir.join(format!("{}.{}", id, ext)));
     }
+}
+
+// -- products -----------------------------------------------------------------
+
+// Shared by the product list and search so both return the same shape.
+// The row just written by POST /products, flattened next to `ok` so the UI can insert it without a refetch.
+// Item numbers are 1..=9999; the next one is always one past the highest in use.
+fn next_item_no(conn: &Connection) -> Result<i64, ApiError> {
+    let mx: i64 = conn.query_row("SELECT COALESCE(MAX(item_no), 0) FROM products", [], |r| r.get(0))?;
+    if mx >= 9999 { return Err("Item No overflow".into()); }
+    Ok(mx + 1)
+}
+
+fn inserted_product(conn: &Connection) -> Result<Value, ApiError> {
+    let id = conn.last_insert_rowid();
+    let mut row = conn.query_row(&format!("{} WHERE p.id = ?1", PRODUCT_SELECT), params![id], product_row)?;
+    row["ok"] = json!(true);
+    Ok(row)
+}
+
+const PRODUCT_SELECT: &str = "SELECT p.id, p.item_no, p.name, c.name as category, p.price_cents, p.is_available, p.image_path, p.tax_rate_bps, p.stock_qty, p.low_stock_threshold, p.cost_cents, p.deleted_at, p.barcode FROM products p LEFT JOIN categories c ON p.category_id = c.id";
+
+fn product_row(r: &rusqlite::Row) -> rusqlite::Result<Value> {
+    Ok(json!({
+        "id": r.get::<_, i64>(0)?,
+        "item_no": r.get::<_, Option<i64>>(1)?,
+        "name": r.get::<_, String>(2)?,
+        "category": r.get::<_, Option<String>>(3)?,
+        "price_cents": r.get::<_, i64>(4)?,
+        "is_available": r.get::<_, i64>(5)?,
+        "image_path": r.get::<_, Option<String>>(6)?,
+        "tax_rate_bps": r.get::<_, i64>(7)?,
+        "stock_qty": r.get::<_, Option<i64>>(8)?,
+        "low_stock_threshold": r.get::<_, i64>(9)?,
+        "cost_cents": r.get::<_, i64>(10)?,
+        "deleted_at": r.get::<_, Option<String>>(11)?,
+        "barcode": r.get::<_, Option<String>>(12)?,
+    }))
+}
+
+// Partial update for PUT /products/batch: only keys present in the patch are written.
+fn apply_product_patch(conn: &Connection, id: i64, p: &Value) -> Result<(), ApiError> {
+    let old_price: i64 = conn.query_row("SELECT price_cents FROM products WHERE id = ?1 AND deleted_at IS NULL", params![id], |r| r.get(0))
+        .map_err(|_| ApiError::NotFound("Product not found".to_string()))?;
+    let name = match p.get("name") {
+        None | Some(Value::Null) => None,
+        Some(v) => Some(v.as_str().map(|s| s.trim()).filter(|s| !s.is_empty()).ok_or("name cannot be empty")?.to_string()),
+    };
+    let price = if p.get("price_cents").is_some() || p.get("price_rupees").is_some() { Some(price_field(p)?) } else { None };
+    let set_cat = p.get("category").is_some();
+    let cat_id = resolve_category_id(conn, p["category"].as_str().unwrap_or("").trim());
+    let avail = match p.get("is_available") {
+        None | Some(Value::Null) => None,
+        Some(v) => Some(v.as_bool().map(|f| f as i64).or_else(|| v.as_i64().filter(|n| *n == 0 || *n == 1)).ok_or("is_available must be 0 or 1")?),
+    };
+    let set_no = p.get("item_no").is_some();
+    let item_no = match p.get("item_no") {
+        None | Some(Value::Null) => None,
+        Some(v) => Some(v.as_i64().filter(|n| (1..=9999).contains(n)).ok_or("item_no must be between 1 and 9999")?),
+    };
+    conn.execute(
+        "UPDATE products SET name=COALESCE(?2, name), price_cents=COALESCE(?3, price_cents), category_id=CASE WHEN ?4 THEN ?5 ELSE category_id END, is_available=COALESCE(?6, is_available), item_no=CASE WHEN ?7 THEN ?8 ELSE item_no END, updated_at=datetime('now','localtime') WHERE id=?1",
+        params![id, name, price, set_cat, cat_id, avail, set_no, item_no],
+    ).map_err(product_write_err)?;
+    if let Some(new) = price { record_price_change(conn, id, old_price, new)?; }
     Ok(())
 }
 
+fn product_write_err(e: rusqlite::Error) -> ApiError {
+    let m = e.to_string().to_lowercase();
+    if m.contains("unique") && m.contains("item_no") { ApiError::Conflict("Item No already in use".to_string()) }
+    else if m.contains("unique") && m.contains("barcode") { ApiError::Conflict("Barcode already in use".to_string()) }
+    else { e.into() }
+}
+
+fn barcode_field(b: &Value) -> Option<String> {
+    b["barcode"].as_str().map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+fn tax_rate_field(b: &Value) -> Result<Option<i64>, String> {
+    match b.get("tax_rate_bps") {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => v.as_i64().filter(|n| (0..=10_000).contains(n)).map(Some).ok_or_else(|| "tax_rate_bps must be between 0 and 10000".to_string()),
+    }
+}
+
+// GST is levied half as CGST and half as SGST; any odd paisa goes to SGST.
+fn split_gst(tax_cents: i64) -> (i64, i64) {
+    let cgst = tax_cents / 2;
+    (cgst, tax_cents - cgst)
+}
+
 // -- backup -------------------------------------------------------------------
 
-fn list_backups(dir: &PathBuf) -> Vec<Value> {
+fn is_backup_name(name: &str) -> bool { name.ends_with(".db") || name.ends_with(".db.gz") }
+
+fn sidecar_path(backup: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.sha256", backup.to_string_lossy()))
+}
+
+fn sha256_file(path: &Path) -> Result<String, ApiError> {
+    let mut f = fs::File::open(path).map_err(internal("Hash open"))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut f, &mut hasher).map_err(internal("Hash read"))?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// Sidecars use the `sha256sum` layout ("<hex>  <name>"), so only the first token matters.
+fn read_checksum(backup: &Path) -> Option<String> {
+    let text = fs::read_to_string(sidecar_path(backup)).ok()?;
+    text.split_whitespace().next().map(|s| s.to_lowercase())
+}
+
+fn verify_backup(backup: &Path) -> Result<(), ApiError> {
+    let Some(expected) = read_checksum(backup) else { return Ok(()) };
+    if sha256_file(backup)? != expected {
+        return Err("Backup checksum mismatch: the file is corrupt or incomplete".into());
+    }
+    Ok(())
+}
+
+// `verify` re-hashes every file against its sidecar, so only ask for it on demand.
+fn list_backups(dir: &PathBuf, verify: bool) -> Vec<Value> {
     let Ok(entries) = read_dir(dir) else { return vec![] };
     let mut results: Vec<(String, String, u64, String)> = Vec::new();
     for entry in entries.flatten() {
         let p = entry.path();
-        if p.is_file() && p.extension().map(|e| e == "db").unwrap_or(false) {
-            let name = entry.file_name().to_string_lossy().to_string();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if p.is_file() && is_backup_name(&name) {
             let full = p.to_string_lossy().to_string();
             if let Ok(meta) = fs::metadata(&p) {
                 let modified = meta.modified().ok()
@@ -375,163 +2032,666 @@ fn list_backups(dir: &PathBuf) -> Vec<Value> {
     }
     results.sort_by(|a, b| b.3.cmp(&a.3));
     results.into_iter().map(|(name, path, size, modified)| {
-        json!({ "name": name, "path": path, "modified_at": modified, "size_bytes": size })
+        let p = PathBuf::from(&path);
+        let checksum = read_checksum(&p);
+        let verified = if verify { checksum.as_ref().map(|c| sha256_file(&p).map(|h| &h == c).unwrap_or(false)) } else { None };
+        json!({ "name": name, "path": path, "modified_at": modified, "size_bytes": size, "checksum": checksum, "verified": verified })
     }).collect()
 }
 
-fn do_backup(conn: &Connection, db_path: &PathBuf, target_dir: &PathBuf) -> Result<String, String> {
+fn do_backup(conn: &Connection, db_path: &PathBuf, target_dir: &PathBuf) -> Result<String, ApiError> {
     let _ = conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);");
     let ts = simple_ts();
-    let fname = format!("meet-eat-{}.db", ts);
-    create_dir_all(target_dir).map_err(|e| format!("Backup dir: {e}"))?;
+    let compress = get_setting(conn, "backup_compress", "0") == "1";
+    let fname = if compress { format!("meet-eat-{}.db.gz", ts) } else { format!("meet-eat-{}.db", ts) };
+    create_dir_all(target_dir).map_err(internal("Backup dir"))?;
     let dst = target_dir.join(&fname);
-    fs::copy(db_path, &dst).map_err(|e| format!("Backup copy: {e}"))?;
+    if compress {
+        let mut src = fs::File::open(db_path).map_err(internal("Backup open"))?;
+        let out = fs::File::create(&dst).map_err(internal("Backup create"))?;
+        let mut enc = GzEncoder::new(out, Compression::default());
+        std::io::copy(&mut src, &mut enc).map_err(internal("Backup compress"))?;
+        enc.finish().map_err(internal("Backup compress"))?;
+    } else {
+        fs::copy(db_path, &dst).map_err(internal("Backup copy"))?;
+    }
+    let digest = sha256_file(&dst)?;
+    write(sidecar_path(&dst), format!("{}  {}\n", digest, fname)).map_err(internal("Backup checksum"))?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    set_setting(conn, "last_backup_at", &now.to_string());
+    let keep = get_setting(conn, "backup_retention_count", "30").parse::<usize>().unwrap_or(30).max(1);
+    prune_backups(target_dir, keep);
     Ok(dst.to_string_lossy().to_string())
 }
 
+// Only our own `meet-eat-<ts>.db[.gz]` files are eligible; anything else the user
+// drops into the folder is left alone. The timestamp in the name sorts oldest first.
+fn prune_backups(dir: &Path, keep: usize) {
+    let Ok(entries) = read_dir(dir) else { return };
+    let mut ours: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            let name = p.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            p.is_file() && name.starts_with("meet-eat-") && is_backup_name(&name)
+        })
+        .collect();
+    if ours.len() <= keep { return; }
+    ours.sort();
+    for p in &ours[..ours.len() - keep] {
+        let _ = remove_file(p);
+        let _ = remove_file(sidecar_path(p));
+    }
+}
+
+// Run by the scheduler thread every BACKUP_TICK_SECS; backs up only once the
+// configured interval has passed since `last_backup_at`.
+fn run_due_backup(state: &AppState) -> Result<Option<String>, ApiError> {
+    with_db(state, |conn| {
+        let iv = get_setting(conn, "backup_interval_minutes", "1440").parse::<i64>().unwrap_or(1440).max(1);
+        let last = get_setting(conn, "last_backup_at", "0").parse::<i64>().unwrap_or(0);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+        if now - last < iv * 60 {
+            return Ok(None);
+        }
+        let target = get_setting(conn, "backup_path", &state.backup_dir.to_string_lossy());
+        do_backup(conn, &state.db_path, &PathBuf::from(target)).map(Some)
+    })
+}
+
+const BACKUP_TICK_SECS: u64 = 60;
+
+// Release builds have no console, so a failed scheduled backup is only reported through state.errors.
+fn scheduled_backup_tick(state: &AppState) {
+    if let Err(e) = run_due_backup(state) {
+        record_error(state, "SCHEDULE", "/backup/run", &e);
+    }
+}
+
+// Copies a backup to `dst`, inflating `.gz` backups on the way.
+fn copy_backup(src: &Path, dst: &Path) -> Result<(), ApiError> {
+    if src.to_string_lossy().ends_with(".gz") {
+        let input = fs::File::open(src).map_err(internal("Open backup"))?;
+        let mut out = fs::File::create(dst).map_err(internal("Restore"))?;
+        std::io::copy(&mut GzDecoder::new(input), &mut out).map_err(internal("Decompress backup"))?;
+    } else {
+        fs::copy(src, dst).map_err(internal("Restore"))?;
+    }
+    Ok(())
+}
+
+fn resolve_backup_source(b: &Value) -> Result<PathBuf, ApiError> {
+    let src_raw = b.get("source").and_then(|v| v.as_str()).map(|s| s.to_string())
+        .or_else(|| { let bp = b.get("backup_path").and_then(|v| v.as_str())?; let f = b.get("file_name").and_then(|v| v.as_str())?; Some(format!("{}\\{}", bp, f)) })
+        .or_else(|| b.get("backup_path").and_then(|v| v.as_str()).map(|s| s.to_string()))
+        .unwrap_or_default();
+    if src_raw.is_empty() { return Err("No backup source".into()); }
+
+    let sp = PathBuf::from(&src_raw);
+    if sp.is_file() { Ok(sp) } else if sp.is_dir() {
+        let bks = list_backups(&sp, false);
+        let first = bks.first().and_then(|v| v["path"].as_str().map(PathBuf::from));
+        first.ok_or_else(|| ApiError::NotFound("No backup files in directory".to_string()))
+    } else { Err(ApiError::NotFound("Backup not found".to_string())) }
+}
+
+fn db_summary(conn: &Connection) -> Result<Value, ApiError> {
+    let tables: i64 = conn
+        .query_row("SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name IN ('products','categories','bills','bill_items')", [], |r| r.get(0))
+        .map_err(|e| format!("Not a readable database: {e}"))?;
+    if tables != 4 { return Err("Not a POS database".into()); }
+    let count = |sql: &str| conn.query_row(sql, [], |r| r.get::<_, i64>(0)).unwrap_or(0);
+    let max_bill_no: Option<String> = conn.query_row("SELECT MAX(bill_no) FROM bills", [], |r| r.get(0)).unwrap_or(None);
+    let newest_bill_at: Option<String> = conn.query_row("SELECT MAX(created_at) FROM bills", [], |r| r.get(0)).unwrap_or(None);
+    Ok(json!({
+        "products": count("SELECT COUNT(*) FROM products"),
+        "categories": count("SELECT COUNT(*) FROM categories"),
+        "bills": count("SELECT COUNT(*) FROM bills"),
+        "max_bill_no": max_bill_no,
+        "newest_bill_at": newest_bill_at,
+    }))
+}
+
+// Local time, so a backup taken at 11pm IST is stamped with that day, not the next.
 fn simple_ts() -> String {
-    let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
-    let days = secs / 86400;
-    let tod = secs % 86400;
-    let (y, m, d) = days_to_ymd(days);
-    format!("{:04}{:02}{:02}_{:02}{:02}{:02}", y, m, d, tod / 3600, (tod % 3600) / 60, tod % 60)
-}
-
-fn days_to_ymd(days: i64) -> (i64, i64, i64) {
-    let z = days + 719468;
-    let era = if z >= 0 { z } else { z - 146096 } / 146097;
-    let doe = z - era * 146097;
-    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
-    let y = yoe + era * 400;
-    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
-    let mp = (5 * doy + 2) / 153;
-    let d = doy - (153 * mp + 2) / 5 + 1;
-    let m = if mp < 10 { mp + 3 } else { mp - 9 };
-    let yr = if m <= 2 { y + 1 } else { y };
-    (yr, m, d)
+    chrono::Local::now().format("%Y%m%d_%H%M%S").to_string()
 }
 
 // -- API router ---------------------------------------------------------------
 
 #[tauri::command]
 fn api_call(
+    app: tauri::AppHandle,
     state: State<AppState>,
     method: String,
     path: String,
     body: Option<Value>,
-) -> Result<Value, String> {
-    let (base, qs) = parse_qs(&path);
+) -> Result<Value, ApiError> {
+    let result = route(Some(&app), state.inner(), &method, &path, body);
+    if let Err(e) = &result {
+        record_error(state.inner(), &method, &path, e);
+    }
+    result
+}
+
+// `app` is only needed to relaunch; tests route without a running Tauri app.
+fn route(
+    app: Option<&tauri::AppHandle>,
+    state: &AppState,
+    method: &str,
+    path: &str,
+    body: Option<Value>,
+) -> Result<Value, ApiError> {
+    let (base, qs) = parse_qs(path);
     let base = base.as_str();
-    let method = method.as_str();
 
     match (method, base) {
-        ("GET", "/health") => Ok(json!({ "ok": true })),
+        ("GET", "/health") => match db_health(state) {
+            Ok(()) => Ok(json!({ "ok": true })),
+            Err(reason) => Ok(json!({ "ok": false, "reason": reason })),
+        },
 
-        ("GET", "/metrics") => with_db(state.inner(), |conn| {
-            let count: i64 = conn.query_row("SELECT COUNT(*) FROM bills", [], |r| r.get(0)).unwrap_or(0);
-            let size = fs::metadata(&state.db_path).map(|m| m.len()).unwrap_or(0);
-            Ok(json!({ "bills": count, "db_size_bytes": size }))
+        ("GET", "/diagnostics/errors") => {
+            let errors = state.errors.lock().map_err(|_| ApiError::Internal("Error log lock poisoned".to_string()))?;
+            Ok(json!({ "errors": errors.iter().rev().cloned().collect::<Vec<Value>>() }))
+        }
+
+        // One round-trip for everything the UI needs at launch.
+        ("GET", "/bootstrap") => with_db(state, |conn| {
+            Ok(json!({
+                "app_version": env!("CARGO_PKG_VERSION"),
+                "schema_version": get_setting(conn, "schema_version", "0").parse::<i64>().unwrap_or(0),
+                "settings": export_settings(conn),
+                "categories": list_categories(conn, false)?,
+                "print_config": load_print_config(conn),
+            }))
         }),
 
-        // -- categories -------------------------------------------------------
-        ("GET", "/categories") => with_db(state.inner(), |conn| {
-            let mut stmt = conn.prepare("SELECT id, name, is_active FROM categories ORDER BY name").map_err(|e| e.to_string())?;
-            let rows: Vec<Value> = stmt.query_map([], |r| Ok(json!({ "id": r.get::<_, i64>(0)?, "name": r.get::<_, String>(1)?, "is_active": r.get::<_, i64>(2)? }))).map_err(|e| e.to_string())?.filter_map(|r| r.ok()).collect();
-            Ok(json!(rows))
+        ("GET", "/metrics") => with_db(state, |conn| {
+            let count: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM bills WHERE voided_at IS NULL{}", training_clause(&qs, "is_training")), [], |r| r.get(0)).unwrap_or(0);
+            let size = fs::metadata(&state.db_path).map(|m| m.len()).unwrap_or(0);
+            // Untracked products (NULL stock) and deleted ones never show up here.
+            let mut ls = conn.prepare("SELECT id, name, stock_qty, low_stock_threshold FROM products WHERE deleted_at IS NULL AND stock_qty IS NOT NULL AND stock_qty <= low_stock_threshold ORDER BY stock_qty, name")?;
+            let low_stock: Vec<Value> = ls.query_map([], |r| Ok(json!({ "id": r.get::<_, i64>(0)?, "name": r.get::<_, String>(1)?, "stock_qty": r.get::<_, i64>(2)?, "threshold": r.get::<_, i64>(3)? })))?.filter_map(|r| r.ok()).collect();
+            let tc = training_clause(&qs, "is_training");
+            let window = |from: &str, to: &str| -> (i64, i64) {
+                conn.query_row(
+                    &format!("SELECT COUNT(*), COALESCE(SUM(total_cents), 0) FROM bills WHERE created_at >= {} AND created_at < {} AND voided_at IS NULL{}", from, to, tc),
+                    [],
+                    |r| Ok((r.get(0)?, r.get(1)?)),
+                ).unwrap_or((0, 0))
+            };
+            let (today_count, today_revenue) = window("date('now','localtime')", "date('now','localtime','+1 day')");
+            let (_, month_revenue) = window("date('now','localtime','start of month')", "date('now','localtime','start of month','+1 month')");
+            let aov = if today_count > 0 { today_revenue / today_count } else { 0 };
+            Ok(json!({
+                "bills": count,
+                "db_size_bytes": size,
+                "low_stock": low_stock,
+                "today_bill_count": today_count,
+                "today_revenue_cents": today_revenue,
+                "month_revenue_cents": month_revenue,
+                "avg_order_value_cents": aov,
+            }))
         }),
 
-        // -- products ---------------------------------------------------------
-        ("GET", "/products/search") => with_db(state.inner(), |conn| {
-            let q = qs.get("q").cloned().unwrap_or_default();
-            let pat = format!("%{}%", q);
-            let mut stmt = conn.prepare(
-                "SELECT p.id, p.item_no, p.name, c.name as category, p.price_cents, p.is_available FROM products p LEFT JOIN categories c ON p.category_id = c.id WHERE p.is_available = 1 AND (p.name LIKE ?1 OR CAST(p.item_no AS TEXT) LIKE ?1) ORDER BY (p.item_no IS NULL), p.item_no, p.name LIMIT 20"
-            ).map_err(|e| e.to_string())?;
-            let rows: Vec<Value> = stmt.query_map(params![pat], |r| Ok(json!({ "id": r.get::<_, i64>(0)?, "item_no": r.get::<_, Option<i64>>(1)?, "name": r.get::<_, String>(2)?, "category": r.get::<_, Option<String>>(3)?, "price_cents": r.get::<_, i64>(4)?, "is_available": r.get::<_, i64>(5)? }))).map_err(|e| e.to_string())?.filter_map(|r| r.ok()).collect();
-            Ok(json!(rows))
+        // Polled every minute by the header widget, so keep it to one indexed query.
+        ("GET", "/stats/today") => with_db(state, |conn| {
+            let row = conn
+                .query_row(
+                    &format!("SELECT COUNT(*), COALESCE(SUM(total_cents), 0), MAX(created_at)
+                     FROM bills
+                     WHERE created_at >= date('now','localtime') AND created_at < date('now','localtime','+1 day') AND voided_at IS NULL{}", training_clause(&qs, "is_training")),
+                    [],
+                    |r| Ok((r.get::<_, i64>(0)?, r.get::<_, i64>(1)?, r.get::<_, Option<String>>(2)?)),
+                )
+                .unwrap_or((0, 0, None));
+            Ok(json!({ "bill_count": row.0, "net_total_cents": row.1, "last_bill_at": row.2 }))
         }),
 
-        ("GET", "/products") => with_db(state.inner(), |conn| {
-            let mut stmt = conn.prepare(
-                "SELECT p.id, p.item_no, p.name, c.name as category, p.price_cents, p.is_available FROM products p LEFT JOIN categories c ON p.category_id = c.id ORDER BY (p.item_no IS NULL), p.item_no, p.name"
-            ).map_err(|e| e.to_string())?;
-            let rows: Vec<Value> = stmt.query_map([], |r| Ok(json!({ "id": r.get::<_, i64>(0)?, "item_no": r.get::<_, Option<i64>>(1)?, "name": r.get::<_, String>(2)?, "category": r.get::<_, Option<String>>(3)?, "price_cents": r.get::<_, i64>(4)?, "is_available": r.get::<_, i64>(5)? }))).map_err(|e| e.to_string())?.filter_map(|r| r.ok()).collect();
-            Ok(json!(rows))
+        ("POST", "/db/repair-item-no") => with_db(state, |conn| {
+            let changes = repair_item_nos(conn)?;
+            conn.execute_batch(ITEM_NO_INDEX)?;
+            Ok(json!({ "ok": true, "changes": changes }))
         }),
 
-        ("POST", "/products") => with_db(state.inner(), |conn| {
+        ("POST", "/db/pragma") => with_db(state, |conn| {
             let b = body.as_ref().ok_or("Missing body")?;
-            let name = b["name"].as_str().ok_or("name required")?.trim().to_string();
-            let cat = b["category"].as_str().unwrap_or("");
-            let price = b["price_cents"].as_i64().ok_or("price_cents required")?;
-            let cat_id = resolve_category_id(conn, cat);
-            let raw_no = b.get("item_no").and_then(|v| v.as_i64());
-
-            if let Some(n) = raw_no {
-                if n >= 1 && n <= 9999 {
-                    conn.execute("INSERT INTO products(item_no, name, category_id, price_cents, is_available) VALUES(?1,?2,?3,?4,1)", params![n, name, cat_id, price]).map_err(|e| {
-                        let m = e.to_string().to_lowercase();
-                        if m.contains("unique") && m.contains("item_no") { "Item No already in use".to_string() } else { e.to_string() }
-                    })?;
-                    return Ok(json!({ "ok": true }));
+            let mut updates = Vec::new();
+            for (key, _) in TUNING_SETTINGS {
+                if let Some(v) = b.get(*key) {
+                    updates.push((*key, normalize_setting(key, v).map_err(|e| format!("{}: {}", key, e))?));
                 }
             }
-            for _ in 0..3 {
-                let mx: i64 = conn.query_row("SELECT COALESCE(MAX(item_no), 0) FROM products", [], |r| r.get(0)).unwrap_or(0);
-                let nx = mx + 1;
-                if nx < 1 || nx > 9999 { return Err("Item No overflow".to_string()); }
-                match conn.execute("INSERT INTO products(item_no, name, category_id, price_cents, is_available) VALUES(?1,?2,?3,?4,1)", params![nx, name, cat_id, price]) {
-                    Ok(_) => return Ok(json!({ "ok": true })),
-                    Err(e) => {
-                        let m = e.to_string().to_lowercase();
-                        if m.contains("unique") && m.contains("item_no") { continue; }
-                        return Err(e.to_string());
-                    }
-                }
+            for (key, value) in &updates {
+                set_setting(conn, key, value);
             }
-            Err("Failed to allocate Item No".to_string())
+            apply_tuning_pragmas(conn)?;
+            let current: serde_json::Map<String, Value> = TUNING_SETTINGS.iter()
+                .map(|(k, d)| (k.to_string(), json!(get_setting(conn, k, d).parse::<i64>().unwrap_or(0))))
+                .collect();
+            Ok(json!({ "ok": true, "pragmas": current }))
         }),
 
-        _ if method == "PUT" && base.ends_with("/availability") => {
-            let id_str = base.trim_start_matches("/products/").trim_end_matches("/availability");
-            let id: i64 = id_str.parse().map_err(|_| "Invalid product id".to_string())?;
+        // Read-only; safe to run while billing is live.
+        ("GET", "/maintenance/integrity") => with_db(state, |conn| {
+            let mut issues = Vec::new();
+            let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+            for msg in stmt.query_map([], |r| r.get::<_, String>(0))?.filter_map(|r| r.ok()) {
+                if msg != "ok" { issues.push(json!({ "check": "integrity", "message": msg })); }
+            }
+            let mut stmt = conn.prepare("PRAGMA foreign_key_check")?;
+            let fk = stmt.query_map([], |r| Ok(json!({
+                "check": "foreign_key",
+                "table": r.get::<_, String>(0)?,
+                "rowid": r.get::<_, Option<i64>>(1)?,
+                "parent": r.get::<_, String>(2)?,
+            })))?.filter_map(|r| r.ok());
+            issues.extend(fk);
+            Ok(json!({ "ok": issues.is_empty(), "issues": issues }))
+        }),
+
+        // Holds the db mutex for the whole run, so billing blocks until VACUUM finishes.
+        ("POST", "/maintenance/optimize") => with_db(state, |conn| {
+            let before = db_disk_size(&state.db_path);
+            conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(())).map_err(internal("Checkpoint failed"))?;
+            conn.execute_batch("VACUUM; PRAGMA optimize;").map_err(internal("Optimize failed"))?;
+            let after = db_disk_size(&state.db_path);
+            let warning = (before >= LARGE_DB_BYTES).then(|| format!(
+                "Database is {} MB; VACUUM needs about as much free disk space and blocks billing while it runs",
+                before / (1024 * 1024)
+            ));
+            Ok(json!({ "ok": true, "size_before_bytes": before, "size_after_bytes": after, "warning": warning }))
+        }),
+
+        // -- categories -------------------------------------------------------
+        ("GET", "/categories") => with_db(state, |conn| {
+            let active_only = qs.get("active_only").map(|v| v == "1").unwrap_or(false);
+            Ok(json!(list_categories(conn, active_only)?))
+        }),
+
+        _ if method == "PUT" && base.starts_with("/categories/") && base.ends_with("/active") => {
+            let id: i64 = base.trim_start_matches("/categories/").trim_end_matches("/active").parse().map_err(|_| "Invalid category id".to_string())?;
             let b = body.as_ref().ok_or("Missing body")?;
-            let avail = b["is_available"].as_i64().unwrap_or(1);
-            with_db(state.inner(), |conn| {
-                conn.execute("UPDATE products SET is_available = ?1 WHERE id = ?2", params![avail, id]).map_err(|e| e.to_string())?;
+            let active = b["is_active"].as_bool().map(|v| v as i64).or_else(|| b["is_active"].as_i64()).ok_or("is_active required")?;
+            with_db(state, |conn| {
+                let changed = conn.execute("UPDATE categories SET is_active = ?1 WHERE id = ?2", params![(active != 0) as i64, id])?;
+                if changed == 0 { return Err(ApiError::NotFound("Category not found".to_string())); }
                 Ok(json!({ "ok": true }))
             })
         }
 
-        _ if method == "PUT" && base.starts_with("/products/") => {
-            let id: i64 = base.trim_start_matches("/products/").parse().map_err(|_| "Invalid id".to_string())?;
+        _ if method == "PUT" && base.starts_with("/categories/") => {
+            let id: i64 = base.trim_start_matches("/categories/").parse().map_err(|_| "Invalid category id".to_string())?;
             let b = body.as_ref().ok_or("Missing body")?;
-            let name = b["name"].as_str().ok_or("name required")?.trim().to_string();
-            let cat = b["category"].as_str().unwrap_or("");
-            let price = b["price_cents"].as_i64().ok_or("price_cents required")?;
-            let raw_no = b.get("item_no").and_then(|v| v.as_i64());
-            let item_no = raw_no.and_then(|n| if n >= 1 && n <= 9999 { Some(n) } else { None });
-            with_db(state.inner(), |conn| {
-                let cat_id = resolve_category_id(conn, cat);
-                conn.execute("UPDATE products SET item_no=?1, name=?2, category_id=?3, price_cents=?4, updated_at=datetime('now','localtime') WHERE id=?5", params![item_no, name, cat_id, price, id]).map_err(|e| {
-                    let m = e.to_string().to_lowercase();
-                    if m.contains("unique") && m.contains("item_no") { "Item No already in use".to_string() } else { e.to_string() }
+            let name = b["name"].as_str().map(|s| s.trim()).filter(|s| !s.is_empty()).ok_or("name required")?;
+            with_db(state, |conn| {
+                let changed = conn.execute("UPDATE categories SET name = ?1 WHERE id = ?2", params![name, id]).map_err(|e| {
+                    if e.to_string().to_lowercase().contains("unique") { ApiError::Conflict(format!("A category named '{}' already exists", name)) } else { e.into() }
                 })?;
+                if changed == 0 { return Err(ApiError::NotFound("Category not found".to_string())); }
                 Ok(json!({ "ok": true }))
             })
         }
 
-        _ if method == "DELETE" && base.starts_with("/products/") => {
-            let id: i64 = base.trim_start_matches("/products/").parse().map_err(|_| "Invalid id".to_string())?;
-            with_db(state.inner(), |conn| {
+        ("POST", "/categories/merge") => with_db(state, |conn| {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let from_id = b["from_id"].as_i64().ok_or("from_id required")?;
+            let into_id = b["into_id"].as_i64().ok_or("into_id required")?;
+            if from_id == into_id { return Err("Cannot merge a category into itself".into()); }
+            let found: i64 = conn.query_row("SELECT COUNT(*) FROM categories WHERE id IN (?1, ?2)", params![from_id, into_id], |r| r.get(0)).unwrap_or(0);
+            if found != 2 { return Err(ApiError::NotFound("Category not found".to_string())); }
+            let tx = conn.unchecked_transaction()?;
+            let moved = tx.execute("UPDATE products SET category_id = ?1, updated_at = datetime('now','localtime') WHERE category_id = ?2", params![into_id, from_id])?;
+            tx.execute("DELETE FROM categories WHERE id = ?1", params![from_id])?;
+            tx.commit()?;
+            Ok(json!({ "ok": true, "moved": moved }))
+        }),
+
+        // Body is the full desired order; categories left out keep their relative order after it.
+        ("POST", "/categories/reorder") => with_db(state, |conn| {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let ids: Vec<i64> = b.as_array().or_else(|| b["ids"].as_array()).ok_or("Array of category ids required")?
+                .iter().map(|v| v.as_i64().ok_or("Invalid category id".to_string())).collect::<Result<_, _>>()?;
+            let mut all: Vec<i64> = {
+                let mut stmt = conn.prepare("SELECT id FROM categories ORDER BY sort_order, name")?;
+                let rows = stmt.query_map([], |r| r.get(0))?.filter_map(|r| r.ok()).collect();
+                rows
+            };
+            if let Some(bad) = ids.iter().find(|id| !all.contains(id)) {
+                return Err(ApiError::NotFound(format!("Category {} not found", bad)));
+            }
+            all.retain(|id| !ids.contains(id));
+            let tx = conn.unchecked_transaction()?;
+            for (i, id) in ids.iter().chain(all.iter()).enumerate() {
+                tx.execute("UPDATE categories SET sort_order = ?1 WHERE id = ?2", params![i as i64 + 1, id])?;
+            }
+            tx.commit()?;
+            Ok(json!(list_categories(conn, false)?))
+        }),
+
+        // -- products ---------------------------------------------------------
+        ("GET", "/products/search") => with_db(state, |conn| {
+            let q = qs.get("q").cloned().unwrap_or_default();
+            // Menu editors pass include_unavailable=1 to find disabled items; rows carry is_available either way.
+            let avail = if qs.get("include_unavailable").map(|v| v == "1").unwrap_or(false) { "" } else { "p.is_available = 1 AND " };
+            // A scanner gun sends the full barcode; an exact hit is the only sensible result.
+            if !q.trim().is_empty() {
+                let hit = conn.query_row(&format!("{} WHERE p.barcode = ?1 AND {}p.deleted_at IS NULL", PRODUCT_SELECT, avail), params![q.trim()], product_row);
+                if let Ok(row) = hit {
+                    return Ok(json!([row]));
+                }
+            }
+            let pat = format!("%{}%", like_escape(&q));
+            let term = q.trim();
+            let term_pat = like_escape(term);
+            let limit: i64 = qs.get("limit").and_then(|v| v.parse().ok()).unwrap_or(20).clamp(1, 100);
+            // Rank: exact item_no, exact name, name prefix, word prefix, then any substring.
+            let mut stmt = conn.prepare(&format!(
+                "{} WHERE {}p.deleted_at IS NULL AND (p.name LIKE ?1 ESCAPE '\\' OR CAST(p.item_no AS TEXT) LIKE ?1 ESCAPE '\\')
+                 ORDER BY CASE
+                   WHEN CAST(p.item_no AS TEXT) = ?2 THEN 0
+                   WHEN p.name LIKE ?4 ESCAPE '\\' THEN 1
+                   WHEN p.name LIKE ?4 || '%' ESCAPE '\\' THEN 2
+                   WHEN p.name LIKE '% ' || ?4 || '%' ESCAPE '\\' THEN 3
+                   ELSE 4 END,
+                 (p.item_no IS NULL), p.item_no, p.name LIMIT ?3",
+                PRODUCT_SELECT, avail
+            ))?;
+            let rows: Vec<Value> = stmt.query_map(params![pat, term, limit, term_pat], product_row)?.filter_map(|r| r.ok()).collect();
+            Ok(json!(rows))
+        }),
+
+        ("GET", "/products") => with_db(state, |conn| {
+            let filter = if qs.get("include_deleted").map(|v| v == "1").unwrap_or(false) { "" } else { "WHERE p.deleted_at IS NULL" };
+            let order = "ORDER BY (p.item_no IS NULL), p.item_no, p.name";
+            // Without page/limit the old bare array is returned, which the menu screens still expect.
+            if !qs.contains_key("page") && !qs.contains_key("limit") {
+                let mut stmt = conn.prepare(&format!("{} {} {}", PRODUCT_SELECT, filter, order))?;
+                let rows: Vec<Value> = stmt.query_map([], product_row)?.filter_map(|r| r.ok()).collect();
+                return Ok(json!(rows));
+            }
+            let page: i64 = qs.get("page").and_then(|v| v.parse().ok()).unwrap_or(1).max(1);
+            let limit: i64 = qs.get("limit").and_then(|v| v.parse().ok()).unwrap_or(50).clamp(1, 500);
+            let total: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM products p {}", filter), [], |r| r.get(0)).unwrap_or(0);
+            let mut stmt = conn.prepare(&format!("{} {} {} LIMIT ?1 OFFSET ?2", PRODUCT_SELECT, filter, order))?;
+            let rows: Vec<Value> = stmt.query_map(params![limit, (page - 1) * limit], product_row)?.filter_map(|r| r.ok()).collect();
+            Ok(json!({ "rows": rows, "total": total }))
+        }),
+
+        ("POST", "/products") => with_db(state, |conn| {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let name = b["name"].as_str().ok_or("name required")?.trim().to_string();
+            let cat = b["category"].as_str().unwrap_or("");
+            let price = price_field(b)?;
+            let tax = tax_rate_field(b)?.unwrap_or(0);
+            let cost = money_field(b, "cost").unwrap_or(0);
+            if cost < 0 { return Err("cost_cents cannot be negative".into()); }
+            let barcode = barcode_field(b);
+            let cat_id = resolve_category_id(conn, cat);
+            let raw_no = b.get("item_no").and_then(|v| v.as_i64());
+
+            if let Some(n) = raw_no {
+                if n >= 1 && n <= 9999 {
+                    conn.execute("INSERT INTO products(item_no, name, category_id, price_cents, is_available, tax_rate_bps, cost_cents, barcode) VALUES(?1,?2,?3,?4,1,?5,?6,?7)", params![n, name, cat_id, price, tax, cost, barcode]).map_err(product_write_err)?;
+                    return inserted_product(conn);
+                }
+            }
+            for _ in 0..3 {
+                let nx = next_item_no(conn)?;
+                match conn.execute("INSERT INTO products(item_no, name, category_id, price_cents, is_available, tax_rate_bps, cost_cents, barcode) VALUES(?1,?2,?3,?4,1,?5,?6,?7)", params![nx, name, cat_id, price, tax, cost, barcode]) {
+                    Ok(_) => return inserted_product(conn),
+                    Err(e) => {
+                        let err = product_write_err(e);
+                        if err == ApiError::Conflict("Item No already in use".to_string()) { continue; }
+                        return Err(err);
+                    }
+                }
+            }
+            Err(ApiError::Conflict("Failed to allocate Item No".to_string()))
+        }),
+
+        ("POST", "/products/bulk-price") => with_db(state, |conn| {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let bps = b["percent_bps"].as_i64().ok_or("percent_bps required")?;
+            if bps < -10_000 { return Err("percent_bps below -10000 would make prices negative".into()); }
+            let category = b["category"].as_str().map(|s| s.trim()).filter(|s| !s.is_empty());
+            let cat_id: Option<i64> = match category {
+                Some(name) => Some(conn.query_row("SELECT id FROM categories WHERE name = ?1", params![name], |r| r.get(0)).map_err(|_| format!("Category '{}' not found", name))?),
+                None => None,
+            };
+            let tx = conn.unchecked_transaction()?;
+            let ids: Vec<(i64, String, i64)> = {
+                let mut stmt = tx.prepare("SELECT id, name, price_cents FROM products WHERE deleted_at IS NULL AND (?1 IS NULL OR category_id = ?1)")?;
+                let rows = stmt.query_map(params![cat_id], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?.filter_map(|r| r.ok()).collect();
+                rows
+            };
+            for (id, name, price) in &ids {
+                let new_price = price + bps_of(*price, bps);
+                // Dropping the transaction leaves every price as it was.
+                if new_price > MAX_PRICE_CENTS { return Err(format!("New price for {} would exceed {}", name, MAX_PRICE_CENTS).into()); }
+                tx.execute("UPDATE products SET price_cents = ?1, updated_at = datetime('now','localtime') WHERE id = ?2", params![new_price, id])?;
+                record_price_change(&tx, *id, *price, new_price)?;
+            }
+            audit(&tx, "product.bulk_price", "product", None, json!({ "percent_bps": bps, "category": category, "updated": ids.len() }))?;
+            tx.commit()?;
+            Ok(json!({ "ok": true, "updated": ids.len() }))
+        }),
+
+        // Each patch runs in its own savepoint: a bad row is reported and skipped, the rest still commit.
+        ("PUT", "/products/batch") => with_db(state, |conn| {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let patches = b.as_array().or_else(|| b["updates"].as_array()).ok_or("updates array required")?;
+            let mut tx = conn.unchecked_transaction()?;
+            let mut results = Vec::new();
+            let mut updated = 0;
+            for p in patches {
+                let id = p["id"].as_i64();
+                let sp = tx.savepoint()?;
+                match id.ok_or_else(|| ApiError::from("id required")).and_then(|id| apply_product_patch(&sp, id, p)) {
+                    Ok(()) => {
+                        sp.commit()?;
+                        updated += 1;
+                        results.push(json!({ "id": id, "ok": true }));
+                    }
+                    Err(e) => results.push(json!({ "id": id, "ok": false, "error": e })),
+                }
+            }
+            if updated > 0 {
+                audit(&tx, "product.batch_update", "product", None, json!({ "updated": updated, "failed": patches.len() - updated }))?;
+            }
+            tx.commit()?;
+            Ok(json!({ "ok": true, "updated": updated, "results": results }))
+        }),
+
+        ("GET", "/products/export") => with_db(state, |conn| {
+            let filter = if qs.get("available_only").map(|v| v == "1").unwrap_or(false) { "WHERE p.deleted_at IS NULL AND p.is_available = 1" } else { "WHERE p.deleted_at IS NULL" };
+            let sql = format!("SELECT p.item_no, p.name, c.name, p.price_cents, p.is_available FROM products p LEFT JOIN categories c ON p.category_id = c.id {} ORDER BY (p.item_no IS NULL), p.item_no, p.name", filter);
+            let mut stmt = conn.prepare(&sql)?;
+            let mut csv = String::from("item_no,name,category,price_cents,is_available\r\n");
+            let rows = stmt.query_map([], |r| Ok((r.get::<_, Option<i64>>(0)?, r.get::<_, String>(1)?, r.get::<_, Option<String>>(2)?, r.get::<_, i64>(3)?, r.get::<_, i64>(4)?)))?;
+            for (item_no, name, cat, price, avail) in rows.filter_map(|r| r.ok()) {
+                let no = item_no.map(|n| n.to_string()).unwrap_or_default();
+                csv.push_str(&format!("{},{},{},{},{}\r\n", no, csv_escape(&name), csv_escape(&cat.unwrap_or_default()), price, avail));
+            }
+            Ok(json!({ "csv": csv }))
+        }),
+
+        // Columns: item_no,name,category,price_cents,is_available. Any bad row rolls back the whole file.
+        ("POST", "/products/import") => with_db(state, |conn| {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let csv = b["csv"].as_str().ok_or("csv required")?;
+            let mut records = parse_csv(csv);
+            if records.first().and_then(|(_, f)| f.first()).map(|f| f.trim().eq_ignore_ascii_case("item_no")).unwrap_or(false) {
+                records.remove(0);
+            }
+
+            let tx = conn.unchecked_transaction()?;
+            let mut inserted = 0;
+            let mut skipped = Vec::new();
+            for (line, f) in &records {
+                let col = |i: usize| f.get(i).map(|v| v.trim()).unwrap_or("");
+                let name = col(1);
+                if name.is_empty() { skipped.push(json!({ "line": line, "reason": "name required" })); continue; }
+                let price = match col(3).parse::<i64>() {
+                    Ok(p) if (0..=MAX_PRICE_CENTS).contains(&p) => p,
+                    _ => { skipped.push(json!({ "line": line, "reason": "bad price" })); continue; }
+                };
+                let avail = match col(4) { "" | "1" | "true" => 1, "0" | "false" => 0, _ => { skipped.push(json!({ "line": line, "reason": "bad is_available" })); continue; } };
+                let item_no = match col(0) {
+                    "" => match next_item_no(&tx) {
+                        Ok(n) => n,
+                        Err(e) => { skipped.push(json!({ "line": line, "reason": e.message() })); continue; }
+                    },
+                    v => match v.parse::<i64>() {
+                        Ok(n) if (1..=9999).contains(&n) => n,
+                        _ => { skipped.push(json!({ "line": line, "reason": "bad item_no" })); continue; }
+                    },
+                };
+                let cat_id = resolve_category_id(&tx, col(2));
+                match tx.execute("INSERT INTO products(item_no, name, category_id, price_cents, is_available) VALUES(?1,?2,?3,?4,?5)", params![item_no, name, cat_id, price, avail]) {
+                    Ok(_) => inserted += 1,
+                    Err(e) => {
+                        let m = e.to_string().to_lowercase();
+                        let reason = if m.contains("unique") && m.contains("item_no") { "duplicate item_no".to_string() } else { e.to_string() };
+                        skipped.push(json!({ "line": line, "reason": reason }));
+                    }
+                }
+            }
+            // Dropping the transaction rolls back every row, so one bad line means nothing was imported.
+            if !skipped.is_empty() {
+                let lines: Vec<String> = skipped.iter().map(|s| format!("line {}: {}", s["line"], s["reason"].as_str().unwrap_or(""))).collect();
+                return Err(format!("Import rolled back, nothing was saved ({})", lines.join("; ")).into());
+            }
+            tx.commit()?;
+            Ok(json!({ "inserted": inserted, "skipped": skipped }))
+        }),
+
+        _ if method == "POST" && base.starts_with("/products/") && base.ends_with("/image") => {
+            let id: i64 = base.trim_start_matches("/products/").trim_end_matches("/image").parse().map_err(|_| "Invalid product id".to_string())?;
+            let b = body.as_ref().ok_or("Missing body")?;
+            let src = PathBuf::from(b["source"].as_str().ok_or("source required")?);
+            let size = fs::metadata(&src).map_err(|_| ApiError::NotFound("Image not found".to_string()))?.len();
+            if size > MAX_IMAGE_BYTES { return Err("Image too large (max 2 MB)".into()); }
+            let bytes = fs::read(&src).map_err(internal("Read image"))?;
+            let ext = image_ext(&bytes).ok_or("Unsupported image type (png, jpg, gif, webp)")?;
+            with_db(state, |conn| {
+                let exists: i64 = conn.query_row("SELECT COUNT(*) FROM products WHERE id = ?1", params![id], |r| r.get(0)).unwrap_or(0);
+                if exists == 0 { return Err(ApiError::NotFound("Product not found".to_string())); }
+                create_dir_all(&state.image_dir).map_err(internal("Image dir"))?;
+                remove_product_images(&state.image_dir, id);
+                let file_name = format!("{}.{}", id, ext);
+                write(state.image_dir.join(&file_name), &bytes).map_err(internal("Write image"))?;
+                let rel = format!("product_images/{}", file_name);
+                conn.execute("UPDATE products SET image_path = ?1, updated_at = datetime('now','localtime') WHERE id = ?2", params![rel, id])?;
+                Ok(json!({ "ok": true, "image_path": rel }))
+            })
+        }
+
+        _ if method == "POST" && base.starts_with("/products/") && base.ends_with("/stock") => {
+            let id: i64 = base.trim_start_matches("/products/").trim_end_matches("/stock").parse().map_err(|_| "Invalid product id".to_string())?;
+            let b = body.as_ref().ok_or("Missing body")?;
+            let delta = b["delta"].as_i64().filter(|d| *d != 0).ok_or("delta must be a non-zero integer")?;
+            let reason = b["reason"].as_str().map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+            with_db(state, |conn| {
+                let current: Option<i64> = conn.query_row("SELECT stock_qty FROM products WHERE id = ?1", params![id], |r| r.get(0)).map_err(|_| ApiError::NotFound("Product not found".to_string()))?;
+                // Adjusting an untracked product starts tracking it from zero.
+                let next = current.unwrap_or(0) + delta;
+                if next < 0 { return Err(format!("Stock cannot go below zero ({} on hand)", current.unwrap_or(0)).into()); }
+                let tx = conn.unchecked_transaction()?;
+                tx.execute("UPDATE products SET stock_qty = ?1, updated_at = datetime('now','localtime') WHERE id = ?2", params![next, id])?;
+                tx.execute("INSERT INTO stock_adjustments(product_id,delta,reason,created_at) VALUES(?1,?2,?3,datetime('now','localtime'))", params![id, delta, reason])?;
+                tx.commit()?;
+                Ok(json!({ "ok": true, "stock_qty": next }))
+            })
+        }
+
+        _ if method == "GET" && base.starts_with("/products/") && base.ends_with("/price-history") => {
+            let id: i64 = base.trim_start_matches("/products/").trim_end_matches("/price-history").parse().map_err(|_| "Invalid product id".to_string())?;
+            with_db(state, |conn| {
+                let mut stmt = conn.prepare("SELECT old_price_cents,new_price_cents,changed_at FROM product_price_history WHERE product_id = ?1 ORDER BY changed_at, id")?;
+                let rows: Vec<Value> = stmt.query_map(params![id], |r| Ok(json!({ "old_price_cents": r.get::<_, i64>(0)?, "new_price_cents": r.get::<_, i64>(1)?, "changed_at": r.get::<_, String>(2)? })))?.filter_map(|r| r.ok()).collect();
+                Ok(json!({ "rows": rows }))
+            })
+        }
+
+        _ if method == "POST" && base.starts_with("/products/") && base.ends_with("/restore") => {
+            let id: i64 = base.trim_start_matches("/products/").trim_end_matches("/restore").parse().map_err(|_| "Invalid product id".to_string())?;
+            with_db(state, |conn| {
+                let changed = conn.execute("UPDATE products SET deleted_at = NULL, updated_at = datetime('now','localtime') WHERE id = ?1 AND deleted_at IS NOT NULL", params![id])?;
+                if changed == 0 { return Err(ApiError::Conflict("Product is not deleted".to_string())); }
+                audit(conn, "product.restore", "product", Some(id), json!({}))?;
+                Ok(json!({ "ok": true }))
+            })
+        }
+
+        _ if method == "PUT" && base.ends_with("/availability") => {
+            let id_str = base.trim_start_matches("/products/").trim_end_matches("/availability");
+            let id: i64 = id_str.parse().map_err(|_| "Invalid product id".to_string())?;
+            let b = body.as_ref().ok_or("Missing body")?;
+            let avail = b["is_available"].as_i64().unwrap_or(1);
+            with_db(state, |conn| {
+                conn.execute("UPDATE products SET is_available = ?1 WHERE id = ?2", params![avail, id])?;
+                Ok(json!({ "ok": true }))
+            })
+        }
+
+        _ if method == "PUT" && base.starts_with("/products/") => {
+            let id: i64 = base.trim_start_matches("/products/").parse().map_err(|_| "Invalid id".to_string())?;
+            let b = body.as_ref().ok_or("Missing body")?;
+            let name = b["name"].as_str().ok_or("name required")?.trim().to_string();
+            let cat = b["category"].as_str().unwrap_or("");
+            let price = price_field(b)?;
+            let tax = tax_rate_field(b)?;
+            let threshold = match b.get("low_stock_threshold") {
+                None | Some(Value::Null) => None,
+                Some(v) => Some(v.as_i64().filter(|n| *n >= 0).ok_or("low_stock_threshold must be a non-negative integer")?),
+            };
+            let cost = money_field(b, "cost");
+            if cost.map(|c| c < 0).unwrap_or(false) { return Err("cost_cents cannot be negative".into()); }
+            // Only touch the barcode when the key is sent; null or "" clears it.
+            let set_barcode = b.get("barcode").is_some();
+            let barcode = barcode_field(b);
+            let raw_no = b.get("item_no").and_then(|v| v.as_i64());
+            let item_no = raw_no.and_then(|n| if n >= 1 && n <= 9999 { Some(n) } else { None });
+            with_db(state, |conn| {
+                // A deleted product has to be restored before it can be edited.
+                let old_price: i64 = conn.query_row("SELECT price_cents FROM products WHERE id = ?1 AND deleted_at IS NULL", params![id], |r| r.get(0))
+                    .map_err(|_| ApiError::NotFound("Product not found".to_string()))?;
+                let cat_id = resolve_category_id(conn, cat);
+                let tx = conn.unchecked_transaction()?;
+                tx.execute("UPDATE products SET item_no=?1, name=?2, category_id=?3, price_cents=?4, tax_rate_bps=COALESCE(?6, tax_rate_bps), low_stock_threshold=COALESCE(?7, low_stock_threshold), cost_cents=COALESCE(?8, cost_cents), barcode=CASE WHEN ?9 THEN ?10 ELSE barcode END, updated_at=datetime('now','localtime') WHERE id=?5", params![item_no, name, cat_id, price, id, tax, threshold, cost, set_barcode, barcode]).map_err(product_write_err)?;
+                record_price_change(&tx, id, old_price, price)?;
+                tx.commit()?;
+                Ok(json!({ "ok": true }))
+            })
+        }
+
+        // Deleting hides the product and can be undone via /restore; `?permanent=1` removes it for good.
+        _ if method == "DELETE" && base.starts_with("/products/") => {
+            let id: i64 = base.trim_start_matches("/products/").parse().map_err(|_| "Invalid id".to_string())?;
+            let permanent = qs.get("permanent").map(|v| v == "1").unwrap_or(false);
+            with_db(state, |conn| {
+                let name: Option<String> = conn.query_row("SELECT name FROM products WHERE id = ?1", params![id], |r| r.get(0)).ok();
+                if !permanent {
+                    let changed = conn.execute("UPDATE products SET deleted_at = datetime('now','localtime') WHERE id = ?1 AND deleted_at IS NULL", params![id])?;
+                    if changed == 0 { return Err(ApiError::NotFound("Product not found".to_string())); }
+                    audit(conn, "product.delete", "product", Some(id), json!({ "name": name }))?;
+                    return Ok(json!({ "ok": true, "deleted": true }));
+                }
                 match conn.execute("DELETE FROM products WHERE id = ?1", params![id]) {
-                    Ok(_) => Ok(json!({ "ok": true })),
+                    Ok(_) => {
+                        remove_product_images(&state.image_dir, id);
+                        audit(conn, "product.purge", "product", Some(id), json!({ "name": name }))?;
+                        Ok(json!({ "ok": true }))
+                    }
                     Err(e) => {
                         let m = e.to_string().to_lowercase();
                         if m.contains("foreign key") || m.contains("constraint") {
-                            conn.execute("UPDATE products SET is_available = 0 WHERE id = ?1", params![id]).map_err(|e| e.to_string())?;
+                            conn.execute("UPDATE products SET is_available = 0 WHERE id = ?1", params![id])?;
+                            audit(conn, "product.disable", "product", Some(id), json!({ "name": name }))?;
                             Ok(json!({ "ok": true, "disabled": true }))
-                        } else { Err(e.to_string()) }
+                        } else { Err(e.into()) }
                     }
                 }
             })
@@ -541,93 +2701,307 @@ fn api_call(
         ("POST", "/bills") => {
             let b = body.as_ref().ok_or("Missing body")?;
             let raw = b["items"].as_array().ok_or("items required")?;
-            if raw.is_empty() { return Err("No items".to_string()); }
+            if raw.is_empty() { return Err("No items".into()); }
 
-            struct It { pid: i64, pname: String, unit: i64, qty: i64, lt: i64 }
+            struct It { pid: i64, pname: String, unit: i64, qty: i64, disc: i64, lt: i64, comp: bool, comp_reason: Option<String> }
             let items: Vec<It> = raw.iter().filter_map(|it| {
                 let pid = it["product_id"].as_i64().unwrap_or(0);
                 let pn = it["product_name"].as_str().unwrap_or("").trim().to_string();
                 let u = it["unit_price_cents"].as_i64().unwrap_or(0).max(0);
                 let q = it["qty"].as_i64().unwrap_or(0).max(1).min(1000);
-                if pid > 0 && !pn.is_empty() { Some(It { pid, pname: pn, unit: u, qty: q, lt: q * u }) } else { None }
+                let comp = it["comp"].as_bool().unwrap_or(false);
+                let comp_reason = it["comp_reason"].as_str().map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+                // Line discounts come off the line itself, so the bill subtotal is already net of them.
+                let disc = if comp { 0 } else { it["discount_cents"].as_i64().unwrap_or(0).clamp(0, q * u) };
+                let lt = if comp { 0 } else { q * u - disc };
+                if pid > 0 && !pn.is_empty() { Some(It { pid, pname: pn, unit: u, qty: q, disc, lt, comp, comp_reason }) } else { None }
             }).collect();
-            if items.is_empty() { return Err("No valid items".to_string()); }
-
-            let subtotal: i64 = items.iter().map(|i| i.lt).sum();
-            let dr = b["discount_rate_bps"].as_i64().unwrap_or(0);
-            let dc = ((subtotal as f64 * dr as f64) / 10_000.0).round() as i64;
-            let total = subtotal - dc;
-            let payment_mode_raw = b["payment_mode"].as_str().unwrap_or("cash").to_lowercase();
-            let payment_mode = match payment_mode_raw.as_str() {
-                "cash" | "online" | "split" => payment_mode_raw,
-                _ => "cash".to_string(),
-            };
-            let split_cash_raw = b["split_cash_cents"].as_i64().unwrap_or(0);
-            let split_online_raw = b["split_online_cents"].as_i64().unwrap_or(0);
-            let mut split_cash_cents = split_cash_raw.max(0);
-            let mut split_online_cents = split_online_raw.max(0);
-
-            if payment_mode == "split" {
-                if split_cash_cents + split_online_cents != total {
-                    return Err("Split amounts must match total".to_string());
+            if items.is_empty() { return Err("No valid items".into()); }
+
+            // payment_method is the older name for the same field.
+            let requested_mode = b["payment_mode"].as_str().or_else(|| b["payment_method"].as_str()).unwrap_or("cash").trim().to_lowercase();
+            if !PAYMENT_MODES.contains(&requested_mode.as_str()) {
+                return Err(format!("Invalid payment_mode '{}': expected {}", requested_mode, PAYMENT_MODES.join(", ")).into());
+            }
+            let mut payments: Vec<(String, i64)> = Vec::new();
+            if let Some(arr) = b["payments"].as_array() {
+                for p in arr {
+                    let m = p["method"].as_str().map(|s| s.trim().to_lowercase()).unwrap_or_default();
+                    if !matches!(m.as_str(), "cash" | "card" | "upi" | "other") {
+                        return Err(format!("Invalid payment method '{}': expected cash, card, upi or other", m).into());
+                    }
+                    let amount = p["amount_cents"].as_i64().filter(|a| *a > 0).ok_or("Each payment needs a positive amount_cents")?;
+                    payments.push((m, amount));
                 }
-            } else if payment_mode == "cash" {
-                split_cash_cents = total;
-                split_online_cents = 0;
-            } else if payment_mode == "online" {
-                split_cash_cents = 0;
-                split_online_cents = total;
-            }
-
-            with_db(state.inner(), |conn| {
-                let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
-                tx.execute("INSERT OR IGNORE INTO settings(key,value) VALUES('bill_seq','0')", []).map_err(|e| e.to_string())?;
-                tx.execute("UPDATE settings SET value = CAST(value AS INTEGER) + 1 WHERE key = 'bill_seq'", []).map_err(|e| e.to_string())?;
-                let seq: i64 = tx.query_row("SELECT value FROM settings WHERE key = 'bill_seq'", [], |r| r.get::<_, String>(0).map(|v| v.parse::<i64>().unwrap_or(1))).unwrap_or(1);
-                let bill_no = format!("MNE-{:06}", seq);
-                tx.execute("INSERT INTO bills(bill_no,subtotal_cents,discount_rate_bps,discount_cents,payment_mode,split_cash_cents,split_online_cents,total_cents,created_at) VALUES(?1,?2,?3,?4,?5,?6,?7,?8,datetime('now','localtime'))", params![bill_no, subtotal, dr, dc, payment_mode, split_cash_cents, split_online_cents, total]).map_err(|e| e.to_string())?;
-                let bill_id = tx.last_insert_rowid();
+            }
+            let tendered = b["amount_tendered_cents"].as_i64();
+            let training = b["is_training"].as_bool().unwrap_or(false);
+            let cashier = b["cashier"].as_str().map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+            // Takeaway bills simply have no table.
+            let table_no = b["table_no"].as_str().map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+            let customer_id = b["customer_id"].as_i64();
+            let held_order_id = b["held_order_id"].as_i64();
+
+            with_db(state, |conn| {
+                if let Some(cid) = customer_id {
+                    let found: i64 = conn.query_row("SELECT COUNT(*) FROM customers WHERE id = ?1", params![cid], |r| r.get(0)).unwrap_or(0);
+                    if found == 0 { return Err(ApiError::NotFound("Customer not found".to_string())); }
+                }
+                // A stale cart or held order may still carry a product deleted since.
                 for it in &items {
-                    tx.execute("INSERT INTO bill_items(bill_id,product_id,product_name,unit_price_cents,qty,line_total_cents) VALUES(?1,?2,?3,?4,?5,?6)", params![bill_id, it.pid, it.pname, it.unit, it.qty, it.lt]).map_err(|e| e.to_string())?;
+                    let deleted: bool = conn.query_row("SELECT deleted_at IS NOT NULL FROM products WHERE id = ?1", params![it.pid], |r| r.get(0)).unwrap_or(false);
+                    if deleted { return Err(format!("{} has been deleted and can no longer be sold", it.pname).into()); }
+                }
+                // Settling a held order carries its open time onto the bill for the turnaround report.
+                let opened_at: Option<String> = match held_order_id {
+                    Some(hid) => Some(conn.query_row("SELECT created_at FROM held_orders WHERE id = ?1", params![hid], |r| r.get(0))
+                        .map_err(|_| ApiError::NotFound("Held order not found".to_string()))?),
+                    None => None,
+                };
+                let subtotal: i64 = items.iter().map(|i| i.lt).sum();
+                let dr = b["discount_rate_bps"].as_i64().unwrap_or(0);
+                let max_dr: i64 = get_setting(conn, "max_discount_bps", "5000").parse().unwrap_or(5000);
+                if dr < 0 || dr > max_dr {
+                    return Err(format!("Discount must be between 0% and {:.2}%", max_dr as f64 / 100.0).into());
+                }
+                // Rates come from the product, never the client. The order of discount and tax
+                // follows the discount_before_tax setting and is stored on the bill.
+                // Unit cost is copied onto the line so later cost edits don't rewrite past margins.
+                let rate_cost: Vec<(i64, i64)> = items.iter().map(|i| {
+                    conn.query_row("SELECT tax_rate_bps, cost_cents FROM products WHERE id=?1", params![i.pid], |r| Ok((r.get(0)?, r.get(1)?))).unwrap_or((0, 0))
+                }).collect();
+                let discount_before_tax = get_setting(conn, "discount_before_tax", "1") == "1";
+                let lines: Vec<(i64, i64)> = items.iter().zip(&rate_cost).map(|(i, rc)| (i.lt, rc.0)).collect();
+                let (dc, line_tax) = discount_and_tax(&lines, dr, discount_before_tax);
+                let line_meta: Vec<(i64, i64, i64)> = rate_cost.iter().zip(line_tax).map(|(&(rate, cost), tax)| (rate, tax, cost)).collect();
+                let total_tax: i64 = line_meta.iter().map(|t| t.1).sum();
+                // Tips sit outside the discountable subtotal.
+                let tip = b["tip_cents"].as_i64().unwrap_or(0);
+                if tip < 0 { return Err("Tip cannot be negative".into()); }
+                let due = subtotal - dc + total_tax + tip;
+                // An itemised payments list must cover the bill before any surcharge.
+                let paid: i64 = payments.iter().map(|p| p.1).sum();
+                if !payments.is_empty() && paid != due {
+                    return Err(format!("Payments total {} does not match bill total {}", cents_to_rs(paid), cents_to_rs(due)).into());
+                }
+                // Card surcharge is charged on the amount going through the card and never discounted;
+                // with itemised payments each card share is raised by its own surcharge.
+                let surcharge_bps = get_setting(conn, "card_surcharge_bps", "0").parse::<i64>().unwrap_or(0).clamp(0, 1_000);
+                let payments: Vec<(String, i64)> = payments.iter()
+                    .map(|(m, a)| (m.clone(), if m == "card" { a + bps_of(*a, surcharge_bps) } else { *a }))
+                    .collect();
+                let surcharge = if !payments.is_empty() {
+                    payments.iter().map(|p| p.1).sum::<i64>() - paid
+                } else if requested_mode == "card" {
+                    bps_of(due, surcharge_bps)
+                } else { 0 };
+                let total = due + surcharge;
+                let mut payment_mode = requested_mode.clone();
+                let mut split_cash_raw = b["split_cash_cents"].as_i64().unwrap_or(0);
+                let mut split_online_raw = b["split_online_cents"].as_i64().unwrap_or(0);
+                // An itemised payments list supersedes payment_mode; the cash/online columns are
+                // derived from it so existing reports keep adding up.
+                if !payments.is_empty() {
+                    split_cash_raw = payments.iter().filter(|p| p.0 == "cash").map(|p| p.1).sum();
+                    split_online_raw = total - split_cash_raw;
+                    payment_mode = match payments.first() {
+                        Some((m, _)) if payments.iter().all(|p| p.0 == *m) => m.clone(),
+                        _ => "split".to_string(),
+                    };
+                }
+                let mut split_cash_cents = split_cash_raw.max(0);
+                let mut split_online_cents = split_online_raw.max(0);
+
+                if payment_mode == "split" {
+                    if split_cash_cents + split_online_cents != total {
+                        return Err("Split amounts must match total".into());
+                    }
+                } else if payment_mode == "cash" {
+                    split_cash_cents = total;
+                    split_online_cents = 0;
+                } else {
+                    // card, upi, other and online all settle outside the till.
+                    split_cash_cents = 0;
+                    split_online_cents = total;
+                }
+
+                let change = match (payment_mode.as_str(), tendered) {
+                    ("cash", Some(t)) => {
+                        if t < total { return Err("Amount tendered is less than the bill total".into()); }
+                        Some(t - total)
+                    }
+                    _ => None,
+                };
+
+                if get_setting(conn, "require_discount_reason", "0") == "1" && items.iter().any(|i| i.comp && i.comp_reason.is_none()) {
+                    return Err("Comp reason required".into());
+                }
+                let tx = conn.unchecked_transaction()?;
+                // Training bills run off their own counter so real invoice numbers stay contiguous.
+                let (seq_key, prefix) = if training { ("training_seq", "TRN".to_string()) } else { ("bill_seq", get_setting(&tx, "bill_prefix", "MNE")) };
+                tx.execute("INSERT OR IGNORE INTO settings(key,value) VALUES(?1,'0')", params![seq_key])?;
+                let today: String = tx.query_row("SELECT strftime('%Y%m%d','now','localtime')", [], |r| r.get(0))?;
+                let daily = get_setting(&tx, "bill_seq_reset", "never") == "daily";
+                if daily {
+                    // The counter restarts at 1 on the first bill of each day; the date segment keeps numbers unique.
+                    let date_key = format!("{}_date", seq_key);
+                    if get_setting(&tx, &date_key, "") != today {
+                        tx.execute("UPDATE settings SET value = '0' WHERE key = ?1", params![seq_key])?;
+                        tx.execute("INSERT INTO settings(key,value) VALUES(?1,?2) ON CONFLICT(key) DO UPDATE SET value=excluded.value", params![date_key, today])?;
+                    }
                 }
-                tx.commit().map_err(|e| e.to_string())?;
-                Ok(json!({ "bill_no": bill_no }))
+                tx.execute("UPDATE settings SET value = CAST(value AS INTEGER) + 1 WHERE key = ?1", params![seq_key])?;
+                let seq: i64 = tx.query_row("SELECT value FROM settings WHERE key = ?1", params![seq_key], |r| r.get::<_, String>(0).map(|v| v.parse::<i64>().unwrap_or(1))).unwrap_or(1);
+                let pad: usize = get_setting(&tx, "bill_pad_width", "6").parse().unwrap_or(6);
+                let date = (daily || get_setting(&tx, "bill_no_date", "0") == "1").then_some(today.as_str());
+                let bill_no = format_bill_no(&prefix, date, seq, pad);
+                tx.execute("INSERT INTO bills(bill_no,subtotal_cents,discount_rate_bps,discount_cents,payment_mode,split_cash_cents,split_online_cents,tip_cents,surcharge_cents,total_cents,amount_tendered_cents,change_cents,is_training,cashier,table_no,total_tax_cents,customer_id,discount_before_tax,opened_at,created_at) VALUES(?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17,?18,?19,datetime('now','localtime'))", params![bill_no, subtotal, dr, dc, payment_mode, split_cash_cents, split_online_cents, tip, surcharge, total, tendered, change, training as i64, cashier, table_no, total_tax, customer_id, discount_before_tax as i64, opened_at])?;
+                let bill_id = tx.last_insert_rowid();
+                if let Some(hid) = held_order_id {
+                    tx.execute("DELETE FROM held_orders WHERE id = ?1", params![hid])?;
+                }
+                for (m, amount) in &payments {
+                    tx.execute("INSERT INTO bill_payments(bill_id,method,amount_cents) VALUES(?1,?2,?3)", params![bill_id, m, amount])?;
+                }
+                for (it, (rate, tax, cost)) in items.iter().zip(&line_meta) {
+                    tx.execute("INSERT INTO bill_items(bill_id,product_id,product_name,unit_price_cents,qty,discount_cents,line_total_cents,is_comp,comp_reason,tax_rate_bps,tax_cents,cost_cents) VALUES(?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12)", params![bill_id, it.pid, it.pname, it.unit, it.qty, it.disc, it.lt, it.comp as i64, it.comp_reason, rate, tax, cost])?;
+                }
+                // NULL stock means untracked. Training bills never touch real stock. A product can sit
+                // on several lines (a comp plus a paid one), so stock is checked against the total.
+                if !training {
+                    let mut wanted: std::collections::BTreeMap<i64, (i64, &str)> = std::collections::BTreeMap::new();
+                    for it in &items {
+                        wanted.entry(it.pid).or_insert((0, it.pname.as_str())).0 += it.qty;
+                    }
+                    for (pid, (qty, name)) in &wanted {
+                        let stock: Option<i64> = tx.query_row("SELECT stock_qty FROM products WHERE id = ?1", params![pid], |r| r.get(0)).unwrap_or(None);
+                        if let Some(n) = stock.filter(|n| n < qty) {
+                            // Dropping the transaction rolls back the bill and every decrement so far.
+                            return Err(format!("Insufficient stock for {} ({} available, {} ordered)", name, n, qty).into());
+                        }
+                        tx.execute("UPDATE products SET stock_qty = stock_qty - ?1 WHERE id = ?2 AND stock_qty IS NOT NULL", params![qty, pid])?;
+                    }
+                }
+                tx.commit()?;
+                Ok(json!({ "bill_no": bill_no, "total_cents": total, "total_tax_cents": total_tax, "surcharge_cents": surcharge, "change_cents": change }))
             })
         }
 
-        ("GET", "/bills") => with_db(state.inner(), |conn| {
-            let page: i64 = qs.get("page").and_then(|v| v.parse().ok()).unwrap_or(1).max(1);
-            let limit: i64 = qs.get("limit").and_then(|v| v.parse().ok()).unwrap_or(10).max(1).min(100);
-            let bnq = qs.get("bill_no").cloned().unwrap_or_default();
-            let start = qs.get("start").and_then(|v| to_date_only(v));
-            let end = qs.get("end").and_then(|v| to_date_only(v));
-
-            let mut wc = Vec::new();
-            let mut bv: Vec<String> = Vec::new();
-            if !bnq.is_empty() { wc.push("bill_no LIKE ?".to_string()); bv.push(format!("%{}%", bnq)); }
-            if let Some(s) = &start { wc.push("created_at >= ?".to_string()); bv.push(format!("{} 00:00:00", s)); }
-            if let Some(e) = &end { wc.push("created_at <= ?".to_string()); bv.push(format!("{} 23:59:59", e)); }
-
-            let wsql = if wc.is_empty() { String::new() } else { format!("WHERE {}", wc.join(" AND ")) };
-
-            let csql = format!("SELECT COUNT(*) FROM bills {}", wsql);
-            let mut cs = conn.prepare(&csql).map_err(|e| e.to_string())?;
-            let cparams: Vec<&dyn rusqlite::types::ToSql> = bv.iter().map(|v| v as &dyn rusqlite::types::ToSql).collect();
-            let total: i64 = cs.query_row(cparams.as_slice(), |r| r.get(0)).unwrap_or(0);
-
-            let dsql = format!("SELECT id,bill_no,subtotal_cents,discount_rate_bps,discount_cents,payment_mode,split_cash_cents,split_online_cents,total_cents,created_at FROM bills {} ORDER BY created_at DESC LIMIT ? OFFSET ?", wsql);
-            let mut ds = conn.prepare(&dsql).map_err(|e| e.to_string())?;
-            let offset = (page - 1) * limit;
-            let mut ap: Vec<Box<dyn rusqlite::types::ToSql>> = bv.iter().map(|v| Box::new(v.clone()) as Box<dyn rusqlite::types::ToSql>).collect();
-            ap.push(Box::new(limit));
-            ap.push(Box::new(offset));
-            let pr: Vec<&dyn rusqlite::types::ToSql> = ap.iter().map(|v| v.as_ref()).collect();
-
-            let rows: Vec<Value> = ds.query_map(pr.as_slice(), |r| Ok(json!({ "id": r.get::<_, i64>(0)?, "bill_no": r.get::<_, String>(1)?, "subtotal_cents": r.get::<_, i64>(2)?, "discount_rate_bps": r.get::<_, i64>(3)?, "discount_cents": r.get::<_, i64>(4)?, "payment_mode": r.get::<_, String>(5)?, "split_cash_cents": r.get::<_, i64>(6)?, "split_online_cents": r.get::<_, i64>(7)?, "total_cents": r.get::<_, i64>(8)?, "created_at": r.get::<_, String>(9)? }))).map_err(|e| e.to_string())?.filter_map(|r| r.ok()).collect();
-            Ok(json!({ "rows": rows, "total": total }))
+        ("GET", "/bills") => with_db(state, |conn| list_bills(conn, &qs)),
+
+        // Ledger export: fixed column order, amounts in rupees, voided and training bills left out.
+        ("GET", "/bills/export") => with_db(state, |conn| {
+            validate_range_params(&qs)?;
+            let (start_ts, end_ts) = report_range(conn, &qs);
+            let mut stmt = conn.prepare(&format!(
+                "SELECT bill_no, created_at, subtotal_cents, discount_cents, total_tax_cents, tip_cents, surcharge_cents, total_cents, payment_mode
+                 FROM bills
+                 WHERE created_at >= ?1 AND created_at <= ?2 AND voided_at IS NULL{}
+                 ORDER BY created_at, id",
+                training_clause(&qs, "is_training")
+            ))?;
+            let mut csv = String::from("bill_no,created_at,subtotal,discount,tax,tip,surcharge,total,payment_mode\r\n");
+            let rows = stmt.query_map(params![start_ts, end_ts], |r| Ok((
+                r.get::<_, String>(0)?, r.get::<_, String>(1)?,
+                [r.get::<_, i64>(2)?, r.get::<_, i64>(3)?, r.get::<_, i64>(4)?, r.get::<_, i64>(5)?, r.get::<_, i64>(6)?, r.get::<_, i64>(7)?],
+                r.get::<_, String>(8)?,
+            )))?;
+            for (bill_no, created_at, amounts, mode) in rows.filter_map(|r| r.ok()) {
+                let money: Vec<String> = amounts.iter().map(|c| cents_to_rs(*c)).collect();
+                csv.push_str(&format!("{},{},{},{}\r\n", csv_escape(&bill_no), csv_escape(&created_at), money.join(","), csv_escape(&mode)));
+            }
+            Ok(json!({ "csv": csv }))
+        }),
+
+        // One row per sold line, for tax audits. Same range and exclusions as /bills/export.
+        ("GET", "/bills/export-items") => with_db(state, |conn| {
+            validate_range_params(&qs)?;
+            let (start_ts, end_ts) = report_range(conn, &qs);
+            let mut stmt = conn.prepare(&format!(
+                "SELECT b.bill_no, b.created_at, i.product_name, i.qty, i.unit_price_cents, i.discount_cents, i.tax_rate_bps, i.tax_cents, i.line_total_cents
+                 FROM bill_items i JOIN bills b ON b.id = i.bill_id
+                 WHERE b.created_at >= ?1 AND b.created_at <= ?2 AND b.voided_at IS NULL{}
+                 ORDER BY b.created_at, b.id, i.id",
+                training_clause(&qs, "b.is_training")
+            ))?;
+            let mut csv = String::from("bill_no,date,product_name,qty,unit_price,discount,tax_rate,tax,line_total\r\n");
+            let rows = stmt.query_map(params![start_ts, end_ts], |r| Ok((
+                r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?, r.get::<_, i64>(3)?,
+                r.get::<_, i64>(4)?, r.get::<_, i64>(5)?, r.get::<_, i64>(6)?, r.get::<_, i64>(7)?, r.get::<_, i64>(8)?,
+            )))?;
+            for (bill_no, created_at, name, qty, unit, disc, rate, tax, lt) in rows.filter_map(|r| r.ok()) {
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{},{:.2},{},{}\r\n",
+                    csv_escape(&bill_no), csv_escape(&created_at), csv_escape(&name), qty,
+                    cents_to_rs(unit), cents_to_rs(disc), rate as f64 / 100.0, cents_to_rs(tax), cents_to_rs(lt)
+                ));
+            }
+            Ok(json!({ "csv": csv }))
+        }),
+
+        // For a sale rung up by mistake: voids the newest bill and puts its stock back. The bill
+        // stays on file so invoice numbers remain contiguous for the auditors.
+        ("POST", "/bills/undo-last") => with_db(state, |conn| {
+            let window: f64 = get_setting(conn, "undo_window_minutes", "5").parse().unwrap_or(5.0);
+            let (id, bill_no, training, voided, age_min): (i64, String, bool, Option<String>, f64) = conn
+                .query_row(
+                    "SELECT id, bill_no, is_training, voided_at, (julianday('now','localtime') - julianday(created_at)) * 1440.0 FROM bills ORDER BY id DESC LIMIT 1",
+                    [],
+                    |r| Ok((r.get(0)?, r.get(1)?, r.get::<_, i64>(2)? != 0, r.get(3)?, r.get(4)?)),
+                )
+                .map_err(|_| ApiError::NotFound("No bills to undo".to_string()))?;
+            if voided.is_some() { return Err(ApiError::Conflict(format!("Bill {} is already voided", bill_no))); }
+            if age_min > window {
+                return Err(format!("Bill {} is older than {} minutes and can no longer be undone", bill_no, window).into());
+            }
+            let tx = conn.unchecked_transaction()?;
+            // Training bills never took stock, so there is nothing to give back.
+            if !training {
+                tx.execute(
+                    "UPDATE products SET stock_qty = stock_qty + (SELECT COALESCE(SUM(qty), 0) FROM bill_items WHERE bill_id = ?1 AND product_id = products.id)
+                     WHERE stock_qty IS NOT NULL AND id IN (SELECT product_id FROM bill_items WHERE bill_id = ?1)",
+                    params![id],
+                )?;
+            }
+            tx.execute("UPDATE bills SET voided_at = datetime('now','localtime'), void_reason = 'Undone' WHERE id = ?1", params![id])?;
+            audit(&tx, "bill.undo", "bill", Some(id), json!({ "bill_no": bill_no }))?;
+            tx.commit()?;
+            Ok(json!({ "ok": true, "bill_no": bill_no }))
         }),
 
-        ("GET", "/analytics/payments") => with_db(state.inner(), |conn| {
+        // Cursor feed for the customer display: poll with the last id seen.
+        ("GET", "/bills/since") => with_db(state, |conn| {
+            let since: i64 = qs.get("id").and_then(|v| v.parse().ok()).unwrap_or(0).max(0);
+            let limit: i64 = qs.get("limit").and_then(|v| v.parse().ok()).unwrap_or(50).clamp(1, 200);
+            // Training bills never reach the customer display; voided ones carry voided_at so it can hide them.
+            let mut bs = conn.prepare("SELECT id,bill_no,subtotal_cents,discount_cents,tip_cents,total_cents,payment_mode,created_at,voided_at FROM bills WHERE id > ?1 AND is_training = 0 ORDER BY id LIMIT ?2")?;
+            let mut is = conn.prepare("SELECT product_id,product_name,unit_price_cents,qty,line_total_cents FROM bill_items WHERE bill_id=?1")?;
+            let mut bills = Vec::new();
+            let mut max_id = since;
+            let headers: Vec<(i64, Value)> = bs.query_map(params![since, limit], |r| Ok((r.get::<_, i64>(0)?, json!({ "id": r.get::<_, i64>(0)?, "bill_no": r.get::<_, String>(1)?, "subtotal_cents": r.get::<_, i64>(2)?, "discount_cents": r.get::<_, i64>(3)?, "tip_cents": r.get::<_, i64>(4)?, "total_cents": r.get::<_, i64>(5)?, "payment_mode": r.get::<_, String>(6)?, "created_at": r.get::<_, String>(7)?, "voided_at": r.get::<_, Option<String>>(8)? }))))?.filter_map(|r| r.ok()).collect();
+            for (id, mut bill) in headers {
+                let items: Vec<Value> = is.query_map(params![id], |r| Ok(json!({ "product_id": r.get::<_, i64>(0)?, "product_name": r.get::<_, String>(1)?, "unit_price_cents": r.get::<_, i64>(2)?, "qty": r.get::<_, i64>(3)?, "line_total_cents": r.get::<_, i64>(4)? })))?.filter_map(|r| r.ok()).collect();
+                bill["items"] = json!(items);
+                bills.push(bill);
+                max_id = max_id.max(id);
+            }
+            Ok(json!({ "bills": bills, "max_id": max_id }))
+        }),
+
+        _ if method == "POST" && base.starts_with("/bills/") && base.ends_with("/void") => {
+            let id: i64 = base.trim_start_matches("/bills/").trim_end_matches("/void").parse().map_err(|_| "Invalid id".to_string())?;
+            let reason = body.as_ref().and_then(|b| b["reason"].as_str()).map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+            with_db(state, |conn| {
+                check_bill_age(conn, id)?;
+                let changed = conn.execute("UPDATE bills SET voided_at = datetime('now','localtime'), void_reason = ?1 WHERE id = ?2 AND voided_at IS NULL", params![reason, id])?;
+                if changed == 0 { return Err(ApiError::Conflict("Bill already voided".to_string())); }
+                let (bill_no, total): (String, i64) = conn.query_row("SELECT bill_no, total_cents FROM bills WHERE id = ?1", params![id], |r| Ok((r.get(0)?, r.get(1)?)))?;
+                audit(conn, "bill.void", "bill", Some(id), json!({ "bill_no": bill_no, "total_cents": total, "reason": reason }))?;
+                Ok(json!({ "ok": true }))
+            })
+        }
+
+        ("GET", "/analytics/payments") => with_db(state, |conn| {
             let today = conn
                 .query_row("SELECT date('now','localtime')", [], |r| r.get::<_, String>(0))
                 .unwrap_or_else(|_| "1970-01-01".to_string());
@@ -649,7 +3023,7 @@ fn api_call(
             }
 
             if start < min_allowed || end > today {
-                return Err("Date range must be within the last 3 days".to_string());
+                return Err("Date range must be within the last 3 days".into());
             }
 
             let start_ts = format!("{} 00:00:00", start);
@@ -657,15 +3031,15 @@ fn api_call(
 
             let row = conn
                 .query_row(
-                    "SELECT
+                    &format!("SELECT
                         COALESCE(SUM(CASE WHEN payment_mode = 'cash' THEN 1 ELSE 0 END), 0) as cash_bill_count,
-                        COALESCE(SUM(CASE WHEN payment_mode = 'online' THEN 1 ELSE 0 END), 0) as online_bill_count,
+                        COALESCE(SUM(CASE WHEN payment_mode NOT IN ('cash', 'split') THEN 1 ELSE 0 END), 0) as online_bill_count,
                         COALESCE(SUM(CASE WHEN payment_mode = 'split' THEN 1 ELSE 0 END), 0) as split_bill_count,
                         COALESCE(SUM(split_cash_cents), 0) as cash_total_cents,
                         COALESCE(SUM(split_online_cents), 0) as online_total_cents,
                         COALESCE(SUM(CASE WHEN payment_mode = 'split' THEN total_cents ELSE 0 END), 0) as split_total_cents
                      FROM bills
-                     WHERE created_at >= ?1 AND created_at <= ?2",
+                     WHERE created_at >= ?1 AND created_at <= ?2 AND voided_at IS NULL{}", training_clause(&qs, "is_training")),
                     params![start_ts, end_ts],
                     |r| {
                         Ok((
@@ -680,126 +3054,2397 @@ fn api_call(
                 )
                 .unwrap_or((0, 0, 0, 0, 0, 0));
 
-            Ok(json!({
+            let rows = vec![
+                json!({ "mode": "cash", "bill_count": row.0, "total_cents": row.3 }),
+                json!({ "mode": "online", "bill_count": row.1, "total_cents": row.4 }),
+                json!({ "mode": "split", "bill_count": row.2, "total_cents": row.5 }),
+            ];
+            Ok(report_response(&qs, &rows, json!({
                 "cash": { "bill_count": row.0, "total_cents": row.3 },
                 "online": { "bill_count": row.1, "total_cents": row.4 },
                 "split": { "bill_count": row.2, "total_cents": row.5 }
-            }))
+            })))
+        }),
+
+        // -- reports ----------------------------------------------------------
+        ("GET", "/reports/daily") => with_db(state, |conn| {
+            let date = match qs.get("date") {
+                Some(v) => to_date_only(v).ok_or("Invalid date, expected YYYY-MM-DD")?,
+                None => conn
+                    .query_row("SELECT date('now','localtime')", [], |r| r.get::<_, String>(0))
+                    .unwrap_or_else(|_| "1970-01-01".to_string()),
+            };
+            let start_ts = format!("{} 00:00:00", date);
+            let end_ts = format!("{} 23:59:59", date);
+            let mut stmt = conn.prepare(&format!(
+                "SELECT payment_mode, COUNT(*), COALESCE(SUM(subtotal_cents), 0), COALESCE(SUM(discount_cents), 0), COALESCE(SUM(total_cents), 0)
+                 FROM bills
+                 WHERE created_at >= ?1 AND created_at <= ?2 AND voided_at IS NULL{}
+                 GROUP BY payment_mode ORDER BY payment_mode",
+                training_clause(&qs, "is_training")
+            ))?;
+            let rows: Vec<Value> = stmt.query_map(params![start_ts, end_ts], |r| Ok(json!({ "payment_mode": r.get::<_, String>(0)?, "bill_count": r.get::<_, i64>(1)?, "subtotal_cents": r.get::<_, i64>(2)?, "discount_cents": r.get::<_, i64>(3)?, "total_cents": r.get::<_, i64>(4)? })))?.filter_map(|r| r.ok()).collect();
+            let sum = |k: &str| rows.iter().map(|r| r[k].as_i64().unwrap_or(0)).sum::<i64>();
+            let mut cs = conn.prepare(&format!(
+                "SELECT cashier, COUNT(*), COALESCE(SUM(total_cents), 0)
+                 FROM bills
+                 WHERE created_at >= ?1 AND created_at <= ?2 AND voided_at IS NULL{}
+                 GROUP BY cashier ORDER BY cashier",
+                training_clause(&qs, "is_training")
+            ))?;
+            let by_cashier: Vec<Value> = cs.query_map(params![start_ts, end_ts], |r| Ok(json!({ "cashier": r.get::<_, Option<String>>(0)?, "bill_count": r.get::<_, i64>(1)?, "total_cents": r.get::<_, i64>(2)? })))?.filter_map(|r| r.ok()).collect();
+            Ok(report_response(&qs, &rows, json!({
+                "date": date,
+                "bill_count": sum("bill_count"),
+                "subtotal_cents": sum("subtotal_cents"),
+                "discount_cents": sum("discount_cents"),
+                "total_cents": sum("total_cents"),
+                "by_payment_mode": rows,
+                "by_cashier": by_cashier
+            })))
+        }),
+
+        // One entry per calendar day in the range, zero-filled, for the sales chart.
+        ("GET", "/reports/revenue-series") => with_db(state, |conn| {
+            validate_range_params(&qs)?;
+            let (start_ts, end_ts) = report_range(conn, &qs);
+            let days: i64 = conn.query_row("SELECT CAST(julianday(date(?2)) - julianday(date(?1)) AS INTEGER)", params![start_ts, end_ts], |r| r.get(0)).unwrap_or(0);
+            if days > 366 { return Err("Range too long, at most 366 days".into()); }
+            let mut stmt = conn.prepare(&format!(
+                "WITH RECURSIVE days(d) AS (SELECT date(?1) UNION ALL SELECT date(d, '+1 day') FROM days WHERE d < date(?2))
+                 SELECT days.d, COALESCE(SUM(b.total_cents), 0), COUNT(b.id)
+                 FROM days LEFT JOIN bills b ON b.created_at >= days.d || ' 00:00:00' AND b.created_at <= days.d || ' 23:59:59' AND b.voided_at IS NULL{}
+                 GROUP BY days.d ORDER BY days.d",
+                training_clause(&qs, "b.is_training")
+            ))?;
+            let rows: Vec<Value> = stmt.query_map(params![start_ts, end_ts], |r| Ok(json!({ "date": r.get::<_, String>(0)?, "total_cents": r.get::<_, i64>(1)?, "bills": r.get::<_, i64>(2)? })))?.filter_map(|r| r.ok()).collect();
+            Ok(report_response(&qs, &rows, json!(rows)))
+        }),
+
+        // Always 24 buckets so the heatmap never has to fill holes.
+        ("GET", "/reports/hourly") => with_db(state, |conn| {
+            validate_range_params(&qs)?;
+            let (start_ts, end_ts) = report_range(conn, &qs);
+            let mut stmt = conn.prepare(&format!(
+                "SELECT CAST(strftime('%H', created_at) AS INTEGER), COUNT(*), COALESCE(SUM(total_cents), 0)
+                 FROM bills
+                 WHERE created_at >= ?1 AND created_at <= ?2 AND voided_at IS NULL{}
+                 GROUP BY 1",
+                training_clause(&qs, "is_training")
+            ))?;
+            let mut buckets = [(0i64, 0i64); 24];
+            for (hour, count, revenue) in stmt.query_map(params![start_ts, end_ts], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, i64>(1)?, r.get::<_, i64>(2)?)))?.filter_map(|r| r.ok()) {
+                if let Some(b) = buckets.get_mut(hour as usize) { *b = (count, revenue); }
+            }
+            let rows: Vec<Value> = buckets.iter().enumerate().map(|(h, (count, revenue))| json!({ "hour": h, "bill_count": count, "revenue_cents": revenue })).collect();
+            Ok(report_response(&qs, &rows, json!({ "rows": rows })))
+        }),
+
+        ("GET", "/reports/top-products") => with_db(state, |conn| {
+            let (start_ts, end_ts) = report_range(conn, &qs);
+            let limit: i64 = qs.get("limit").and_then(|v| v.parse().ok()).unwrap_or(10).clamp(1, 100);
+            let mut stmt = conn.prepare(&format!(
+                "SELECT i.product_id, MAX(i.product_name), SUM(i.qty), SUM(i.line_total_cents)
+                 FROM bill_items i JOIN bills b ON b.id = i.bill_id
+                 WHERE b.created_at >= ?1 AND b.created_at <= ?2 AND b.voided_at IS NULL{}
+                 GROUP BY i.product_id
+                 ORDER BY SUM(i.qty) DESC, SUM(i.line_total_cents) DESC
+                 LIMIT ?3",
+                training_clause(&qs, "b.is_training")
+            ))?;
+            let rows: Vec<Value> = stmt.query_map(params![start_ts, end_ts, limit], |r| Ok(json!({ "product_id": r.get::<_, i64>(0)?, "product_name": r.get::<_, String>(1)?, "qty_sold": r.get::<_, i64>(2)?, "revenue_cents": r.get::<_, i64>(3)? })))?.filter_map(|r| r.ok()).collect();
+            Ok(report_response(&qs, &rows, json!({ "rows": rows })))
+        }),
+
+        // Keyed by the product's current category; names are only for display, like top-products.
+        ("GET", "/reports/category-sales") => with_db(state, |conn| {
+            let (start_ts, end_ts) = report_range(conn, &qs);
+            let mut stmt = conn.prepare(&format!(
+                "SELECT p.category_id, c.name, SUM(i.qty), SUM(i.line_total_cents)
+                 FROM bill_items i JOIN bills b ON b.id = i.bill_id
+                 LEFT JOIN products p ON p.id = i.product_id
+                 LEFT JOIN categories c ON c.id = p.category_id
+                 WHERE b.created_at >= ?1 AND b.created_at <= ?2 AND b.voided_at IS NULL{}
+                 GROUP BY p.category_id
+                 ORDER BY SUM(i.line_total_cents) DESC",
+                training_clause(&qs, "b.is_training")
+            ))?;
+            let rows: Vec<Value> = stmt.query_map(params![start_ts, end_ts], |r| Ok(json!({ "category_id": r.get::<_, Option<i64>>(0)?, "category": r.get::<_, Option<String>>(1)?, "qty_sold": r.get::<_, i64>(2)?, "revenue_cents": r.get::<_, i64>(3)? })))?.filter_map(|r| r.ok()).collect();
+            Ok(report_response(&qs, &rows, json!({ "rows": rows })))
+        }),
+
+        ("GET", "/reports/margins") => with_db(state, |conn| {
+            let (start_ts, end_ts) = report_range(conn, &qs);
+            let mut stmt = conn.prepare(&format!(
+                "SELECT i.product_id, MAX(i.product_name), SUM(i.qty), SUM(i.line_total_cents), SUM(i.qty * i.cost_cents)
+                 FROM bill_items i JOIN bills b ON b.id = i.bill_id
+                 WHERE b.created_at >= ?1 AND b.created_at <= ?2 AND b.voided_at IS NULL{}
+                 GROUP BY i.product_id
+                 ORDER BY SUM(i.line_total_cents) - SUM(i.qty * i.cost_cents) DESC",
+                training_clause(&qs, "b.is_training")
+            ))?;
+            let rows: Vec<Value> = stmt.query_map(params![start_ts, end_ts], |r| {
+                let revenue = r.get::<_, i64>(3)?;
+                let cost = r.get::<_, i64>(4)?;
+                let margin = revenue - cost;
+                let pct = if revenue != 0 { (margin as f64 * 10_000.0 / revenue as f64).round() / 100.0 } else { 0.0 };
+                Ok(json!({ "product_id": r.get::<_, i64>(0)?, "product_name": r.get::<_, String>(1)?, "qty_sold": r.get::<_, i64>(2)?, "revenue_cents": revenue, "cost_cents": cost, "margin_cents": margin, "margin_pct": pct }))
+            })?.filter_map(|r| r.ok()).collect();
+            let revenue: i64 = rows.iter().map(|r| r["revenue_cents"].as_i64().unwrap_or(0)).sum();
+            let margin: i64 = rows.iter().map(|r| r["margin_cents"].as_i64().unwrap_or(0)).sum();
+            Ok(report_response(&qs, &rows, json!({ "rows": rows, "revenue_cents": revenue, "margin_cents": margin })))
+        }),
+
+        ("GET", "/reports/comps") => with_db(state, |conn| {
+            let (start_ts, end_ts) = report_range(conn, &qs);
+            let mut stmt = conn.prepare(&format!(
+                "SELECT b.bill_no, b.created_at, i.product_name, i.qty, i.unit_price_cents * i.qty, i.comp_reason
+                 FROM bill_items i JOIN bills b ON b.id = i.bill_id
+                 WHERE i.is_comp = 1 AND b.created_at >= ?1 AND b.created_at <= ?2 AND b.voided_at IS NULL{}
+                 ORDER BY b.created_at",
+                training_clause(&qs, "b.is_training")
+            ))?;
+            let rows: Vec<Value> = stmt.query_map(params![start_ts, end_ts], |r| Ok(json!({ "bill_no": r.get::<_, String>(0)?, "created_at": r.get::<_, String>(1)?, "product_name": r.get::<_, String>(2)?, "qty": r.get::<_, i64>(3)?, "comp_value_cents": r.get::<_, i64>(4)?, "reason": r.get::<_, Option<String>>(5)? })))?.filter_map(|r| r.ok()).collect();
+            let total: i64 = rows.iter().map(|r| r["comp_value_cents"].as_i64().unwrap_or(0)).sum();
+            Ok(report_response(&qs, &rows, json!({ "rows": rows, "comp_count": rows.len(), "comp_value_cents": total })))
+        }),
+
+        ("GET", "/reports/tips") => with_db(state, |conn| {
+            let (start_ts, end_ts) = report_range(conn, &qs);
+            let mut stmt = conn.prepare(&format!(
+                "SELECT bill_no, created_at, payment_mode, tip_cents FROM bills
+                 WHERE tip_cents > 0 AND created_at >= ?1 AND created_at <= ?2 AND voided_at IS NULL{}
+                 ORDER BY created_at",
+                training_clause(&qs, "is_training")
+            ))?;
+            let rows: Vec<Value> = stmt.query_map(params![start_ts, end_ts], |r| Ok(json!({ "bill_no": r.get::<_, String>(0)?, "created_at": r.get::<_, String>(1)?, "payment_mode": r.get::<_, String>(2)?, "tip_cents": r.get::<_, i64>(3)? })))?.filter_map(|r| r.ok()).collect();
+            let total: i64 = rows.iter().map(|r| r["tip_cents"].as_i64().unwrap_or(0)).sum();
+            Ok(report_response(&qs, &rows, json!({ "rows": rows, "bill_count": rows.len(), "tip_total_cents": total })))
+        }),
+
+        // Minutes from a held order's open time to its bill, split by dine-in (has a table) and takeaway.
+        // Bills that were never held have no open time and are left out rather than counted as zero.
+        ("GET", "/reports/turnaround") => with_db(state, |conn| {
+            validate_range_params(&qs)?;
+            let (start_ts, end_ts) = report_range(conn, &qs);
+            let mut stmt = conn.prepare(&format!(
+                "SELECT CASE WHEN table_no IS NULL THEN 'takeaway' ELSE 'dine_in' END, COUNT(*), AVG((julianday(created_at) - julianday(opened_at)) * 1440.0)
+                 FROM bills
+                 WHERE opened_at IS NOT NULL AND created_at >= ?1 AND created_at <= ?2 AND voided_at IS NULL{}
+                 GROUP BY 1 ORDER BY 1",
+                training_clause(&qs, "is_training")
+            ))?;
+            let rows: Vec<Value> = stmt.query_map(params![start_ts, end_ts], |r| Ok(json!({ "order_type": r.get::<_, String>(0)?, "bill_count": r.get::<_, i64>(1)?, "avg_minutes": (r.get::<_, f64>(2)? * 10.0).round() / 10.0 })))?.filter_map(|r| r.ok()).collect();
+            Ok(report_response(&qs, &rows, json!({ "rows": rows })))
+        }),
+
+        // -- held orders ------------------------------------------------------
+        // The payload is the same items array POST /bills takes, so recall just refills the cart.
+        // created_at is the order's open time; POST /bills with held_order_id copies it to the bill.
+        ("POST", "/held-orders") => with_db(state, |conn| {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let items = b["items"].as_array().filter(|a| !a.is_empty()).ok_or("items array required")?;
+            let label = b["label"].as_str().map(|s| s.trim()).filter(|s| !s.is_empty()).unwrap_or("Held order");
+            let payload = serde_json::to_string(items).map_err(internal("Encode items"))?;
+            conn.execute("INSERT INTO held_orders(label,payload,created_at) VALUES(?1,?2,datetime('now','localtime'))", params![label, payload])?;
+            Ok(json!({ "id": conn.last_insert_rowid() }))
+        }),
+
+        ("GET", "/held-orders") => with_db(state, |conn| {
+            let mut stmt = conn.prepare("SELECT id,label,payload,created_at FROM held_orders ORDER BY created_at, id")?;
+            let rows: Vec<Value> = stmt.query_map([], |r| {
+                let items: Value = serde_json::from_str(&r.get::<_, String>(2)?).unwrap_or(Value::Null);
+                Ok(json!({ "id": r.get::<_, i64>(0)?, "label": r.get::<_, String>(1)?, "item_count": items.as_array().map(|a| a.len()).unwrap_or(0), "created_at": r.get::<_, String>(3)? }))
+            })?.filter_map(|r| r.ok()).collect();
+            Ok(json!({ "rows": rows }))
+        }),
+
+        _ if method == "GET" && base.starts_with("/held-orders/") => {
+            let id: i64 = base.trim_start_matches("/held-orders/").parse().map_err(|_| "Invalid id".to_string())?;
+            with_db(state, |conn| {
+                let (label, payload, created_at) = conn
+                    .query_row("SELECT label,payload,created_at FROM held_orders WHERE id=?1", params![id], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?)))
+                    .map_err(|_| ApiError::NotFound("Held order not found".to_string()))?;
+                let items: Value = serde_json::from_str(&payload).map_err(internal("Held order items"))?;
+                Ok(json!({ "id": id, "label": label, "items": items, "created_at": created_at }))
+            })
+        }
+
+        _ if method == "DELETE" && base.starts_with("/held-orders/") => {
+            let id: i64 = base.trim_start_matches("/held-orders/").parse().map_err(|_| "Invalid id".to_string())?;
+            with_db(state, |conn| {
+                let changed = conn.execute("DELETE FROM held_orders WHERE id=?1", params![id])?;
+                if changed == 0 {
+                    return Err(ApiError::NotFound("Held order not found".to_string()));
+                }
+                Ok(json!({ "ok": true }))
+            })
+        }
+
+        // -- audit ------------------------------------------------------------
+        ("GET", "/audit") => with_db(state, |conn| {
+            let limit: i64 = qs.get("limit").and_then(|v| v.parse().ok()).unwrap_or(100).clamp(1, 1000);
+            let action = qs.get("action").map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+            let mut stmt = conn.prepare("SELECT id,action,entity,entity_id,detail,created_at FROM audit_log WHERE (?1 IS NULL OR action = ?1) ORDER BY id DESC LIMIT ?2")?;
+            let rows: Vec<Value> = stmt.query_map(params![action, limit], |r| {
+                let detail: Value = r.get::<_, Option<String>>(4)?.and_then(|d| serde_json::from_str(&d).ok()).unwrap_or(Value::Null);
+                Ok(json!({ "id": r.get::<_, i64>(0)?, "action": r.get::<_, String>(1)?, "entity": r.get::<_, String>(2)?, "entity_id": r.get::<_, Option<i64>>(3)?, "detail": detail, "created_at": r.get::<_, String>(5)? }))
+            })?.filter_map(|r| r.ok()).collect();
+            Ok(json!({ "rows": rows }))
+        }),
+
+        // -- customers --------------------------------------------------------
+        // Phone is the identity: posting a known number returns that customer instead of a duplicate.
+        ("POST", "/customers") => with_db(state, |conn| {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let name = b["name"].as_str().map(|s| s.trim()).filter(|s| !s.is_empty()).ok_or("name required")?;
+            let phone = normalize_phone(b["phone"].as_str().unwrap_or_default()).ok_or("A valid phone number is required")?;
+            let inserted = conn.execute("INSERT OR IGNORE INTO customers(name,phone,created_at) VALUES(?1,?2,datetime('now','localtime'))", params![name, phone])?;
+            let mut customer = conn.query_row("SELECT id,name,phone,created_at FROM customers WHERE phone=?1", params![phone], customer_row)?;
+            customer["created"] = json!(inserted > 0);
+            Ok(customer)
         }),
 
+        ("GET", "/customers/search") => with_db(state, |conn| {
+            let q = qs.get("q").map(|s| s.trim().to_string()).unwrap_or_default();
+            let digits: String = q.chars().filter(|c| c.is_ascii_digit()).collect();
+            let phone_pat = if digits.is_empty() { String::new() } else { format!("%{}%", digits) };
+            let mut stmt = conn.prepare("SELECT id,name,phone,created_at FROM customers WHERE name LIKE ?1 OR phone LIKE ?2 ORDER BY name LIMIT 50")?;
+            let rows: Vec<Value> = stmt.query_map(params![format!("%{}%", q), phone_pat], customer_row)?.filter_map(|r| r.ok()).collect();
+            Ok(json!(rows))
+        }),
+
+        _ if method == "GET" && base.starts_with("/customers/") && base.ends_with("/bills") => {
+            let id: i64 = base.trim_start_matches("/customers/").trim_end_matches("/bills").parse().map_err(|_| "Invalid customer id".to_string())?;
+            with_db(state, |conn| {
+                let customer = conn.query_row("SELECT id,name,phone,created_at FROM customers WHERE id=?1", params![id], customer_row).map_err(|_| ApiError::NotFound("Customer not found".to_string()))?;
+                let mut cq = qs.clone();
+                cq.insert("customer_id".to_string(), id.to_string());
+                let mut page = list_bills(conn, &cq)?;
+                // Lifetime figures ignore paging and date filters; voided and training bills never count.
+                let (count, spend): (i64, i64) = conn
+                    .query_row("SELECT COUNT(*), COALESCE(SUM(total_cents), 0) FROM bills WHERE customer_id = ?1 AND voided_at IS NULL AND is_training = 0", params![id], |r| Ok((r.get(0)?, r.get(1)?)))
+                    .unwrap_or((0, 0));
+                page["customer"] = customer;
+                page["lifetime_bill_count"] = json!(count);
+                page["lifetime_spend_cents"] = json!(spend);
+                Ok(page)
+            })
+        }
+
+        _ if method == "POST" && base.starts_with("/bills/") && base.ends_with("/reprint") => {
+            let id: i64 = base.trim_start_matches("/bills/").trim_end_matches("/reprint").parse().map_err(|_| "Invalid bill id".to_string())?;
+            let (payload, printer, cfg) = with_db(state, |conn| {
+                Ok((receipt_from_bill(conn, id)?, resolve_printer(conn, body.as_ref().and_then(|b| b["printerName"].as_str())), load_print_config(conn)))
+            })?;
+            do_print(state.transport.as_ref(), &printer, &payload, &cfg, 1, false)?;
+            Ok(json!({ "ok": true, "printer": printer, "bill_no": payload.bill_no }))
+        }
+
         _ if method == "DELETE" && base.starts_with("/bills/") => {
             let id: i64 = base.trim_start_matches("/bills/").parse().map_err(|_| "Invalid id".to_string())?;
-            with_db(state.inner(), |conn| {
-                let changed = conn.execute("DELETE FROM bills WHERE id=?1", params![id]).map_err(|e| e.to_string())?;
+            with_db(state, |conn| {
+                check_bill_age(conn, id)?;
+                let bill: Option<(String, i64)> = conn.query_row("SELECT bill_no, total_cents FROM bills WHERE id = ?1", params![id], |r| Ok((r.get(0)?, r.get(1)?))).ok();
+                let changed = conn.execute("DELETE FROM bills WHERE id=?1", params![id])?;
                 if changed == 0 {
-                    return Err("Bill not found".to_string());
+                    return Err(ApiError::NotFound("Bill not found".to_string()));
                 }
+                let (bill_no, total) = bill.unwrap_or_default();
+                audit(conn, "bill.delete", "bill", Some(id), json!({ "bill_no": bill_no, "total_cents": total }))?;
                 Ok(json!({ "ok": true }))
             })
         }
 
         _ if method == "GET" && base.starts_with("/bills/") => {
             let id: i64 = base.trim_start_matches("/bills/").parse().map_err(|_| "Invalid id".to_string())?;
-            with_db(state.inner(), |conn| {
-                let mut stmt = conn.prepare("SELECT product_id,product_name,unit_price_cents,qty,line_total_cents FROM bill_items WHERE bill_id=?1").map_err(|e| e.to_string())?;
-                let rows: Vec<Value> = stmt.query_map(params![id], |r| Ok(json!({ "product_id": r.get::<_, i64>(0)?, "product_name": r.get::<_, String>(1)?, "unit_price_cents": r.get::<_, i64>(2)?, "qty": r.get::<_, i64>(3)?, "line_total_cents": r.get::<_, i64>(4)? }))).map_err(|e| e.to_string())?.filter_map(|r| r.ok()).collect();
-                Ok(json!({ "items": rows }))
+            with_db(state, |conn| {
+                let mut stmt = conn.prepare("SELECT product_id,product_name,unit_price_cents,qty,line_total_cents,is_comp,comp_reason,discount_cents,tax_rate_bps,tax_cents FROM bill_items WHERE bill_id=?1")?;
+                let rows: Vec<Value> = stmt.query_map(params![id], |r| Ok(json!({ "product_id": r.get::<_, i64>(0)?, "product_name": r.get::<_, String>(1)?, "unit_price_cents": r.get::<_, i64>(2)?, "qty": r.get::<_, i64>(3)?, "line_total_cents": r.get::<_, i64>(4)?, "is_comp": r.get::<_, i64>(5)?, "comp_reason": r.get::<_, Option<String>>(6)?, "discount_cents": r.get::<_, i64>(7)?, "tax_rate_bps": r.get::<_, i64>(8)?, "tax_cents": r.get::<_, i64>(9)? })))?.filter_map(|r| r.ok()).collect();
+                let payment = conn
+                    .query_row("SELECT payment_mode,amount_tendered_cents,change_cents,table_no FROM bills WHERE id=?1", params![id], |r| Ok((r.get::<_, Option<String>>(0)?, r.get::<_, Option<i64>>(1)?, r.get::<_, Option<i64>>(2)?, r.get::<_, Option<String>>(3)?)))
+                    .unwrap_or((None, None, None, None));
+                let mut ps = conn.prepare("SELECT method,amount_cents FROM bill_payments WHERE bill_id=?1 ORDER BY id")?;
+                let payments: Vec<Value> = ps.query_map(params![id], |r| Ok(json!({ "method": r.get::<_, String>(0)?, "amount_cents": r.get::<_, i64>(1)? })))?.filter_map(|r| r.ok()).collect();
+                let customer = conn
+                    .query_row("SELECT c.id,c.name,c.phone FROM bills b JOIN customers c ON c.id = b.customer_id WHERE b.id=?1", params![id], |r| Ok(json!({ "id": r.get::<_, i64>(0)?, "name": r.get::<_, String>(1)?, "phone": r.get::<_, String>(2)? })))
+                    .ok();
+                // Taxable value mirrors POST /bills: net of the bill discount rate only when it was applied before tax.
+                let dr: i64 = conn.query_row("SELECT CASE WHEN discount_before_tax = 1 THEN discount_rate_bps ELSE 0 END FROM bills WHERE id=?1", params![id], |r| r.get(0)).unwrap_or(0);
+                let mut by_rate: std::collections::BTreeMap<i64, (i64, i64)> = std::collections::BTreeMap::new();
+                for it in rows.iter().filter(|it| it["tax_rate_bps"].as_i64().unwrap_or(0) > 0) {
+                    let lt = it["line_total_cents"].as_i64().unwrap_or(0);
+                    let e = by_rate.entry(it["tax_rate_bps"].as_i64().unwrap_or(0)).or_insert((0, 0));
+                    e.0 += lt - bps_of(lt, dr);
+                    e.1 += it["tax_cents"].as_i64().unwrap_or(0);
+                }
+                let taxes: Vec<Value> = by_rate.into_iter().map(|(rate, (taxable, tax))| {
+                    let (cgst, sgst) = split_gst(tax);
+                    json!({ "rate_bps": rate, "taxable_cents": taxable, "tax_cents": tax, "cgst_cents": cgst, "sgst_cents": sgst })
+                }).collect();
+                let total_tax: i64 = taxes.iter().map(|t| t["tax_cents"].as_i64().unwrap_or(0)).sum();
+                Ok(json!({ "items": rows, "payment_mode": payment.0, "amount_tendered_cents": payment.1, "change_cents": payment.2, "table_no": payment.3, "payments": payments, "customer": customer, "taxes": taxes, "total_tax_cents": total_tax }))
             })
         }
 
+        // -- settings ---------------------------------------------------------
+        ("GET", "/settings/export") => with_db(state, |conn| Ok(json!({ "settings": export_settings(conn) }))),
+
+        ("POST", "/settings/import") => with_db(state, |conn| {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let incoming = b["settings"].as_object().ok_or("settings object required")?;
+            let mut applied = Vec::new();
+            let mut skipped = Vec::new();
+            let tx = conn.unchecked_transaction()?;
+            for (key, value) in incoming {
+                if !EXPORTABLE_SETTINGS.contains(&key.as_str()) {
+                    skipped.push(json!({ "key": key, "reason": "not importable" }));
+                    continue;
+                }
+                match normalize_setting(key, value) {
+                    Ok(v) => {
+                        tx.execute("INSERT INTO settings(key,value) VALUES(?1,?2) ON CONFLICT(key) DO UPDATE SET value=excluded.value", params![key, v])?;
+                        applied.push(key.clone());
+                    }
+                    Err(reason) => skipped.push(json!({ "key": key, "reason": reason })),
+                }
+            }
+            tx.commit()?;
+            Ok(json!({ "applied": applied, "skipped": skipped }))
+        }),
+
+        // Logical backup: survives SQLite upgrades and architecture changes, unlike the file copy below.
+        ("GET", "/export/json") => with_db(state, export_json),
+
+        ("POST", "/import/json") => with_db(state, |conn| {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let replace = match b["mode"].as_str() {
+                Some("replace") => true,
+                Some("merge") => false,
+                _ => return Err("mode must be 'replace' or 'merge'".into()),
+            };
+            let doc = if b["data"].is_object() { &b["data"] } else { b };
+            import_json(conn, doc, replace)
+        }),
+
         // -- backup -----------------------------------------------------------
-        ("GET", "/backup/settings") => with_db(state.inner(), |conn| {
+        ("GET", "/backup/settings") => with_db(state, |conn| {
             let bp = get_setting(conn, "backup_path", &state.backup_dir.to_string_lossy());
             let iv = get_setting(conn, "backup_interval_minutes", "1440");
-            Ok(json!({ "backup_path": bp, "backup_interval_minutes": iv.parse::<i64>().unwrap_or(1440) }))
+            let keep = get_setting(conn, "backup_retention_count", "30");
+            let compress = get_setting(conn, "backup_compress", "0") == "1";
+            Ok(json!({ "backup_path": bp, "backup_interval_minutes": iv.parse::<i64>().unwrap_or(1440), "backup_retention_count": keep.parse::<i64>().unwrap_or(30), "backup_compress": compress }))
         }),
 
-        ("POST", "/backup/settings") => with_db(state.inner(), |conn| {
+        ("POST", "/backup/settings") => with_db(state, |conn| {
             let b = body.as_ref().ok_or("Missing body")?;
             let fallback = state.backup_dir.to_string_lossy().to_string();
             let bp = b["backup_path"].as_str().unwrap_or(&fallback);
             let iv = b["backup_interval_minutes"].as_i64().unwrap_or(1440);
+            let keep = match b.get("backup_retention_count") {
+                Some(v) => Some(normalize_setting("backup_retention_count", v).map_err(|e| format!("backup_retention_count {}", e))?),
+                None => None,
+            };
             set_setting(conn, "backup_path", bp);
             set_setting(conn, "backup_interval_minutes", &iv.to_string());
+            if let Some(keep) = keep {
+                set_setting(conn, "backup_retention_count", &keep);
+            }
+            if let Some(c) = b["backup_compress"].as_bool() {
+                set_setting(conn, "backup_compress", if c { "1" } else { "0" });
+            }
             Ok(json!({ "ok": true }))
         }),
 
-        ("GET", "/backup/files") => with_db(state.inner(), |conn| {
+        ("GET", "/backup/files") => with_db(state, |conn| {
             let t = qs.get("path").cloned().unwrap_or_else(|| get_setting(conn, "backup_path", &state.backup_dir.to_string_lossy()));
-            let files = list_backups(&PathBuf::from(&t));
+            let verify = qs.get("verify").map(|v| v == "1" || v == "true").unwrap_or(false);
+            let files = list_backups(&PathBuf::from(&t), verify);
             Ok(json!({ "files": files, "backup_path": t }))
         }),
 
-        ("POST", "/backup/run") => with_db(state.inner(), |conn| {
+        ("GET", "/backup/status") => with_db(state, |conn| {
+            let bp = get_setting(conn, "backup_path", &state.backup_dir.to_string_lossy());
+            let iv = get_setting(conn, "backup_interval_minutes", "1440").parse::<i64>().unwrap_or(1440);
+            let newest = list_backups(&PathBuf::from(&bp), false).into_iter().next();
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+            let stale = match &newest {
+                Some(f) => {
+                    let modified = f["modified_at"].as_str().and_then(|v| v.parse::<i64>().ok()).unwrap_or(0);
+                    now - modified > iv * 60
+                }
+                None => true,
+            };
+            Ok(json!({ "newest": newest, "backup_path": bp, "backup_interval_minutes": iv, "stale": stale }))
+        }),
+
+        ("POST", "/backup/run") => with_db(state, |conn| {
             let b = body.as_ref();
             let t = b.and_then(|v| v["target"].as_str()).map(|s| s.to_string()).unwrap_or_else(|| get_setting(conn, "backup_path", &state.backup_dir.to_string_lossy()));
             let file = do_backup(conn, &state.db_path, &PathBuf::from(&t))?;
             Ok(json!({ "file": file }))
         }),
 
+        ("POST", "/backup/restore/diff") => {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let actual = resolve_backup_source(b)?;
+            let inflated = actual.to_string_lossy().ends_with(".gz");
+            let readable = if inflated {
+                let tmp = std::env::temp_dir().join(format!("meet-eat-diff-{}.db", simple_ts()));
+                copy_backup(&actual, &tmp)?;
+                tmp
+            } else {
+                actual.clone()
+            };
+            let backup_conn = Connection::open_with_flags(&readable, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+                .map_err(internal("Open backup"))?;
+            let backup = db_summary(&backup_conn);
+            drop(backup_conn);
+            if inflated { let _ = remove_file(&readable); }
+            let backup = backup?;
+            let current = with_db(state, db_summary)?;
+            Ok(json!({ "source": actual.to_string_lossy(), "current": current, "backup": backup }))
+        }
+
         ("POST", "/backup/restore") => {
             let b = body.as_ref().ok_or("Missing body")?;
-            let src_raw = b.get("source").and_then(|v| v.as_str()).map(|s| s.to_string())
-                .or_else(|| { let bp = b.get("backup_path").and_then(|v| v.as_str())?; let f = b.get("file_name").and_then(|v| v.as_str())?; Some(format!("{}\\{}", bp, f)) })
-                .or_else(|| b.get("backup_path").and_then(|v| v.as_str()).map(|s| s.to_string()))
-                .unwrap_or_default();
-            if src_raw.is_empty() { return Err("No backup source".to_string()); }
-
-            let sp = PathBuf::from(&src_raw);
-            let actual = if sp.is_file() { sp } else if sp.is_dir() {
-                let bks = list_backups(&sp);
-                let first = bks.first().and_then(|v| v["path"].as_str().map(PathBuf::from));
-                first.ok_or("No backup files in directory")?
-            } else { return Err("Backup not found".to_string()); };
-
-            let mut guard = state.db.lock().map_err(|e| e.to_string())?;
+            let actual = resolve_backup_source(b)?;
+            verify_backup(&actual)?;
+            // Stage next to the live DB first so a bad or truncated backup never replaces it.
+            let staged = state.db_path.with_extension("db.restore");
+            if let Err(e) = copy_backup(&actual, &staged) {
+                let _ = remove_file(&staged);
+                return Err(e);
+            }
+
+            let mut guard = state.db.lock().map_err(|_| ApiError::Internal("Database lock poisoned".to_string()))?;
             if let Some(c) = guard.take() {
                 let _ = c.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);");
                 let _ = c.close();
             }
             let _ = fs::remove_file(format!("{}-wal", state.db_path.to_string_lossy()));
             let _ = fs::remove_file(format!("{}-shm", state.db_path.to_string_lossy()));
-            fs::copy(&actual, &state.db_path).map_err(|e| format!("Restore: {e}"))?;
-            let nc = init_db(&state.db_path)?;
+            fs::rename(&staged, &state.db_path).map_err(internal("Restore"))?;
+            let nc = init_db(&state.db_path).map_err(ApiError::Internal)?;
+            // Logged into the restored DB, since the old log was just replaced with it. The restore
+            // has already happened, so a failed entry must not leave the app without a connection.
+            let _ = audit(&nc, "backup.restore", "database", None, json!({ "restored_from": actual.to_string_lossy() }));
             *guard = Some(nc);
             Ok(json!({ "ok": true, "restored_from": actual.to_string_lossy() }))
         }
 
+        // -- app ------------------------------------------------------------
+        ("POST", "/app/restart/token") => {
+            let token: String = with_db(state, |conn| Ok(conn.query_row("SELECT lower(hex(randomblob(16)))", [], |r| r.get(0))?))?;
+            *state.restart_token.lock().map_err(|_| ApiError::Internal("Restart token lock poisoned".to_string()))? = Some((token.clone(), Instant::now()));
+            Ok(json!({ "token": token, "expires_in_secs": RESTART_TOKEN_TTL.as_secs() }))
+        }
+
+        ("POST", "/app/restart") => {
+            let given = body.as_ref().and_then(|b| b["token"].as_str()).unwrap_or("");
+            // Taken either way, so every token is good for exactly one attempt.
+            let issued = state.restart_token.lock().map_err(|_| ApiError::Internal("Restart token lock poisoned".to_string()))?.take();
+            match issued {
+                Some((token, at)) if token == given && at.elapsed() <= RESTART_TOKEN_TTL => {}
+                _ => return Err("Restart not confirmed: request a fresh token from /app/restart/token".into()),
+            }
+            {
+                let guard = state.db.lock().map_err(|_| ApiError::Internal("Database lock poisoned".to_string()))?;
+                if let Some(c) = guard.as_ref() {
+                    let _ = c.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);");
+                }
+            }
+            app.ok_or_else(|| ApiError::Internal("Restart needs the running app".to_string()))?.restart();
+            Ok(json!({ "ok": true }))
+        }
+
         // -- print ------------------------------------------------------------
+        // A failed lookup still answers so the settings screen can fall back to a text box.
+        ("GET", "/printers") => match list_printers() {
+            Ok(printers) => Ok(json!({ "printers": printers })),
+            Err(e) => Ok(json!({ "printers": [], "error": e })),
+        },
+
         ("POST", "/print") => {
             let b = body.as_ref().ok_or("Missing body")?;
-            let printer = b["printerName"].as_str().unwrap_or("Rugtek printer").to_string();
             let pv = b.get("payload").ok_or("Missing payload")?;
             let payload: ReceiptPayload = serde_json::from_value(pv.clone()).map_err(|e| format!("Bad payload: {e}"))?;
-            do_print(&printer, &payload)?;
-            Ok(json!({ "ok": true }))
+            let (printer, cfg) = with_db(state, |conn| Ok((resolve_printer(conn, b["printerName"].as_str()), load_print_config(conn))))?;
+            let copies = b["copies"].as_u64().unwrap_or(1).clamp(1, MAX_COPIES as u64) as usize;
+            let labelled = b["copyLabels"].as_bool().unwrap_or(false);
+            do_print(state.transport.as_ref(), &printer, &payload, &cfg, copies, labelled)?;
+            // Only remember printers that actually worked.
+            with_db(state, |conn| { set_setting(conn, "last_printer", &printer); Ok(()) })?;
+            Ok(json!({ "ok": true, "printer": printer }))
         }
 
-        _ => Err(format!("Not found: {} {}", method, path)),
-    }
-}
+        ("POST", "/print/test") => {
+            let (printer, cfg, now) = with_db(state, |conn| {
+                let now = conn.query_row("SELECT datetime('now','localtime')", [], |r| r.get::<_, String>(0)).unwrap_or_default();
+                Ok((resolve_printer(conn, body.as_ref().and_then(|b| b["printerName"].as_str())), load_print_config(conn), now))
+            })?;
+            state.transport.send(&printer, &build_test_bytes(&printer, &now, cfg.width, cfg.cut))?;
+            Ok(json!({ "ok": true, "printer": printer }))
+        }
 
-// -- main ---------------------------------------------------------------------
+        // Same layout as /print, but nothing leaves the machine.
+        ("POST", "/print/preview") => {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let pv = b.get("payload").unwrap_or(b);
+            let payload: ReceiptPayload = serde_json::from_value(pv.clone()).map_err(|e| format!("Bad payload: {e}"))?;
+            let cfg = with_db(state, |conn| Ok(load_print_config(conn)))?;
+            let text = receipt_body(&payload, &cfg);
+            let lines = text.split("\r\n").count();
+            Ok(json!({ "text": text, "lines": lines, "width": cfg.width }))
+        }
 
-fn main() {
-    tauri::Builder::default()
-        .invoke_handler(tauri::generate_handler![api_call])
-        .setup(|app| {
-            let data_dir = app.path_resolver().app_data_dir().unwrap_or_else(|| {
-                let ad = std::env::var("APPDATA").unwrap_or_else(|_| ".".to_string());
-                PathBuf::from(ad).join("com.meetandeat.app")
+        ("POST", "/print/kot") => {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let items: Vec<KotItem> = serde_json::from_value(b["items"].clone()).map_err(|e| format!("Bad items: {e}"))?;
+            let items: Vec<KotItem> = items.into_iter().filter(|it| it.qty > 0 && !it.name.trim().is_empty()).collect();
+            if items.is_empty() { return Err("No items".into()); }
+            let text = |k: &str| b[k].as_str().map(str::trim).filter(|s| !s.is_empty()).map(str::to_string)
+                .or_else(|| b[k].as_i64().map(|n| n.to_string()));
+            let (table, order) = (text("table_no"), text("order_no"));
+            let (printer, cfg, now) = with_db(state, |conn| {
+                let now = conn.query_row("SELECT datetime('now','localtime')", [], |r| r.get::<_, String>(0)).unwrap_or_default();
+                Ok((resolve_printer(conn, b["printerName"].as_str()), load_print_config(conn), now))
+            })?;
+            state.transport.send(&printer, &build_kot_bytes(&items, table.as_deref(), order.as_deref(), &now, cfg.width, cfg.cut))?;
+            Ok(json!({ "ok": true, "printer": printer }))
+        }
+
+        // `previous` is what the kitchen already has for a held order and `current` is the order now;
+        // only the difference is printed.
+        ("POST", "/print/kot-delta") => {
+            let b = body.as_ref().ok_or("Missing body")?;
+            let set = |k: &str| -> Result<(i64, Vec<KotItem>), ApiError> {
+                let held = b[k]["held_order_id"].as_i64().ok_or_else(|| format!("{}.held_order_id required", k))?;
+                let items: Vec<KotItem> = serde_json::from_value(b[k]["items"].clone()).map_err(|e| format!("Bad {} items: {e}", k))?;
+                Ok((held, items.into_iter().filter(|it| it.qty > 0 && !it.name.trim().is_empty()).collect()))
+            };
+            let (prev_id, previous) = set("previous")?;
+            let (held_id, current) = set("current")?;
+            if prev_id != held_id { return Err("previous and current must belong to the same held order".into()); }
+            let delta = kot_delta(&previous, &current);
+            if delta.is_empty() { return Err("Nothing changed since the last KOT".into()); }
+            let table = b["table_no"].as_str().map(str::trim).filter(|s| !s.is_empty()).map(str::to_string);
+            let (label, printer, cfg, now) = with_db(state, |conn| {
+                let label: String = conn.query_row("SELECT label FROM held_orders WHERE id=?1", params![held_id], |r| r.get(0))
+                    .map_err(|_| ApiError::NotFound("Held order not found".to_string()))?;
+                let now = conn.query_row("SELECT datetime('now','localtime')", [], |r| r.get::<_, String>(0)).unwrap_or_default();
+                Ok((label, resolve_printer(conn, b["printerName"].as_str()), load_print_config(conn), now))
+            })?;
+            state.transport.send(&printer, &build_kot_bytes(&delta, table.as_deref(), Some(&label), &now, cfg.width, cfg.cut))?;
+            Ok(json!({ "ok": true, "printer": printer, "lines": delta.len() }))
+        }
+
+        ("POST", "/cash-drawer/open") => {
+            let printer = with_db(state, |conn| Ok(resolve_printer(conn, body.as_ref().and_then(|b| b["printerName"].as_str()))))?;
+            let mut raw = vec![0x1B, 0x40];
+            raw.extend_from_slice(&CASH_DRAWER_PULSE);
+            state.transport.send(&printer, &raw)?;
+            Ok(json!({ "ok": true, "printer": printer }))
+        }
+
+        _ => Err(ApiError::NotFound(format!("Not found: {} {}", method, path))),
+    }
+}
+
+// -- main ---------------------------------------------------------------------
+
+fn main() {
+    tauri::Builder::default()
+        .invoke_handler(tauri::generate_handler![api_call])
+        .setup(|app| {
+            let data_dir = app.path_resolver().app_data_dir().unwrap_or_else(|| {
+                let ad = std::env::var("APPDATA").unwrap_or_else(|_| ".".to_string());
+                PathBuf::from(ad).join("com.meetandeat.app")
             });
             let _ = create_dir_all(&data_dir);
             let db_path = data_dir.join("app.db");
             let backup_dir = data_dir.join("backups");
             let _ = create_dir_all(&backup_dir);
+            let image_dir = data_dir.join("product_images");
             let conn = init_db(&db_path).expect("Failed to initialise database");
-            let backup_dir_str = backup_dir.to_string_lossy().to_string();
-            set_setting(&conn, "backup_path", &backup_dir_str);
-            set_setting(&conn, "backup_interval_minutes", "1440");
-            app.manage(AppState { db: Mutex::new(Some(conn)), db_path, backup_dir });
+            // Seed defaults only; overwriting here would undo the user's backup settings on every launch.
+            if get_setting(&conn, "backup_path", "").is_empty() {
+                set_setting(&conn, "backup_path", &backup_dir.to_string_lossy());
+            }
+            if get_setting(&conn, "backup_interval_minutes", "").is_empty() {
+                set_setting(&conn, "backup_interval_minutes", "1440");
+            }
+            app.manage(AppState { db: Mutex::new(Some(conn)), db_path, backup_dir, image_dir, errors: Mutex::new(VecDeque::new()), transport: Box::new(SpoolerTransport), restart_token: Mutex::new(None) });
+
+            let handle = app.handle();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(std::time::Duration::from_secs(BACKUP_TICK_SECS));
+                scheduled_backup_tick(handle.state::<AppState>().inner());
+            });
             Ok(())
         })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // Keeps every buffer instead of spooling it, so print routes can be checked byte for byte.
+    type Sent = Arc<Mutex<Vec<(String, Vec<u8>)>>>;
+
+    #[derive(Clone, Default)]
+    struct MockTransport(Sent);
+
+    impl PrintTransport for MockTransport {
+        fn send(&self, printer: &str, data: &[u8]) -> Result<(), ApiError> {
+            self.0.lock().unwrap().push((printer.to_string(), data.to_vec()));
+            Ok(())
+        }
+    }
+
+    struct TestApp {
+        state: AppState,
+        printed: MockTransport,
+        dir: PathBuf,
+    }
+
+    impl TestApp {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!("pos-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::SeqCst)));
+            let _ = fs::remove_dir_all(&dir);
+            create_dir_all(&dir).unwrap();
+            let db_path = dir.join("app.db");
+            let conn = init_db(&db_path).unwrap();
+            let printed = MockTransport::default();
+            let state = AppState {
+                db: Mutex::new(Some(conn)),
+                db_path,
+                backup_dir: dir.join("backups"),
+                image_dir: dir.join("product_images"),
+                errors: Mutex::new(VecDeque::new()),
+                transport: Box::new(printed.clone()),
+                restart_token: Mutex::new(None),
+            };
+            TestApp { state, printed, dir }
+        }
+
+        fn call(&self, method: &str, path: &str, body: Option<Value>) -> Result<Value, ApiError> {
+            route(None, &self.state, method, path, body)
+        }
+
+        fn ok(&self, method: &str, path: &str, body: Value) -> Value {
+            let body = if body.is_null() { None } else { Some(body) };
+            self.call(method, path, body).unwrap_or_else(|e| panic!("{} {} failed: {:?}", method, path, e))
+        }
+
+        fn db<R>(&self, f: impl FnOnce(&Connection) -> R) -> R {
+            f(self.state.db.lock().unwrap().as_ref().unwrap())
+        }
+
+        // Everything sent to the printer so far, decoded lossily so tests can search it as text.
+        fn printed_text(&self) -> Vec<String> {
+            self.printed.0.lock().unwrap().iter().map(|(_, data)| String::from_utf8_lossy(data).into_owned()).collect()
+        }
+
+        fn product(&self, name: &str, price_cents: i64) -> i64 {
+            self.ok("POST", "/products", json!({ "name": name, "price_cents": price_cents }))["id"].as_i64().unwrap()
+        }
+
+        // Rings up `qty` of one product at its list price and returns the bill id.
+        fn sell(&self, pid: i64, qty: i64) -> i64 {
+            let (name, price): (String, i64) = self.db(|c| c.query_row("SELECT name, price_cents FROM products WHERE id = ?1", params![pid], |r| Ok((r.get(0)?, r.get(1)?)))).unwrap();
+            let bill = self.ok("POST", "/bills", json!({ "items": [{ "product_id": pid, "product_name": name, "unit_price_cents": price, "qty": qty }] }));
+            self.bill_id(&bill)
+        }
+
+        // POST /bills answers with the bill number; most routes want the id.
+        fn bill_id(&self, bill: &Value) -> i64 {
+            self.db(|c| c.query_row("SELECT id FROM bills WHERE bill_no = ?1", params![bill["bill_no"].as_str()], |r| r.get(0))).unwrap()
+        }
+    }
+
+    impl Drop for TestApp {
+        fn drop(&mut self) {
+            if let Ok(mut guard) = self.state.db.lock() {
+                guard.take();
+            }
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn duplicate_item_no_is_a_conflict() {
+        let app = TestApp::new();
+        app.ok("POST", "/products", json!({ "name": "Tea", "price_cents": 1000, "item_no": 7 }));
+        let err = app.call("POST", "/products", Some(json!({ "name": "Coffee", "price_cents": 1200, "item_no": 7 }))).unwrap_err();
+        assert_eq!(err, ApiError::Conflict("Item No already in use".to_string()));
+        assert_eq!(serde_json::to_value(&err).unwrap(), json!({ "code": "conflict", "message": "Item No already in use" }));
+
+        let missing = app.call("PUT", "/categories/99", Some(json!({ "name": "X" }))).unwrap_err();
+        assert_eq!(missing.code(), "not_found");
+        let invalid = app.call("POST", "/products", Some(json!({}))).unwrap_err();
+        assert_eq!(invalid.code(), "validation");
+    }
+
+    #[test]
+    fn discount_before_tax_changes_the_taxable_value() {
+        let app = TestApp::new();
+        let pid = app.ok("POST", "/products", json!({ "name": "Thali", "price_cents": 1000, "tax_rate_bps": 500 }))["id"].as_i64().unwrap();
+        let cart = json!({ "items": [{ "product_id": pid, "product_name": "Thali", "unit_price_cents": 1000, "qty": 1 }], "discount_rate_bps": 1000 });
+
+        // 10.00 - 1.00 discount + 5% of 9.00
+        let before = app.ok("POST", "/bills", cart.clone());
+        assert_eq!((before["total_tax_cents"].as_i64(), before["total_cents"].as_i64()), (Some(45), Some(945)));
+
+        app.ok("POST", "/settings/import", json!({ "settings": { "discount_before_tax": false } }));
+        // 10.00 - 1.00 discount + 5% of 10.00
+        let after = app.ok("POST", "/bills", cart);
+        assert_eq!((after["total_tax_cents"].as_i64(), after["total_cents"].as_i64()), (Some(50), Some(950)));
+
+        let cur = Currency { symbol: "Rs".to_string(), separator: String::new() };
+        let position = |bill: &Value, label: &str| {
+            let id = app.bill_id(bill);
+            app.db(|c| format_receipt(&receipt_from_bill(c, id).unwrap(), 48, &cur).find(label).unwrap())
+        };
+        assert!(position(&before, "Discount") < position(&before, "CGST"));
+        assert!(position(&after, "Discount") > position(&after, "CGST"));
+    }
+
+    #[test]
+    fn repair_item_no_reassigns_seeded_duplicates() {
+        let app = TestApp::new();
+        app.db(|c| c.execute_batch(
+            "DROP INDEX idx_products_item_no;
+             INSERT INTO products(id, item_no, name, price_cents) VALUES (1, 5, 'Tea', 1000), (2, 5, 'Coffee', 1200), (3, 9, 'Vada', 800);",
+        )).unwrap();
+
+        let res = app.ok("POST", "/db/repair-item-no", Value::Null);
+        assert_eq!(res["changes"], json!([{ "id": 2, "old_item_no": 5, "new_item_no": 10 }]));
+        let audited: i64 = app.db(|c| c.query_row("SELECT COUNT(*) FROM audit_log WHERE action = 'product.item_no_repair' AND entity_id = 2", [], |r| r.get(0))).unwrap();
+        assert_eq!(audited, 1);
+        // The unique index is back, so the same duplicate can no longer be written.
+        assert!(app.db(|c| c.execute("UPDATE products SET item_no = 5 WHERE id = 3", [])).is_err());
+    }
+
+    #[test]
+    fn restart_needs_a_fresh_one_time_token() {
+        let app = TestApp::new();
+        assert_eq!(app.call("POST", "/app/restart", Some(json!({ "confirm": "RESTART" }))).unwrap_err().code(), "validation");
+
+        let token = app.ok("POST", "/app/restart/token", Value::Null)["token"].as_str().unwrap().to_string();
+        assert_eq!(app.call("POST", "/app/restart", Some(json!({ "token": "guess" }))).unwrap_err().code(), "validation");
+        // The wrong guess used the token up.
+        assert_eq!(app.call("POST", "/app/restart", Some(json!({ "token": token }))).unwrap_err().code(), "validation");
+
+        let token = app.ok("POST", "/app/restart/token", Value::Null)["token"].as_str().unwrap().to_string();
+        // Accepted; only the missing Tauri app stops the relaunch here.
+        assert_eq!(app.call("POST", "/app/restart", Some(json!({ "token": token }))).unwrap_err().code(), "internal");
+    }
+
+    #[test]
+    fn void_age_limit_is_enforced_at_the_boundary() {
+        let app = TestApp::new();
+        app.ok("POST", "/settings/import", json!({ "settings": { "max_void_age_hours": 1 } }));
+        let pid = app.product("Tea", 1000);
+        let inside = app.sell(pid, 1);
+        let outside = app.sell(pid, 1);
+        app.db(|c| {
+            c.execute("UPDATE bills SET created_at = datetime('now','localtime','-59 minutes') WHERE id = ?1", params![inside]).unwrap();
+            c.execute("UPDATE bills SET created_at = datetime('now','localtime','-61 minutes') WHERE id = ?1", params![outside]).unwrap();
+        });
+
+        app.ok("POST", &format!("/bills/{}/void", inside), json!({ "reason": "wrong table" }));
+        let err = app.call("POST", &format!("/bills/{}/void", outside), Some(json!({ "reason": "late" }))).unwrap_err();
+        assert_eq!(err.code(), "validation");
+        assert!(err.message().contains("older than 1 hours"));
+        assert_eq!(app.call("DELETE", &format!("/bills/{}", outside), None).unwrap_err().code(), "validation");
+        app.ok("DELETE", &format!("/bills/{}", inside), Value::Null);
+    }
+
+    #[test]
+    fn month_filter_knows_february_in_leap_years() {
+        assert_eq!(month_bounds("2024-02"), Some(("2024-02-01".to_string(), "2024-02-29".to_string())));
+        assert_eq!(month_bounds("2023-02"), Some(("2023-02-01".to_string(), "2023-02-28".to_string())));
+        assert_eq!(month_bounds("1900-02").unwrap().1, "1900-02-28");
+        assert_eq!(month_bounds("2000-02").unwrap().1, "2000-02-29");
+        assert_eq!(month_bounds("2024-13"), None);
+
+        let app = TestApp::new();
+        let bill = app.sell(app.product("Tea", 1000), 1);
+        app.db(|c| c.execute("UPDATE bills SET created_at = '2024-02-29 21:00:00' WHERE id = ?1", params![bill])).unwrap();
+        assert_eq!(app.ok("GET", "/bills?month=2024-02", Value::Null)["total"], json!(1));
+        // Explicit bounds take precedence over the month.
+        assert_eq!(app.ok("GET", "/bills?month=2024-02&start=2024-03-01&end=2024-03-31", Value::Null)["total"], json!(0));
+    }
+
+    #[test]
+    fn same_named_products_are_reported_separately() {
+        let app = TestApp::new();
+        let veg = app.ok("POST", "/products", json!({ "name": "Special", "price_cents": 1000, "category": "Veg" }))["id"].as_i64().unwrap();
+        let meat = app.ok("POST", "/products", json!({ "name": "Special", "price_cents": 2000, "category": "Meat" }))["id"].as_i64().unwrap();
+        app.sell(veg, 3);
+        app.sell(meat, 1);
+
+        let top = app.ok("GET", "/reports/top-products", Value::Null)["rows"].clone();
+        assert_eq!(top.as_array().unwrap().len(), 2);
+        assert_eq!((top[0]["product_id"].as_i64(), top[0]["qty_sold"].as_i64()), (Some(veg), Some(3)));
+        assert_eq!((top[1]["product_id"].as_i64(), top[1]["qty_sold"].as_i64()), (Some(meat), Some(1)));
+
+        let cats = app.ok("GET", "/reports/category-sales", Value::Null)["rows"].clone();
+        assert_eq!(cats.as_array().unwrap().len(), 2);
+        assert_eq!((cats[0]["category"].as_str(), cats[0]["revenue_cents"].as_i64()), (Some("Veg"), Some(3000)));
+        assert_eq!((cats[1]["category"].as_str(), cats[1]["revenue_cents"].as_i64()), (Some("Meat"), Some(2000)));
+    }
+
+    #[test]
+    fn bootstrap_reports_the_migrated_schema_version() {
+        let app = TestApp::new();
+        let boot = app.ok("GET", "/bootstrap", Value::Null);
+        assert_eq!(boot["schema_version"], json!(MIGRATIONS.len()));
+        assert!(boot["categories"].is_array());
+        assert!(boot["settings"].is_object());
+    }
+
+    #[test]
+    fn kot_delta_prints_only_changes_for_one_held_order() {
+        let app = TestApp::new();
+        let held = app.ok("POST", "/held-orders", json!({ "label": "T4", "items": [{ "product_id": 1, "qty": 1 }] }))["id"].as_i64().unwrap();
+        let previous = json!({ "held_order_id": held, "items": [{ "name": "Naan", "qty": 2 }, { "name": "Dal", "qty": 1 }, { "name": "Lassi", "qty": 1 }] });
+        let current = json!({ "held_order_id": held, "items": [{ "name": "Naan", "qty": 3 }, { "name": "Dal", "qty": 1 }, { "name": "Tea", "qty": 2 }] });
+
+        let res = app.ok("POST", "/print/kot-delta", json!({ "previous": previous, "current": current }));
+        assert_eq!(res["lines"], json!(3));
+        let text = app.printed_text().pop().unwrap();
+        assert!(text.contains("1 x Naan") && text.contains("2 x Tea") && text.contains("1 x CANCEL Lassi"));
+        assert!(!text.contains("Dal"));
+        assert!(text.contains("T4"));
+
+        let other = json!({ "held_order_id": held + 1, "items": [{ "name": "Naan", "qty": 3 }] });
+        let err = app.call("POST", "/print/kot-delta", Some(json!({ "previous": previous, "current": other }))).unwrap_err();
+        assert_eq!(err.code(), "validation");
+        let same = app.call("POST", "/print/kot-delta", Some(json!({ "previous": previous, "current": previous })));
+        assert_eq!(same.unwrap_err().code(), "validation");
+        assert_eq!(app.printed_text().len(), 1);
+    }
+
+    #[test]
+    fn csv_import_with_a_bad_row_commits_nothing() {
+        let app = TestApp::new();
+        let csv = "item_no,name,category,price_cents,is_available\n1,Tea,Drinks,1000,1\n,\"Dosa, masala\",Tiffin,abc,1\n";
+        let err = app.call("POST", "/products/import", Some(json!({ "csv": csv }))).unwrap_err();
+        assert_eq!(err.code(), "validation");
+        assert!(err.message().contains("line 3: bad price"));
+        assert_eq!(app.db(|c| c.query_row("SELECT COUNT(*) FROM products", [], |r| r.get::<_, i64>(0))).unwrap(), 0);
+
+        let ok = app.ok("POST", "/products/import", json!({ "csv": "item_no,name,category,price_cents,is_available\n,\"Dosa, masala\",Tiffin,6000,1\n" }));
+        assert_eq!(ok["inserted"], json!(1));
+        let name: String = app.db(|c| c.query_row("SELECT name FROM products WHERE item_no = 1", [], |r| r.get(0))).unwrap();
+        assert_eq!(name, "Dosa, masala");
+
+        // Auto-numbering shares the 9999 bound with POST /products.
+        app.ok("POST", "/products", json!({ "name": "Last", "price_cents": 100, "item_no": 9999 }));
+        let err = app.call("POST", "/products/import", Some(json!({ "csv": ",Extra,,100,1\n" }))).unwrap_err();
+        assert!(err.message().contains("Item No overflow"));
+    }
+
+    #[test]
+    fn product_export_escapes_and_round_trips() {
+        let app = TestApp::new();
+        app.ok("POST", "/products", json!({ "name": "Chicken, Spicy \"Hot\"", "price_cents": 25000, "category": "Mains", "item_no": 1 }));
+        let off = app.ok("POST", "/products", json!({ "name": "Soup", "price_cents": 9000, "item_no": 2 }))["id"].as_i64().unwrap();
+        app.db(|c| c.execute("UPDATE products SET is_available = 0 WHERE id = ?1", params![off])).unwrap();
+
+        let csv = app.ok("GET", "/products/export", Value::Null)["csv"].as_str().unwrap().to_string();
+        assert_eq!(csv, "item_no,name,category,price_cents,is_available\r\n1,\"Chicken, Spicy \"\"Hot\"\"\",Mains,25000,1\r\n2,Soup,,9000,0\r\n");
+        let available = app.ok("GET", "/products/export?available_only=1", Value::Null)["csv"].as_str().unwrap().to_string();
+        assert!(!available.contains("Soup"));
+
+        let fields: Vec<Vec<String>> = parse_csv(&csv).into_iter().map(|(_, f)| f).collect();
+        assert_eq!(fields[1][1], "Chicken, Spicy \"Hot\"");
+    }
+
+    #[test]
+    fn rupees_convert_to_the_cent_a_person_expects() {
+        assert_eq!(rupees_to_cents(0.1 + 0.2), 30);
+        assert_eq!(rupees_to_cents(1.005), 101);
+        assert_eq!(rupees_to_cents(19.99), 1999);
+        assert_eq!(rupees_to_cents(-0.1 - 0.2), -30);
+
+        let app = TestApp::new();
+        let p = app.ok("POST", "/products", json!({ "name": "Tea", "price_rupees": 0.1 + 0.2 }));
+        assert_eq!(p["price_cents"], json!(30));
+        // Explicit cents win over rupees.
+        let p = app.ok("POST", "/products", json!({ "name": "Coffee", "price_cents": 45, "price_rupees": 99.0 }));
+        assert_eq!(p["price_cents"], json!(45));
+    }
+
+    fn payload(items: Value) -> ReceiptPayload {
+        serde_json::from_value(json!({
+            "billNo": "MNE000001", "printedAt": "2026-01-01 12:00:00", "subtotalCents": 0,
+            "discountRateBps": 0, "discountCents": 0, "totalCents": 0, "items": items,
+        })).unwrap()
+    }
+
+    #[test]
+    fn receipt_columns_line_up_with_multibyte_names() {
+        let cur = Currency { symbol: "Rs".to_string(), separator: String::new() };
+        let p = payload(json!([
+            { "name": "Café au lait", "qty": 2, "unitPriceCents": 12000, "lineTotalCents": 24000 },
+            { "name": "இட்லி", "qty": 1, "unitPriceCents": 4000, "lineTotalCents": 4000 },
+            { "name": "Plain Dosa", "qty": 1, "unitPriceCents": 6000, "lineTotalCents": 6000 },
+        ]));
+        for w in [32, 48] {
+            let text = format_receipt(&p, w, &cur);
+            let lines: Vec<&str> = text.lines().collect();
+            let sep_len = display_width(lines.iter().find(|l| l.starts_with("---")).unwrap());
+            for name in ["Café", "இட்லி", "Plain"] {
+                let line = lines.iter().find(|l| l.starts_with(name)).unwrap();
+                assert_eq!(display_width(line), sep_len, "{:?} at width {}", line, w);
+            }
+        }
+    }
+
+    #[test]
+    fn card_surcharge_applies_only_to_card_bills() {
+        let app = TestApp::new();
+        app.ok("POST", "/settings/import", json!({ "settings": { "card_surcharge_bps": 200 } }));
+        let pid = app.ok("POST", "/products", json!({ "name": "Biryani", "price_cents": 20000, "tax_rate_bps": 500 }))["id"].as_i64().unwrap();
+        let cart = |method: &str| json!({ "items": [{ "product_id": pid, "product_name": "Biryani", "unit_price_cents": 20000, "qty": 1 }], "payment_mode": method });
+
+        let cash = app.ok("POST", "/bills", cart("cash"));
+        let card = app.ok("POST", "/bills", cart("card"));
+        assert_eq!((cash["surcharge_cents"].as_i64(), cash["total_cents"].as_i64()), (Some(0), Some(21000)));
+        // 2% of 210.00; the tax is unchanged because the surcharge is not taxable.
+        assert_eq!((card["surcharge_cents"].as_i64(), card["total_cents"].as_i64()), (Some(420), Some(21420)));
+        assert_eq!(card["total_tax_cents"], cash["total_tax_cents"]);
+
+        // Split: only the 110.00 on the card is surcharged, and the card row records what was charged.
+        let split = app.ok("POST", "/bills", json!({
+            "items": [{ "product_id": pid, "product_name": "Biryani", "unit_price_cents": 20000, "qty": 1 }],
+            "payments": [{ "method": "cash", "amount_cents": 10000 }, { "method": "card", "amount_cents": 11000 }],
+        }));
+        assert_eq!((split["surcharge_cents"].as_i64(), split["total_cents"].as_i64()), (Some(220), Some(21220)));
+        let detail = app.ok("GET", &format!("/bills/{}", app.bill_id(&split)), Value::Null);
+        assert_eq!(detail["payments"], json!([{ "method": "cash", "amount_cents": 10000 }, { "method": "card", "amount_cents": 11220 }]));
+
+        let id = app.bill_id(&card);
+        let cur = Currency { symbol: "Rs".to_string(), separator: String::new() };
+        assert!(app.db(|c| format_receipt(&receipt_from_bill(c, id).unwrap(), 48, &cur)).contains("Card Surcharge"));
+        let capped = app.ok("POST", "/settings/import", json!({ "settings": { "card_surcharge_bps": 5000 } }));
+        assert_eq!(capped["skipped"][0]["key"], json!("card_surcharge_bps"));
+    }
+
+    #[test]
+    fn percent_decode_reassembles_utf8() {
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        assert_eq!(percent_decode("Br%C3%B6tchen+roll"), "Brötchen roll");
+        assert_eq!(percent_decode("100%25"), "100%");
+        let (_, qs) = parse_qs("/products/search?q=caf%C3%A9");
+        assert_eq!(qs.get("q").map(String::as_str), Some("café"));
+    }
+
+    #[test]
+    fn voided_bills_drop_out_of_metrics_but_stay_listed() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        app.sell(pid, 1);
+        let bill = app.sell(pid, 2);
+        assert_eq!(app.ok("GET", "/metrics", Value::Null)["bills"], json!(2));
+
+        app.ok("POST", &format!("/bills/{}/void", bill), json!({ "reason": "wrong table" }));
+        assert_eq!(app.ok("GET", "/metrics", Value::Null)["bills"], json!(1));
+        let listed = app.ok("GET", "/bills", Value::Null)["rows"].clone();
+        let voided = listed.as_array().unwrap().iter().find(|b| b["id"] == json!(bill)).unwrap();
+        assert_eq!((voided["voided"].as_bool(), voided["void_reason"].as_str()), (Some(true), Some("wrong table")));
+
+        let again = app.call("POST", &format!("/bills/{}/void", bill), None).unwrap_err();
+        assert_eq!(again.code(), "conflict");
+    }
+
+    #[test]
+    fn turnaround_averages_held_orders_by_type() {
+        let app = TestApp::new();
+        let pid = app.product("Thali", 15000);
+        let items = json!([{ "product_id": pid, "product_name": "Thali", "unit_price_cents": 15000, "qty": 1 }]);
+        let hold = |minutes_ago: i64| {
+            let id = app.ok("POST", "/held-orders", json!({ "label": "T1", "items": items }))["id"].as_i64().unwrap();
+            app.db(|c| c.execute("UPDATE held_orders SET created_at = datetime('now','localtime', ?1) WHERE id = ?2", params![format!("-{} minutes", minutes_ago), id])).unwrap();
+            id
+        };
+        let (a, b, c) = (hold(30), hold(50), hold(10));
+        app.ok("POST", "/bills", json!({ "items": items, "held_order_id": a, "table_no": "4" }));
+        app.ok("POST", "/bills", json!({ "items": items, "held_order_id": b, "table_no": "7" }));
+        app.ok("POST", "/bills", json!({ "items": items, "held_order_id": c }));
+        // Never held, so it has no turnaround at all.
+        app.sell(pid, 1);
+
+        let rows = app.ok("GET", "/reports/turnaround", Value::Null)["rows"].clone();
+        assert_eq!(rows, json!([
+            { "order_type": "dine_in", "bill_count": 2, "avg_minutes": 40.0 },
+            { "order_type": "takeaway", "bill_count": 1, "avg_minutes": 10.0 },
+        ]));
+        // Settled orders leave the held list.
+        assert_eq!(app.ok("GET", "/held-orders", Value::Null)["rows"], json!([]));
+        assert_eq!(app.call("POST", "/bills", Some(json!({ "items": items, "held_order_id": a }))).unwrap_err().code(), "not_found");
+    }
+
+    #[test]
+    fn line_discounts_reduce_line_and_subtotal() {
+        let app = TestApp::new();
+        let combo = app.product("Combo", 5000);
+        let drink = app.product("Drink", 2000);
+        let bill = app.ok("POST", "/bills", json!({ "items": [
+            { "product_id": combo, "product_name": "Combo", "unit_price_cents": 5000, "qty": 2 },
+            { "product_id": drink, "product_name": "Drink", "unit_price_cents": 2000, "qty": 2, "discount_cents": 4000 },
+        ] }));
+        let id = app.bill_id(&bill);
+        let (subtotal, lines): (i64, Vec<(i64, i64)>) = app.db(|c| {
+            let subtotal = c.query_row("SELECT subtotal_cents FROM bills WHERE id = ?1", params![id], |r| r.get(0)).unwrap();
+            let mut stmt = c.prepare("SELECT discount_cents, line_total_cents FROM bill_items WHERE bill_id = ?1 ORDER BY id").unwrap();
+            let lines = stmt.query_map(params![id], |r| Ok((r.get(0)?, r.get(1)?))).unwrap().map(|r| r.unwrap()).collect();
+            (subtotal, lines)
+        });
+        assert_eq!(lines, vec![(0, 10000), (4000, 0)]);
+        assert_eq!(subtotal, 10000);
+    }
+
+    #[test]
+    fn csv_reports_keep_amounts_beyond_i32() {
+        assert_eq!(cents_to_rs(-5), "-0.05");
+        assert_eq!(cents_to_rs(i64::MIN), "-92233720368547758.08");
+        let rows = vec![json!({ "day": "2026-03-01", "revenue_cents": 3_000_000_000i64, "bill_count": 2 })];
+        assert_eq!(rows_to_csv(&rows), "bill_count,day,revenue\r\n2,2026-03-01,30000000.00\r\n");
+    }
+
+    #[test]
+    fn cash_bills_record_change_and_reject_short_tender() {
+        let app = TestApp::new();
+        let pid = app.product("Meals", 12050);
+        let cart = |extra: Value| {
+            let mut b = json!({ "items": [{ "product_id": pid, "product_name": "Meals", "unit_price_cents": 12050, "qty": 1 }] });
+            b.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            b
+        };
+        let bill = app.ok("POST", "/bills", cart(json!({ "payment_mode": "cash", "amount_tendered_cents": 20000 })));
+        assert_eq!(bill["change_cents"], json!(7950));
+        let detail = app.ok("GET", &format!("/bills/{}", app.bill_id(&bill)), Value::Null);
+        assert_eq!((detail["payment_mode"].as_str(), detail["amount_tendered_cents"].as_i64(), detail["change_cents"].as_i64()), (Some("cash"), Some(20000), Some(7950)));
+
+        let short = app.call("POST", "/bills", Some(cart(json!({ "payment_mode": "cash", "amount_tendered_cents": 10000 })))).unwrap_err();
+        assert_eq!(short.message(), "Amount tendered is less than the bill total");
+        let bad = app.call("POST", "/bills", Some(cart(json!({ "payment_mode": "cheque" })))).unwrap_err();
+        assert_eq!(bad.code(), "validation");
+
+        // The older payment_method name lands in the same column, and non-cash never counts as till cash.
+        let upi = app.ok("POST", "/bills", cart(json!({ "payment_method": "upi", "amount_tendered_cents": 20000 })));
+        assert_eq!(upi["change_cents"], Value::Null);
+        let (mode, cash): (String, i64) = app.db(|c| c.query_row("SELECT payment_mode, split_cash_cents FROM bills WHERE bill_no = ?1", params![upi["bill_no"].as_str()], |r| Ok((r.get(0)?, r.get(1)?)))).unwrap();
+        assert_eq!((mode.as_str(), cash), ("upi", 0));
+    }
+
+    #[test]
+    fn v0_database_migrates_to_the_current_schema() {
+        let app = TestApp::new();
+        let path = app.dir.join("v0.db");
+        let old = Connection::open(&path).unwrap();
+        // The schema as first released, before schema_version existed.
+        old.execute_batch(
+            "CREATE TABLE settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+             INSERT INTO settings(key, value) VALUES ('bill_seq', '1');
+             CREATE TABLE categories (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL UNIQUE, is_active INTEGER NOT NULL DEFAULT 1);
+             CREATE TABLE products (id INTEGER PRIMARY KEY AUTOINCREMENT, item_no INTEGER, name TEXT NOT NULL, category_id INTEGER, price_cents INTEGER NOT NULL,
+                 is_available INTEGER NOT NULL DEFAULT 1, created_at TEXT NOT NULL DEFAULT (datetime('now','localtime')), updated_at TEXT NOT NULL DEFAULT (datetime('now','localtime')));
+             CREATE TABLE bills (id INTEGER PRIMARY KEY AUTOINCREMENT, bill_no TEXT NOT NULL UNIQUE, subtotal_cents INTEGER NOT NULL, discount_rate_bps INTEGER NOT NULL DEFAULT 0,
+                 discount_cents INTEGER NOT NULL DEFAULT 0, payment_mode TEXT NOT NULL DEFAULT 'cash', split_cash_cents INTEGER NOT NULL DEFAULT 0,
+                 split_online_cents INTEGER NOT NULL DEFAULT 0, total_cents INTEGER NOT NULL, created_at TEXT NOT NULL DEFAULT (datetime('now','localtime')));
+             CREATE TABLE bill_items (id INTEGER PRIMARY KEY AUTOINCREMENT, bill_id INTEGER NOT NULL, product_id INTEGER NOT NULL, product_name TEXT NOT NULL,
+                 unit_price_cents INTEGER NOT NULL, qty INTEGER NOT NULL, line_total_cents INTEGER NOT NULL);
+             INSERT INTO products(item_no, name, price_cents) VALUES (1, 'Tea', 1000);
+             INSERT INTO bills(bill_no, subtotal_cents, payment_mode, total_cents) VALUES ('OLD1', 1000, 'upi', 1000);
+             INSERT INTO bill_items(bill_id, product_id, product_name, unit_price_cents, qty, line_total_cents) VALUES (1, 1, 'Tea', 1000, 1, 1000);",
+        ).unwrap();
+        drop(old);
+
+        let conn = init_db(&path).unwrap();
+        assert_eq!(get_setting(&conn, "schema_version", "0"), MIGRATIONS.len().to_string());
+        for table in MIGRATED_TABLES {
+            let migrated = table_columns(&conn, table).unwrap();
+            let missing: Vec<String> = app.db(|c| table_columns(c, table).unwrap()).into_iter().filter(|col| !migrated.contains(col)).collect();
+            assert!(missing.is_empty(), "{table} is missing {missing:?}");
+        }
+        let mode: String = conn.query_row("SELECT payment_mode FROM bills WHERE bill_no = 'OLD1'", [], |r| r.get(0)).unwrap();
+        assert_eq!(mode, "upi");
+        assert!(add_column(&conn, "settings", "extra", "TEXT").is_err());
+    }
+
+    #[test]
+    fn daily_report_aggregates_only_the_requested_day() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        let bills = [app.sell(pid, 1), app.sell(pid, 2), app.sell(pid, 4)];
+        app.db(|c| {
+            for (id, day) in bills.iter().zip(["2026-03-01 09:00:00", "2026-03-01 21:30:00", "2026-03-02 00:00:05"]) {
+                c.execute("UPDATE bills SET created_at = ?1 WHERE id = ?2", params![day, id]).unwrap();
+            }
+        });
+        let day = app.ok("GET", "/reports/daily?date=2026-03-01", Value::Null);
+        assert_eq!((day["bill_count"].as_i64(), day["total_cents"].as_i64()), (Some(2), Some(3000)));
+        let next = app.ok("GET", "/reports/daily?date=2026-03-02", Value::Null);
+        assert_eq!((next["bill_count"].as_i64(), next["total_cents"].as_i64()), (Some(1), Some(4000)));
+        assert_eq!(app.call("GET", "/reports/daily?date=03/01/2026", None).unwrap_err().code(), "validation");
+    }
+
+    #[test]
+    fn training_bills_stay_out_of_reports_and_the_list() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        app.sell(pid, 1);
+        let trn = app.ok("POST", "/bills", json!({ "items": [{ "product_id": pid, "product_name": "Tea", "unit_price_cents": 1000, "qty": 5 }], "is_training": true }));
+        assert!(trn["bill_no"].as_str().unwrap().starts_with("TRN"));
+
+        let daily = app.ok("GET", "/reports/daily", Value::Null);
+        assert_eq!((daily["bill_count"].as_i64(), daily["total_cents"].as_i64()), (Some(1), Some(1000)));
+        let with = app.ok("GET", "/reports/daily?include_training=1", Value::Null);
+        assert_eq!(with["bill_count"], json!(2));
+
+        assert_eq!(app.ok("GET", "/bills", Value::Null)["total"], json!(1));
+        assert_eq!(app.ok("GET", "/bills?include_training=1", Value::Null)["total"], json!(2));
+    }
+
+    #[test]
+    fn top_products_orders_by_quantity_and_honours_limit() {
+        let app = TestApp::new();
+        let tea = app.product("Tea", 1000);
+        let dosa = app.product("Dosa", 6000);
+        app.sell(tea, 2);
+        app.sell(dosa, 1);
+        app.sell(tea, 3);
+
+        let rows = app.ok("GET", "/reports/top-products", Value::Null)["rows"].clone();
+        assert_eq!(rows[0], json!({ "product_id": tea, "product_name": "Tea", "qty_sold": 5, "revenue_cents": 5000 }));
+        assert_eq!((rows[1]["product_id"].as_i64(), rows[1]["qty_sold"].as_i64()), (Some(dosa), Some(1)));
+        assert_eq!(app.ok("GET", "/reports/top-products?limit=1", Value::Null)["rows"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn scheduled_backup_runs_when_due_and_reports_failures() {
+        let app = TestApp::new();
+        app.ok("POST", "/settings/import", json!({ "settings": { "backup_interval_minutes": 1 } }));
+        app.db(|c| set_setting(c, "last_backup_at", "0"));
+        scheduled_backup_tick(&app.state);
+        assert_eq!(list_backups(&app.state.backup_dir, false).len(), 1);
+        // last_backup_at was just stamped, so the next tick has nothing to do.
+        scheduled_backup_tick(&app.state);
+        assert_eq!(list_backups(&app.state.backup_dir, false).len(), 1);
+
+        let blocked = app.dir.join("not-a-dir");
+        fs::write(&blocked, b"x").unwrap();
+        app.db(|c| {
+            set_setting(c, "last_backup_at", "0");
+            set_setting(c, "backup_path", &blocked.to_string_lossy());
+        });
+        scheduled_backup_tick(&app.state);
+        let errors = app.state.errors.lock().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0]["method"].as_str(), errors[0]["code"].as_str()), (Some("SCHEDULE"), Some("internal")));
+    }
+
+    #[test]
+    fn backup_run_prunes_to_the_retention_count() {
+        let app = TestApp::new();
+        let dir = app.state.backup_dir.clone();
+        create_dir_all(&dir).unwrap();
+        for i in 0..35 {
+            fs::write(dir.join(format!("meet-eat-20200101_0000{:02}.db", i)), b"old").unwrap();
+        }
+        fs::write(dir.join("my-notes.db"), b"keep").unwrap();
+
+        app.ok("POST", "/backup/settings", json!({ "backup_retention_count": 30 }));
+        let file = app.ok("POST", "/backup/run", Value::Null)["file"].as_str().unwrap().to_string();
+
+        let ours: Vec<String> = read_dir(&dir).unwrap().flatten().map(|e| e.file_name().to_string_lossy().to_string())
+            .filter(|n| n.starts_with("meet-eat-") && !n.ends_with(".sha256")).collect();
+        assert_eq!(ours.len(), 30);
+        assert!(Path::new(&file).exists());
+        // The six oldest went; files we didn't write are never touched.
+        assert!(!dir.join("meet-eat-20200101_000005.db").exists());
+        assert!(dir.join("meet-eat-20200101_000006.db").exists());
+        assert!(dir.join("my-notes.db").exists());
+    }
+
+    #[test]
+    fn compressed_backup_restores_to_the_same_bills() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        app.sell(pid, 1);
+        app.sell(pid, 2);
+        app.ok("POST", "/backup/settings", json!({ "backup_compress": true }));
+        let file = app.ok("POST", "/backup/run", Value::Null)["file"].as_str().unwrap().to_string();
+        assert!(file.ends_with(".db.gz"));
+        app.sell(pid, 3);
+
+        app.ok("POST", "/backup/restore", json!({ "source": file }));
+        let bills: i64 = app.db(|c| c.query_row("SELECT COUNT(*) FROM bills", [], |r| r.get(0))).unwrap();
+        assert_eq!(bills, 2);
+        assert_eq!(app.ok("GET", "/bills", Value::Null)["total"], json!(2));
+    }
+
+    #[test]
+    fn corrupt_backup_is_refused_before_restore() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        app.sell(pid, 1);
+        let file = app.ok("POST", "/backup/run", Value::Null)["file"].as_str().unwrap().to_string();
+        let listed = app.ok("GET", "/backup/files?verify=1", Value::Null)["files"][0].clone();
+        assert_eq!(listed["verified"], json!(true));
+
+        let mut bytes = fs::read(&file).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        fs::write(&file, bytes).unwrap();
+        assert_eq!(app.ok("GET", "/backup/files?verify=1", Value::Null)["files"][0]["verified"], json!(false));
+
+        app.sell(pid, 1);
+        let err = app.call("POST", "/backup/restore", Some(json!({ "source": file }))).unwrap_err();
+        assert!(err.message().contains("checksum mismatch"));
+        // The live database was left alone.
+        assert_eq!(app.db(|c| c.query_row("SELECT COUNT(*) FROM bills", [], |r| r.get::<_, i64>(0))).unwrap(), 2);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn lpstat_output_gives_queue_names() {
+        let out = "Rugtek_RP80 accepting requests since Mon 02 Mar 2026 10:00:00 IST\n\nKitchen accepting requests since Tue 03 Mar 2026 09:00:00 IST\n";
+        assert_eq!(parse_lpstat(out), vec!["Rugtek_RP80".to_string(), "Kitchen".to_string()]);
+        assert!(parse_lpstat("").is_empty());
+    }
+
+    #[test]
+    fn receipt_fits_both_paper_widths() {
+        let cur = Currency { symbol: "Rs".to_string(), separator: String::new() };
+        let mut p = payload(json!([
+            { "name": "Chicken Biryani Family Pack Extra Raita", "qty": 12, "unitPriceCents": 123456, "lineTotalCents": 1481472 },
+            { "name": "Tea", "qty": 1, "unitPriceCents": 1000, "lineTotalCents": 1000 },
+        ]));
+        p.subtotal_cents = 1482472;
+        p.discount_rate_bps = 1000;
+        p.discount_cents = 148247;
+        p.total_cents = 1334225;
+        for w in [NARROW_WIDTH, RECEIPT_WIDTH] {
+            let text = format_receipt(&p, w, &cur);
+            for line in text.split("\r\n") {
+                assert!(display_width(line) <= w, "{:?} is wider than {}", line, w);
+            }
+            assert!(text.split("\r\n").any(|l| l.starts_with("TOTAL") && display_width(l) == w));
+        }
+
+        let app = TestApp::new();
+        app.ok("POST", "/settings/import", json!({ "settings": { "receipt_width": 32 } }));
+        let preview = app.ok("POST", "/print/preview", json!({ "billNo": "X1", "printedAt": "now", "subtotalCents": 1000, "discountRateBps": 0, "discountCents": 0, "totalCents": 1000, "items": [] }));
+        assert_eq!(preview["width"], json!(32));
+    }
+
+    fn position(hay: &[u8], needle: &[u8]) -> Option<usize> {
+        hay.windows(needle.len()).position(|w| w == needle)
+    }
+
+    #[test]
+    fn qr_prints_below_the_total_in_command_order() {
+        let app = TestApp::new();
+        app.ok("POST", "/settings/import", json!({ "settings": { "receipt_qr_enabled": true, "receipt_qr_base_url": "https://receipts.example.com/{bill_no}" } }));
+        let cfg = app.db(load_print_config);
+        let raw = build_receipt_bytes(&payload(json!([])), &cfg);
+
+        let url = b"https://receipts.example.com/MNE000001";
+        let store = [0x1D, 0x28, 0x6B, (url.len() + 3) as u8, 0x00, 0x31, 0x50, 0x30];
+        let steps: Vec<usize> = [
+            &b"TOTAL"[..],
+            &[0x1D, 0x28, 0x6B, 0x04, 0x00, 0x31, 0x41, 0x32, 0x00],
+            &[0x1D, 0x28, 0x6B, 0x03, 0x00, 0x31, 0x43, 0x06],
+            &store,
+            url,
+            &[0x1D, 0x28, 0x6B, 0x03, 0x00, 0x31, 0x51, 0x30],
+            cfg.cut.bytes(),
+        ].iter().map(|n| position(&raw, n).unwrap_or_else(|| panic!("missing {:02X?}", n))).collect();
+        assert!(steps.windows(2).all(|p| p[0] < p[1]), "out of order: {:?}", steps);
+
+        app.ok("POST", "/settings/import", json!({ "settings": { "receipt_qr_enabled": false } }));
+        assert_eq!(position(&build_receipt_bytes(&payload(json!([])), &app.db(load_print_config)), &[0x1D, 0x28, 0x6B]), None);
+    }
+
+    #[test]
+    fn cash_drawer_opens_only_when_enabled() {
+        let app = TestApp::new();
+        let mut cash = payload(json!([]));
+        cash.payment_mode = "cash".to_string();
+        let mut card = payload(json!([]));
+        card.payment_mode = "card".to_string();
+
+        let off = app.db(load_print_config);
+        assert_eq!(position(&build_receipt_bytes(&cash, &off), &CASH_DRAWER_PULSE), None);
+
+        app.ok("POST", "/settings/import", json!({ "settings": { "cash_drawer_enabled": true } }));
+        let on = app.db(load_print_config);
+        let raw = build_receipt_bytes(&cash, &on);
+        let pulse = position(&raw, &CASH_DRAWER_PULSE).expect("drawer pulse");
+        assert!(pulse < position(&raw, on.cut.bytes()).unwrap());
+        assert_eq!(position(&build_receipt_bytes(&card, &on), &CASH_DRAWER_PULSE), None);
+
+        // Two copies still open the drawer once.
+        do_print(&app.printed, "Counter", &cash, &on, 2, false).unwrap();
+        let sent = app.printed.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 2);
+        assert!(position(&sent[0].1, &CASH_DRAWER_PULSE).is_some());
+        assert_eq!(position(&sent[1].1, &CASH_DRAWER_PULSE), None);
+
+        app.ok("POST", "/cash-drawer/open", json!({ "printerName": "Counter" }));
+        assert_eq!(app.printed.0.lock().unwrap().last().unwrap(), &("Counter".to_string(), [&[0x1B, 0x40][..], &CASH_DRAWER_PULSE].concat()));
+    }
+
+    #[test]
+    fn preview_returns_the_receipt_text_without_printing() {
+        let app = TestApp::new();
+        let preview = app.ok("POST", "/print/preview", json!({
+            "billNo": "MNE000042", "printedAt": "2026-01-01 12:00:00", "subtotalCents": 24000,
+            "discountRateBps": 0, "discountCents": 0, "totalCents": 24000,
+            "items": [{ "name": "Mutton Biryani", "qty": 2, "unitPriceCents": 12000, "lineTotalCents": 24000 }],
+        }));
+        let text = preview["text"].as_str().unwrap();
+        let total = format!("TOTAL{}Rs 240.00", " ".repeat(RECEIPT_WIDTH - 5 - 9));
+        assert!(text.split("\r\n").any(|l| l == total), "{}", text);
+        assert_eq!(preview["lines"].as_u64(), Some(text.split("\r\n").count() as u64));
+        assert!(app.printed.0.lock().unwrap().is_empty());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn lp_gets_the_printer_and_raw_option() {
+        let args = lp_args("Rugtek_RP80", Path::new("/tmp/meateat_1.txt"));
+        assert_eq!(args, ["-d", "Rugtek_RP80", "-o", "raw", "/tmp/meateat_1.txt"]);
+    }
+
+    #[test]
+    fn backup_names_carry_a_local_timestamp() {
+        let ts = simple_ts();
+        let parsed = chrono::NaiveDateTime::parse_from_str(&ts, "%Y%m%d_%H%M%S").unwrap();
+        assert!((chrono::Local::now().naive_local() - parsed).num_seconds().abs() < 60);
+
+        let app = TestApp::new();
+        let first = app.db(|c| do_backup(c, &app.state.db_path, &app.state.backup_dir)).unwrap();
+        std::thread::sleep(Duration::from_millis(1100));
+        let second = app.db(|c| do_backup(c, &app.state.db_path, &app.state.backup_dir)).unwrap();
+        assert_ne!(first, second);
+        for p in [&first, &second] {
+            let name = Path::new(p).file_name().unwrap().to_string_lossy().into_owned();
+            let ts = name.strip_prefix("meet-eat-").and_then(|n| n.strip_suffix(".db")).unwrap();
+            assert!(chrono::NaiveDateTime::parse_from_str(ts, "%Y%m%d_%H%M%S").is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn bills_filter_and_group_by_cashier() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        for (cashier, qty) in [("Anu", 1), ("Ravi", 2), ("Anu", 3)] {
+            app.ok("POST", "/bills", json!({ "items": [{ "product_id": pid, "product_name": "Tea", "unit_price_cents": 1000, "qty": qty }], "cashier": cashier }));
+        }
+        let anu = app.ok("GET", "/bills?cashier=Anu", Value::Null);
+        assert_eq!(anu["total"], json!(2));
+        assert!(anu["rows"].as_array().unwrap().iter().all(|b| b["cashier"] == json!("Anu")));
+        assert_eq!(app.ok("GET", "/bills?cashier=Ravi", Value::Null)["total"], json!(1));
+        assert_eq!(app.ok("GET", "/bills?cashier=Nobody", Value::Null)["total"], json!(0));
+
+        let daily = app.ok("GET", "/reports/daily", Value::Null);
+        assert_eq!(daily["by_cashier"], json!([
+            { "cashier": "Anu", "bill_count": 2, "total_cents": 4000 },
+            { "cashier": "Ravi", "bill_count": 1, "total_cents": 2000 },
+        ]));
+    }
+
+    #[test]
+    fn dine_in_bills_are_found_by_table() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        let items = json!([{ "product_id": pid, "product_name": "Tea", "unit_price_cents": 1000, "qty": 1 }]);
+        let dine_in = app.ok("POST", "/bills", json!({ "items": items, "table_no": "T5" }));
+        app.ok("POST", "/bills", json!({ "items": items }));
+
+        let found = app.ok("GET", "/bills?table_no=T5", Value::Null);
+        assert_eq!(found["total"], json!(1));
+        assert_eq!(found["rows"][0]["bill_no"], dine_in["bill_no"]);
+        assert_eq!(found["rows"][0]["table_no"], json!("T5"));
+        let detail = app.ok("GET", &format!("/bills/{}", app.bill_id(&dine_in)), Value::Null);
+        assert_eq!(detail["table_no"], json!("T5"));
+        assert_eq!(app.ok("GET", "/bills", Value::Null)["total"], json!(2));
+    }
+
+    #[test]
+    fn held_order_round_trip() {
+        let app = TestApp::new();
+        let items = json!([{ "product_id": 3, "product_name": "Dosa", "unit_price_cents": 6000, "qty": 2 }]);
+        let id = app.ok("POST", "/held-orders", json!({ "label": "T2", "items": items }))["id"].as_i64().unwrap();
+        app.ok("POST", "/held-orders", json!({ "items": items }));
+
+        let list = app.ok("GET", "/held-orders", Value::Null)["rows"].clone();
+        assert_eq!(list.as_array().unwrap().len(), 2);
+        assert_eq!((list[0]["label"].clone(), list[0]["item_count"].clone()), (json!("T2"), json!(1)));
+        assert_eq!(list[1]["label"], json!("Held order"));
+
+        let recalled = app.ok("GET", &format!("/held-orders/{}", id), Value::Null);
+        assert_eq!(recalled["items"], items);
+
+        app.ok("DELETE", &format!("/held-orders/{}", id), Value::Null);
+        assert_eq!(app.call("GET", &format!("/held-orders/{}", id), None).unwrap_err().code(), "not_found");
+        assert_eq!(app.call("DELETE", &format!("/held-orders/{}", id), None).unwrap_err().code(), "not_found");
+        assert_eq!(app.ok("GET", "/held-orders", Value::Null)["rows"].as_array().unwrap().len(), 1);
+        assert_eq!(app.call("POST", "/held-orders", Some(json!({ "items": [] }))).unwrap_err().code(), "validation");
+    }
+
+    #[test]
+    fn mixed_gst_rates_round_each_line() {
+        let app = TestApp::new();
+        let mut items = Vec::new();
+        for (name, price, rate) in [("Idli", 1010, 500), ("Vada", 1010, 500), ("Dosa", 1010, 500), ("Cola", 1050, 1800)] {
+            let pid = app.ok("POST", "/products", json!({ "name": name, "price_cents": price, "tax_rate_bps": rate }))["id"].as_i64().unwrap();
+            items.push(json!({ "product_id": pid, "product_name": name, "unit_price_cents": price, "qty": 1 }));
+        }
+        // 5% of 10.10 is 0.505 and rounds up on every line: 3 x 0.51, where the pooled 30.30 would give 1.52.
+        let bill = app.ok("POST", "/bills", json!({ "items": items }));
+        assert_eq!((bill["total_tax_cents"].as_i64(), bill["total_cents"].as_i64()), (Some(342), Some(4422)));
+
+        let detail = app.ok("GET", &format!("/bills/{}", app.bill_id(&bill)), Value::Null);
+        assert_eq!(detail["taxes"], json!([
+            { "rate_bps": 500, "taxable_cents": 3030, "tax_cents": 153, "cgst_cents": 76, "sgst_cents": 77 },
+            { "rate_bps": 1800, "taxable_cents": 1050, "tax_cents": 189, "cgst_cents": 94, "sgst_cents": 95 },
+        ]));
+        assert!(detail["items"].as_array().unwrap().iter().take(3).all(|it| it["tax_cents"] == json!(51)));
+
+        let mut p = payload(json!([]));
+        p.taxes = detail["taxes"].as_array().unwrap().iter()
+            .map(|t| ReceiptTax { rate_bps: t["rate_bps"].as_i64().unwrap() as i32, tax_cents: t["tax_cents"].as_i64().unwrap() as i32 })
+            .collect();
+        let text = format_receipt(&p, RECEIPT_WIDTH, &Currency { symbol: "Rs".to_string(), separator: String::new() });
+        for (label, amount) in [("CGST (2.50%)", "Rs 0.76"), ("SGST (2.50%)", "Rs 0.77"), ("CGST (9.00%)", "Rs 0.94"), ("SGST (9.00%)", "Rs 0.95")] {
+            assert!(text.split("\r\n").any(|l| l.starts_with(label) && l.ends_with(amount)), "{} {}", label, text);
+        }
+    }
+
+    #[test]
+    fn overselling_rolls_back_the_whole_bill() {
+        let app = TestApp::new();
+        let special = app.product("Mutton Special", 30000);
+        let tea = app.product("Tea", 1000);
+        app.ok("POST", &format!("/products/{}/stock", tea), json!({ "delta": 10, "reason": "delivery" }));
+        app.ok("POST", &format!("/products/{}/stock", special), json!({ "delta": 3, "reason": "prepared" }));
+
+        // Each line alone fits; together the special is oversold by one.
+        let err = app.call("POST", "/bills", Some(json!({ "items": [
+            { "product_id": tea, "product_name": "Tea", "unit_price_cents": 1000, "qty": 2 },
+            { "product_id": special, "product_name": "Mutton Special", "unit_price_cents": 30000, "qty": 2 },
+            { "product_id": special, "product_name": "Mutton Special", "unit_price_cents": 30000, "qty": 2 },
+        ] }))).unwrap_err();
+        assert_eq!(err, ApiError::Validation("Insufficient stock for Mutton Special (3 available, 4 ordered)".to_string()));
+        assert_eq!(app.db(|c| c.query_row("SELECT COUNT(*) FROM bills", [], |r| r.get::<_, i64>(0))).unwrap(), 0);
+        assert_eq!(app.db(|c| c.query_row("SELECT COUNT(*) FROM bill_items", [], |r| r.get::<_, i64>(0))).unwrap(), 0);
+        let stock = |pid: i64| app.db(|c| c.query_row("SELECT stock_qty FROM products WHERE id = ?1", params![pid], |r| r.get::<_, i64>(0))).unwrap();
+        assert_eq!((stock(tea), stock(special)), (10, 3));
+
+        app.sell(special, 3);
+        assert_eq!(stock(special), 0);
+    }
+
+    #[test]
+    fn metrics_list_only_products_at_or_below_threshold() {
+        let app = TestApp::new();
+        let mut ids = Vec::new();
+        for (name, stock, threshold) in [("Paneer", Some(2), 5), ("Rice", Some(10), 5), ("Water", None, 5)] {
+            let p = app.ok("POST", "/products", json!({ "name": name, "price_cents": 1000 }));
+            let id = p["id"].as_i64().unwrap();
+            app.ok("PUT", &format!("/products/{}", id), json!({ "name": name, "price_cents": 1000, "item_no": p["item_no"], "low_stock_threshold": threshold }));
+            if let Some(n) = stock {
+                app.ok("POST", &format!("/products/{}/stock", id), json!({ "delta": n }));
+            }
+            ids.push(id);
+        }
+        let metrics = app.ok("GET", "/metrics", Value::Null);
+        assert_eq!(metrics["low_stock"], json!([{ "id": ids[0], "name": "Paneer", "stock_qty": 2, "threshold": 5 }]));
+    }
+
+    #[test]
+    fn margins_use_the_cost_at_sale_time() {
+        let app = TestApp::new();
+        let p = app.ok("POST", "/products", json!({ "name": "Biryani", "price_cents": 20000, "cost_cents": 12000 }));
+        let pid = p["id"].as_i64().unwrap();
+        app.sell(pid, 3);
+        app.ok("PUT", &format!("/products/{}", pid), json!({ "name": "Biryani", "price_cents": 20000, "item_no": p["item_no"], "cost_cents": 15000 }));
+        app.sell(pid, 1);
+
+        // 4 x 200.00 revenue against 3 x 120.00 + 1 x 150.00 cost.
+        let report = app.ok("GET", "/reports/margins", Value::Null);
+        let row = &report["rows"][0];
+        assert_eq!((row["revenue_cents"].as_i64(), row["cost_cents"].as_i64(), row["margin_cents"].as_i64()), (Some(80000), Some(51000), Some(29000)));
+        assert_eq!(row["margin_pct"].as_f64(), Some(36.25));
+        assert_eq!(report["margin_cents"], json!(29000));
+    }
+
+    #[test]
+    fn deleted_products_hide_until_restored() {
+        let app = TestApp::new();
+        let p = app.ok("POST", "/products", json!({ "name": "Falooda", "price_cents": 9000 }));
+        let id = p["id"].as_i64().unwrap();
+        app.ok("POST", &format!("/products/{}/stock", id), json!({ "delta": 1 }));
+        app.ok("PUT", &format!("/products/{}", id), json!({ "name": "Falooda", "price_cents": 9000, "item_no": p["item_no"], "low_stock_threshold": 5 }));
+        let visible = |app: &TestApp| {
+            let names = |v: Value| v.as_array().unwrap().iter().any(|r| r["name"] == json!("Falooda"));
+            (
+                names(app.ok("GET", "/products", Value::Null)),
+                names(app.ok("GET", "/products/search?q=Falo", Value::Null)),
+                app.ok("GET", "/products/export", Value::Null)["csv"].as_str().unwrap().contains("Falooda"),
+                names(app.ok("GET", "/metrics", Value::Null)["low_stock"].clone()),
+            )
+        };
+        assert_eq!(visible(&app), (true, true, true, true));
+
+        app.ok("DELETE", &format!("/products/{}", id), Value::Null);
+        assert_eq!(visible(&app), (false, false, false, false));
+        let all = app.ok("GET", "/products?include_deleted=1", Value::Null);
+        assert!(all.as_array().unwrap().iter().any(|r| r["id"] == json!(id) && r["deleted_at"].is_string()));
+        let edit = app.call("PUT", &format!("/products/{}", id), Some(json!({ "name": "Falooda Royal", "price_cents": 12000 }))).unwrap_err();
+        assert_eq!(edit.code(), "not_found");
+        let sale = app.call("POST", "/bills", Some(json!({ "items": [{ "product_id": id, "product_name": "Falooda", "unit_price_cents": 9000, "qty": 1 }] }))).unwrap_err();
+        assert_eq!(sale, ApiError::Validation("Falooda has been deleted and can no longer be sold".to_string()));
+
+        app.ok("POST", &format!("/products/{}/restore", id), Value::Null);
+        assert_eq!(visible(&app), (true, true, true, true));
+        assert_eq!(app.call("POST", &format!("/products/{}/restore", id), None).unwrap_err().code(), "conflict");
+    }
+
+    #[test]
+    fn scanning_a_barcode_finds_exactly_one_product() {
+        let app = TestApp::new();
+        app.ok("POST", "/products", json!({ "name": "Cola 500ml", "price_cents": 4000, "barcode": "8901030865278" }));
+        app.ok("POST", "/products", json!({ "name": "Cola 1L", "price_cents": 7000, "barcode": "8901030865285" }));
+
+        let hits = app.ok("GET", "/products/search?q=8901030865278", Value::Null);
+        assert_eq!(hits.as_array().unwrap().len(), 1);
+        assert_eq!(hits[0]["name"], json!("Cola 500ml"));
+
+        let dup = app.call("POST", "/products", Some(json!({ "name": "Cola Zero", "price_cents": 4000, "barcode": "8901030865278" }))).unwrap_err();
+        assert_eq!(dup, ApiError::Conflict("Barcode already in use".to_string()));
+    }
+
+    #[test]
+    fn bulk_price_raises_one_category_and_respects_the_cap() {
+        let app = TestApp::new();
+        let lassi = app.ok("POST", "/products", json!({ "name": "Lassi", "price_cents": 4990, "category": "Drinks" }))["id"].as_i64().unwrap();
+        let soda = app.ok("POST", "/products", json!({ "name": "Soda", "price_cents": 2000, "category": "Drinks" }))["id"].as_i64().unwrap();
+        let thali = app.product("Thali", 15000);
+        let price = |pid: i64| app.db(|c| c.query_row("SELECT price_cents FROM products WHERE id = ?1", params![pid], |r| r.get::<_, i64>(0))).unwrap();
+
+        let res = app.ok("POST", "/products/bulk-price", json!({ "percent_bps": 500, "category": "Drinks" }));
+        assert_eq!(res["updated"], json!(2));
+        // 49.90 * 1.05 = 52.395 rounds to 52.40.
+        assert_eq!((price(lassi), price(soda), price(thali)), (5240, 2100, 15000));
+
+        app.db(|c| c.execute("UPDATE products SET price_cents = ?1 WHERE id = ?2", params![MAX_PRICE_CENTS - 100, thali])).unwrap();
+        let err = app.call("POST", "/products/bulk-price", Some(json!({ "percent_bps": 1000 }))).unwrap_err();
+        assert_eq!(err.code(), "validation");
+        assert_eq!((price(lassi), price(soda)), (5240, 2100));
+        assert_eq!(app.call("POST", "/products/bulk-price", Some(json!({ "percent_bps": -10_001 }))).unwrap_err().code(), "validation");
+    }
+
+    fn category_id(app: &TestApp, name: &str) -> i64 {
+        app.db(|c| c.query_row("SELECT id FROM categories WHERE name = ?1", params![name], |r| r.get(0))).unwrap()
+    }
+
+    #[test]
+    fn category_rename_collides_and_deactivate_hides_from_pickers() {
+        let app = TestApp::new();
+        let pid = app.ok("POST", "/products", json!({ "name": "Lassi", "price_cents": 4000, "category": "Drinks" }))["id"].as_i64().unwrap();
+        app.ok("POST", "/products", json!({ "name": "Dosa", "price_cents": 6000, "category": "Tiffin" }));
+        let drinks = category_id(&app, "Drinks");
+
+        let err = app.call("PUT", &format!("/categories/{}", drinks), Some(json!({ "name": "Tiffin" }))).unwrap_err();
+        assert_eq!(err, ApiError::Conflict("A category named 'Tiffin' already exists".to_string()));
+        app.ok("PUT", &format!("/categories/{}", drinks), json!({ "name": "Beverages" }));
+
+        app.ok("PUT", &format!("/categories/{}/active", drinks), json!({ "is_active": false }));
+        let names = |q: &str| app.ok("GET", q, Value::Null).as_array().unwrap().iter().map(|c| c["name"].as_str().unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(names("/categories?active_only=1"), ["Tiffin"]);
+        assert!(names("/categories").contains(&"Beverages".to_string()));
+        // Products in an inactive category still sell.
+        app.sell(pid, 1);
+    }
+
+    #[test]
+    fn merging_categories_moves_products_and_drops_the_source() {
+        let app = TestApp::new();
+        for (name, cat) in [("Lassi", "Drinks"), ("Soda", "Drinks"), ("Tea", "Drink")] {
+            app.ok("POST", "/products", json!({ "name": name, "price_cents": 2000, "category": cat }));
+        }
+        let (into, from) = (category_id(&app, "Drinks"), category_id(&app, "Drink"));
+        assert_eq!(app.call("POST", "/categories/merge", Some(json!({ "from_id": into, "into_id": into }))).unwrap_err().code(), "validation");
+
+        let res = app.ok("POST", "/categories/merge", json!({ "from_id": from, "into_id": into }));
+        assert_eq!(res["moved"], json!(1));
+        let cats: Vec<i64> = app.db(|c| c.prepare("SELECT category_id FROM products").unwrap().query_map([], |r| r.get(0)).unwrap().map(|r| r.unwrap()).collect());
+        assert_eq!(cats, [into, into, into]);
+        let left: i64 = app.db(|c| c.query_row("SELECT COUNT(*) FROM categories WHERE id = ?1", params![from], |r| r.get(0))).unwrap();
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn reorder_sets_the_category_order() {
+        let app = TestApp::new();
+        for (name, cat) in [("Lassi", "Drinks"), ("Dosa", "Tiffin"), ("Kheer", "Desserts")] {
+            app.ok("POST", "/products", json!({ "name": name, "price_cents": 2000, "category": cat }));
+        }
+        let names = |v: Value| v.as_array().unwrap().iter().map(|c| c["name"].as_str().unwrap().to_string()).collect::<Vec<_>>();
+        // New categories go to the end in creation order, not alphabetically.
+        assert_eq!(names(app.ok("GET", "/categories", Value::Null)), ["Drinks", "Tiffin", "Desserts"]);
+
+        let ids = json!([category_id(&app, "Tiffin"), category_id(&app, "Desserts"), category_id(&app, "Drinks")]);
+        assert_eq!(names(app.ok("POST", "/categories/reorder", ids)), ["Tiffin", "Desserts", "Drinks"]);
+        assert_eq!(names(app.ok("GET", "/categories", Value::Null)), ["Tiffin", "Desserts", "Drinks"]);
+        assert_eq!(app.call("POST", "/categories/reorder", Some(json!([999]))).unwrap_err().code(), "not_found");
+    }
+
+    #[test]
+    fn integrity_check_passes_on_a_fresh_database() {
+        let app = TestApp::new();
+        assert_eq!(app.ok("GET", "/maintenance/integrity", Value::Null), json!({ "ok": true, "issues": [] }));
+
+        app.db(|c| c.execute_batch("PRAGMA foreign_keys = OFF; INSERT INTO bill_items(bill_id,product_id,product_name,unit_price_cents,qty,line_total_cents) VALUES(404,1,'Ghost',100,1,100); PRAGMA foreign_keys = ON;")).unwrap();
+        let report = app.ok("GET", "/maintenance/integrity", Value::Null);
+        assert_eq!(report["ok"], json!(false));
+        let issues = report["issues"].as_array().unwrap();
+        assert!(issues.iter().any(|i| i["check"] == json!("foreign_key") && i["table"] == json!("bill_items") && i["parent"] == json!("bills")), "{:?}", issues);
+    }
+
+    #[test]
+    fn optimize_reports_sizes_before_and_after() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        for _ in 0..20 { app.sell(pid, 1); }
+        app.db(|c| c.execute("DELETE FROM bill_items", [])).unwrap();
+        let res = app.ok("POST", "/maintenance/optimize", Value::Null);
+        let (before, after) = (res["size_before_bytes"].as_u64().unwrap(), res["size_after_bytes"].as_u64().unwrap());
+        assert!(before > 0 && after > 0 && after <= before, "{} -> {}", before, after);
+        assert!(res["warning"].is_null());
+        assert_eq!(app.ok("GET", "/maintenance/integrity", Value::Null)["ok"], json!(true));
+    }
+
+    #[test]
+    fn reprint_rebuilds_the_stored_bill() {
+        let app = TestApp::new();
+        let dosa = app.ok("POST", "/products", json!({ "name": "Dosa", "price_cents": 6000, "tax_rate_bps": 500 }))["id"].as_i64().unwrap();
+        let tea = app.product("Tea", 1000);
+        let bill = app.ok("POST", "/bills", json!({ "items": [
+            { "product_id": dosa, "product_name": "Dosa", "unit_price_cents": 6000, "qty": 2 },
+            { "product_id": tea, "product_name": "Tea", "unit_price_cents": 1000, "qty": 3 },
+        ], "discount_rate_bps": 1000, "payment_mode": "cash" }));
+        let id = app.bill_id(&bill);
+
+        let p = app.db(|c| receipt_from_bill(c, id)).unwrap();
+        let (bill_no, created_at, subtotal, dc, total): (String, String, i32, i32, i32) = app.db(|c| c.query_row(
+            "SELECT bill_no, created_at, subtotal_cents, discount_cents, total_cents FROM bills WHERE id = ?1", params![id],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?)),
+        )).unwrap();
+        assert_eq!((p.bill_no.as_str(), p.printed_at.as_str()), (bill_no.as_str(), created_at.as_str()));
+        assert_eq!((p.subtotal_cents, p.discount_rate_bps, p.discount_cents, p.total_cents), (subtotal, 1000, dc, total));
+        assert_eq!(p.items.iter().map(|it| (it.name.as_str(), it.qty, it.unit_price_cents, it.line_total_cents)).collect::<Vec<_>>(), [("Dosa", 2, 6000, 12000), ("Tea", 3, 1000, 3000)]);
+        assert_eq!(p.taxes.iter().map(|t| (t.rate_bps, t.tax_cents)).collect::<Vec<_>>(), [(500, 540)]);
+        // No payment mode, so a reprint never opens the drawer.
+        assert!(p.reprint && p.payment_mode.is_empty());
+
+        app.ok("POST", &format!("/bills/{}/reprint", id), json!({ "printerName": "Counter" }));
+        let printed = app.printed_text();
+        assert_eq!(printed.len(), 1);
+        assert!(printed[0].contains("*** REPRINT ***") && printed[0].contains(&bill_no));
+    }
+
+    #[test]
+    fn bill_numbers_follow_prefix_and_width_settings() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        let bill_no = |app: &TestApp| {
+            let id = app.sell(pid, 1);
+            app.db(|c| c.query_row("SELECT bill_no FROM bills WHERE id = ?1", params![id], |r| r.get::<_, String>(0))).unwrap()
+        };
+        assert_eq!(bill_no(&app), "MNE-000001");
+
+        app.ok("POST", "/settings/import", json!({ "settings": { "bill_prefix": "REST", "bill_pad_width": 4 } }));
+        assert_eq!(bill_no(&app), "REST-0002");
+        app.db(|c| c.execute("UPDATE settings SET value = '0' WHERE key = 'bill_seq'", [])).unwrap();
+        assert_eq!(bill_no(&app), "REST-0001");
+
+        app.ok("POST", "/settings/import", json!({ "settings": { "bill_no_date": true } }));
+        let today = chrono::Local::now().format("%Y%m%d").to_string();
+        assert_eq!(bill_no(&app), format!("REST-{}-0002", today));
+    }
+
+    #[test]
+    fn daily_reset_restarts_the_sequence_on_a_new_day() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        app.ok("POST", "/settings/import", json!({ "settings": { "bill_seq_reset": "daily" } }));
+        let today = chrono::Local::now().format("%Y%m%d").to_string();
+        let bill_no = |app: &TestApp| {
+            let id = app.sell(pid, 1);
+            app.db(|c| c.query_row("SELECT bill_no FROM bills WHERE id = ?1", params![id], |r| r.get::<_, String>(0))).unwrap()
+        };
+        // Yesterday's counter stopped at 41.
+        app.db(|c| c.execute_batch("UPDATE settings SET value = '41' WHERE key = 'bill_seq'; INSERT OR REPLACE INTO settings(key,value) VALUES('bill_seq_date','20000101');")).unwrap();
+        assert_eq!(bill_no(&app), format!("MNE-{}-000001", today));
+        assert_eq!(bill_no(&app), format!("MNE-{}-000002", today));
+        assert_eq!(app.db(|c| get_setting(c, "bill_seq_date", "")), today);
+    }
+
+    #[test]
+    fn customers_dedupe_by_phone_and_attach_to_bills() {
+        let app = TestApp::new();
+        let asha = app.ok("POST", "/customers", json!({ "name": "Asha", "phone": "98400 12345" }));
+        assert_eq!((asha["phone"].as_str(), asha["created"].as_bool()), (Some("9840012345"), Some(true)));
+        let again = app.ok("POST", "/customers", json!({ "name": "Asha K", "phone": "9840012345" }));
+        assert_eq!((again["id"].clone(), again["created"].as_bool()), (asha["id"].clone(), Some(false)));
+        assert_eq!(app.call("POST", "/customers", Some(json!({ "name": "X", "phone": "12" }))).unwrap_err().code(), "validation");
+
+        let found = app.ok("GET", "/customers/search?q=0012", Value::Null);
+        assert_eq!(found.as_array().unwrap().len(), 1);
+        assert_eq!(found[0]["name"], json!("Asha"));
+
+        let pid = app.product("Tea", 1000);
+        let bill = app.ok("POST", "/bills", json!({ "items": [{ "product_id": pid, "product_name": "Tea", "unit_price_cents": 1000, "qty": 1 }], "customer_id": asha["id"] }));
+        let detail = app.ok("GET", &format!("/bills/{}", app.bill_id(&bill)), Value::Null);
+        assert_eq!(detail["customer"], json!({ "id": asha["id"], "name": "Asha", "phone": "9840012345" }));
+        let walk_in = app.sell(pid, 1);
+        assert!(app.ok("GET", &format!("/bills/{}", walk_in), Value::Null)["customer"].is_null());
+    }
+
+    #[test]
+    fn customer_history_totals_their_bills() {
+        let app = TestApp::new();
+        let regular = app.ok("POST", "/customers", json!({ "name": "Ravi", "phone": "9000000001" }))["id"].as_i64().unwrap();
+        let new = app.ok("POST", "/customers", json!({ "name": "Meena", "phone": "9000000002" }))["id"].as_i64().unwrap();
+        let pid = app.product("Thali", 15000);
+        let mut totals = 0;
+        for qty in [1, 3] {
+            let bill = app.ok("POST", "/bills", json!({ "items": [{ "product_id": pid, "product_name": "Thali", "unit_price_cents": 15000, "qty": qty }], "customer_id": regular }));
+            totals += bill["total_cents"].as_i64().unwrap();
+        }
+        app.sell(pid, 2);
+
+        let history = app.ok("GET", &format!("/customers/{}/bills", regular), Value::Null);
+        assert_eq!((history["total"].as_i64(), history["lifetime_bill_count"].as_i64()), (Some(2), Some(2)));
+        assert_eq!(history["lifetime_spend_cents"].as_i64(), Some(totals));
+        assert_eq!(totals, 60000);
+
+        let empty = app.ok("GET", &format!("/customers/{}/bills", new), Value::Null);
+        assert_eq!((empty["rows"].clone(), empty["total"].clone(), empty["lifetime_spend_cents"].clone()), (json!([]), json!(0), json!(0)));
+        assert_eq!(app.call("GET", "/customers/999/bills", None).unwrap_err().code(), "not_found");
+    }
+
+    #[test]
+    fn deleting_a_product_writes_one_audit_row() {
+        let app = TestApp::new();
+        let pid = app.product("Falooda", 9000);
+        let before = app.ok("GET", "/audit", Value::Null)["rows"].as_array().unwrap().len();
+        app.ok("DELETE", &format!("/products/{}", pid), Value::Null);
+
+        let rows = app.ok("GET", "/audit", Value::Null)["rows"].as_array().unwrap().clone();
+        assert_eq!(rows.len(), before + 1);
+        assert_eq!((rows[0]["action"].as_str(), rows[0]["entity"].as_str(), rows[0]["entity_id"].as_i64()), (Some("product.delete"), Some("product"), Some(pid)));
+        assert_eq!(rows[0]["detail"], json!({ "name": "Falooda" }));
+
+        app.ok("POST", &format!("/products/{}/restore", pid), Value::Null);
+        assert_eq!(app.ok("GET", "/audit?action=product.delete", Value::Null)["rows"].as_array().unwrap().len(), 1);
+        assert_eq!(app.ok("GET", "/audit?limit=1", Value::Null)["rows"][0]["action"], json!("product.restore"));
+    }
+
+    #[test]
+    fn discount_is_capped_by_the_setting() {
+        let app = TestApp::new();
+        let pid = app.product("Thali", 10000);
+        let cart = |bps: i64| json!({ "items": [{ "product_id": pid, "product_name": "Thali", "unit_price_cents": 10000, "qty": 1 }], "discount_rate_bps": bps });
+
+        assert_eq!(app.ok("POST", "/bills", cart(5000))["total_cents"], json!(5000));
+        let over = app.call("POST", "/bills", Some(cart(5001))).unwrap_err();
+        assert_eq!(over, ApiError::Validation("Discount must be between 0% and 50.00%".to_string()));
+        assert_eq!(app.call("POST", "/bills", Some(cart(-1))).unwrap_err().code(), "validation");
+
+        app.ok("POST", "/settings/import", json!({ "settings": { "max_discount_bps": 1000 } }));
+        assert_eq!(app.ok("POST", "/bills", cart(1000))["total_cents"], json!(9000));
+        assert_eq!(app.call("POST", "/bills", Some(cart(1001))).unwrap_err().code(), "validation");
+        assert_eq!(app.db(|c| c.query_row("SELECT COUNT(*) FROM bills", [], |r| r.get::<_, i64>(0))).unwrap(), 2);
+    }
+
+    #[test]
+    fn split_payments_must_reconcile_to_the_total() {
+        let app = TestApp::new();
+        let pid = app.product("Thali", 10000);
+        let cart = |payments: Value| json!({ "items": [{ "product_id": pid, "product_name": "Thali", "unit_price_cents": 10000, "qty": 1 }], "payments": payments });
+
+        let bill = app.ok("POST", "/bills", cart(json!([{ "method": "cash", "amount_cents": 6000 }, { "method": "card", "amount_cents": 4000 }])));
+        let detail = app.ok("GET", &format!("/bills/{}", app.bill_id(&bill)), Value::Null);
+        assert_eq!(detail["payment_mode"], json!("split"));
+        assert_eq!(detail["payments"], json!([{ "method": "cash", "amount_cents": 6000 }, { "method": "card", "amount_cents": 4000 }]));
+
+        let short = app.call("POST", "/bills", Some(cart(json!([{ "method": "cash", "amount_cents": 6000 }, { "method": "card", "amount_cents": 3000 }])))).unwrap_err();
+        assert_eq!(short, ApiError::Validation("Payments total 90.00 does not match bill total 100.00".to_string()));
+        assert_eq!(app.db(|c| c.query_row("SELECT COUNT(*) FROM bill_payments", [], |r| r.get::<_, i64>(0))).unwrap(), 2);
+    }
+
+    #[test]
+    fn tip_adds_to_the_total_and_prints_after_the_discount() {
+        let app = TestApp::new();
+        let pid = app.product("Thali", 10000);
+        let cart = |tip: i64| json!({ "items": [{ "product_id": pid, "product_name": "Thali", "unit_price_cents": 10000, "qty": 1 }], "discount_rate_bps": 1000, "tip_cents": tip });
+        let bill = app.ok("POST", "/bills", cart(500));
+        // 100.00 - 10.00 + 5.00
+        assert_eq!(bill["total_cents"], json!(9500));
+        assert_eq!(app.call("POST", "/bills", Some(cart(-1))).unwrap_err(), ApiError::Validation("Tip cannot be negative".to_string()));
+
+        let id = app.bill_id(&bill);
+        let p = app.db(|c| receipt_from_bill(c, id)).unwrap();
+        let text = format_receipt(&p, RECEIPT_WIDTH, &Currency { symbol: "Rs".to_string(), separator: String::new() });
+        let lines: Vec<&str> = text.split("\r\n").collect();
+        let at = |label: &str| lines.iter().position(|l| l.starts_with(label)).unwrap_or_else(|| panic!("no {} in {}", label, text));
+        assert!(at("Discount") < at("Tip") && at("Tip") < at("TOTAL"));
+        assert!(lines[at("Tip")].ends_with("Rs 5.00") && lines[at("TOTAL")].ends_with("Rs 95.00"));
+    }
+
+    #[test]
+    fn health_reports_a_missing_or_busy_database() {
+        let app = TestApp::new();
+        assert_eq!(app.ok("GET", "/health", Value::Null), json!({ "ok": true }));
+
+        let conn = app.state.db.lock().unwrap().take();
+        let down = app.ok("GET", "/health", Value::Null);
+        assert_eq!(down["ok"], json!(false));
+        assert_eq!(down["reason"]["message"], json!("Database not available"));
+        *app.state.db.lock().unwrap() = conn;
+
+        let _held = app.state.db.lock().unwrap();
+        let busy = db_health(&app.state).unwrap_err();
+        assert_eq!(busy, ApiError::Internal(format!("Database busy for over {} ms", HEALTH_LOCK_TIMEOUT_MS)));
+    }
+
+    #[test]
+    fn metrics_today_excludes_yesterday() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        app.sell(pid, 1);
+        app.sell(pid, 4);
+        let old = app.sell(pid, 10);
+        app.db(|c| c.execute("UPDATE bills SET created_at = datetime('now','localtime','-1 day') WHERE id = ?1", params![old])).unwrap();
+
+        let m = app.ok("GET", "/metrics", Value::Null);
+        assert_eq!((m["today_bill_count"].as_i64(), m["today_revenue_cents"].as_i64(), m["avg_order_value_cents"].as_i64()), (Some(2), Some(5000), Some(2500)));
+        assert_eq!(m["bills"], json!(3));
+        // On the 1st of the month yesterday belongs to last month.
+        let now = chrono::Local::now();
+        let same_month = (now - chrono::Duration::days(1)).format("%Y%m").to_string() == now.format("%Y%m").to_string();
+        assert_eq!(m["month_revenue_cents"].as_i64(), Some(if same_month { 15000 } else { 5000 }));
+    }
+
+    #[test]
+    fn price_edits_are_kept_in_order() {
+        let app = TestApp::new();
+        let p = app.ok("POST", "/products", json!({ "name": "Biryani", "price_cents": 12000 }));
+        let id = p["id"].as_i64().unwrap();
+        let edit = |price: i64| app.ok("PUT", &format!("/products/{}", id), json!({ "name": "Biryani", "price_cents": price, "item_no": p["item_no"] }));
+        edit(13000);
+        // Renaming or saving without a price change records nothing.
+        edit(13000);
+        edit(12500);
+
+        let rows = app.ok("GET", &format!("/products/{}/price-history", id), Value::Null)["rows"].clone();
+        let changes: Vec<(i64, i64)> = rows.as_array().unwrap().iter().map(|r| (r["old_price_cents"].as_i64().unwrap(), r["new_price_cents"].as_i64().unwrap())).collect();
+        assert_eq!(changes, [(12000, 13000), (13000, 12500)]);
+    }
+
+    #[test]
+    fn undo_last_voids_the_bill_and_restores_stock() {
+        let app = TestApp::new();
+        assert_eq!(app.call("POST", "/bills/undo-last", None).unwrap_err(), ApiError::NotFound("No bills to undo".to_string()));
+        let pid = app.product("Mutton Special", 30000);
+        app.ok("POST", &format!("/products/{}/stock", pid), json!({ "delta": 5 }));
+        let id = app.sell(pid, 2);
+        let bill_no: String = app.db(|c| c.query_row("SELECT bill_no FROM bills WHERE id = ?1", params![id], |r| r.get(0))).unwrap();
+
+        assert_eq!(app.ok("POST", "/bills/undo-last", Value::Null)["bill_no"], json!(bill_no));
+        let (voided, reason): (Option<String>, Option<String>) = app.db(|c| c.query_row("SELECT voided_at, void_reason FROM bills WHERE id = ?1", params![id], |r| Ok((r.get(0)?, r.get(1)?)))).unwrap();
+        assert!(voided.is_some());
+        assert_eq!(reason.as_deref(), Some("Undone"));
+        assert_eq!(app.db(|c| c.query_row("SELECT stock_qty FROM products WHERE id = ?1", params![pid], |r| r.get::<_, i64>(0))).unwrap(), 5);
+        assert_eq!(app.ok("GET", "/metrics", Value::Null)["bills"], json!(0));
+        let audit = app.ok("GET", "/audit?action=bill.undo", Value::Null)["rows"].clone();
+        assert_eq!((audit.as_array().unwrap().len(), audit[0]["detail"]["bill_no"].clone()), (1, json!(bill_no)));
+
+        // The next bill keeps counting instead of reusing the undone number.
+        let next = app.sell(pid, 1);
+        assert!(app.db(|c| c.query_row("SELECT bill_no FROM bills WHERE id = ?1", params![next], |r| r.get::<_, String>(0))).unwrap() > bill_no);
+        assert_eq!(app.call("POST", "/bills/undo-last", None).map(|_| ()), Ok(()));
+        assert_eq!(app.call("POST", "/bills/undo-last", None).unwrap_err().code(), "conflict");
+
+        app.sell(pid, 1);
+        app.db(|c| c.execute("UPDATE bills SET created_at = datetime('now','localtime','-10 minutes') WHERE voided_at IS NULL", [])).unwrap();
+        assert_eq!(app.call("POST", "/bills/undo-last", None).unwrap_err().code(), "validation");
+    }
+
+    #[test]
+    fn products_page_two_of_three() {
+        let app = TestApp::new();
+        for i in 1..=25 {
+            app.product(&format!("Item {:02}", i), 1000);
+        }
+        let page = app.ok("GET", "/products?page=2&limit=10", Value::Null);
+        assert_eq!(page["total"], json!(25));
+        let names: Vec<&str> = page["rows"].as_array().unwrap().iter().map(|r| r["name"].as_str().unwrap()).collect();
+        assert_eq!(names.len(), 10);
+        assert_eq!((names[0], names[9]), ("Item 11", "Item 20"));
+        assert_eq!(app.ok("GET", "/products?page=3&limit=10", Value::Null)["rows"].as_array().unwrap().len(), 5);
+        // Without paging params the bare array is still returned.
+        assert_eq!(app.ok("GET", "/products", Value::Null).as_array().unwrap().len(), 25);
+    }
+
+    #[test]
+    fn bills_filter_by_product_sold() {
+        let app = TestApp::new();
+        let fish = app.product("Fish Curry", 25000);
+        let tea = app.product("Tea", 1000);
+        // Fish on two lines of one bill must still list that bill once.
+        let with_fish = app.ok("POST", "/bills", json!({ "items": [
+            { "product_id": fish, "product_name": "Fish Curry", "unit_price_cents": 25000, "qty": 1 },
+            { "product_id": tea, "product_name": "Tea", "unit_price_cents": 1000, "qty": 1 },
+            { "product_id": fish, "product_name": "Fish Curry", "unit_price_cents": 25000, "qty": 1 },
+        ] }));
+        app.sell(tea, 2);
+
+        for q in [format!("/bills?product_id={}", fish), "/bills?product_name=fish".to_string()] {
+            let found = app.ok("GET", &q, Value::Null);
+            assert_eq!(found["total"], json!(1), "{}", q);
+            assert_eq!(found["rows"].as_array().unwrap().len(), 1);
+            assert_eq!(found["rows"][0]["bill_no"], with_fish["bill_no"]);
+        }
+        assert_eq!(app.ok("GET", &format!("/bills?product_id={}", tea), Value::Null)["total"], json!(2));
+    }
+
+    fn backdate(app: &TestApp, bill: i64, at: &str) {
+        app.db(|c| c.execute("UPDATE bills SET created_at = ?1 WHERE id = ?2", params![at, bill])).unwrap();
+    }
+
+    #[test]
+    fn revenue_series_fills_days_without_sales() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        backdate(&app, app.sell(pid, 1), "2026-03-01 10:00:00");
+        backdate(&app, app.sell(pid, 2), "2026-03-01 20:00:00");
+        backdate(&app, app.sell(pid, 5), "2026-03-03 12:00:00");
+
+        let series = app.ok("GET", "/reports/revenue-series?start=2026-03-01&end=2026-03-03", Value::Null);
+        assert_eq!(series, json!([
+            { "date": "2026-03-01", "total_cents": 3000, "bills": 2 },
+            { "date": "2026-03-02", "total_cents": 0, "bills": 0 },
+            { "date": "2026-03-03", "total_cents": 5000, "bills": 1 },
+        ]));
+        assert_eq!(app.call("GET", "/reports/revenue-series?start=01-03-2026", None).unwrap_err().code(), "validation");
+    }
+
+    #[test]
+    fn hourly_report_buckets_by_hour_of_day() {
+        let app = TestApp::new();
+        let pid = app.product("Meals", 10000);
+        backdate(&app, app.sell(pid, 1), "2026-03-01 13:05:00");
+        backdate(&app, app.sell(pid, 2), "2026-03-02 13:55:00");
+        backdate(&app, app.sell(pid, 3), "2026-03-02 20:30:00");
+
+        let rows = app.ok("GET", "/reports/hourly?start=2026-03-01&end=2026-03-02", Value::Null)["rows"].clone();
+        let rows = rows.as_array().unwrap();
+        assert_eq!(rows.len(), 24);
+        assert_eq!(rows[13], json!({ "hour": 13, "bill_count": 2, "revenue_cents": 30000 }));
+        assert_eq!(rows[20], json!({ "hour": 20, "bill_count": 1, "revenue_cents": 30000 }));
+        assert_eq!(rows.iter().map(|r| r["bill_count"].as_i64().unwrap()).sum::<i64>(), 3);
+    }
+
+    #[test]
+    fn bill_export_writes_header_and_escaped_rows() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        let first = app.sell(pid, 3);
+        app.db(|c| c.execute("UPDATE bills SET created_at = '2026-03-01 10:00:00', bill_no = 'T,\"1\"' WHERE id = ?1", params![first])).unwrap();
+        // Past i32::MAX cents, which used to wrap when narrowed.
+        let big = app.sell(pid, 1);
+        app.db(|c| c.execute("UPDATE bills SET created_at = '2026-03-01 11:00:00', subtotal_cents = 3000000000, total_cents = 3000000000 WHERE id = ?1", params![big])).unwrap();
+        backdate(&app, app.sell(pid, 1), "2026-03-05 10:00:00");
+
+        let csv = app.ok("GET", "/bills/export?start=2026-03-01&end=2026-03-01", Value::Null)["csv"].as_str().unwrap().to_string();
+        let lines: Vec<&str> = csv.split("\r\n").filter(|l| !l.is_empty()).collect();
+        assert_eq!(lines, [
+            "bill_no,created_at,subtotal,discount,tax,tip,surcharge,total,payment_mode",
+            "\"T,\"\"1\"\"\",2026-03-01 10:00:00,30.00,0.00,0.00,0.00,0.00,30.00,cash",
+            "MNE-000002,2026-03-01 11:00:00,30000000.00,0.00,0.00,0.00,0.00,30000000.00,cash",
+        ]);
+    }
+
+    #[test]
+    fn item_export_writes_one_row_per_line() {
+        let app = TestApp::new();
+        let dosa = app.ok("POST", "/products", json!({ "name": "Dosa, Masala", "price_cents": 6000, "tax_rate_bps": 500 }))["id"].as_i64().unwrap();
+        let tea = app.product("Tea", 1000);
+        let bill = app.ok("POST", "/bills", json!({ "items": [
+            { "product_id": dosa, "product_name": "Dosa, Masala", "unit_price_cents": 6000, "qty": 2 },
+            { "product_id": tea, "product_name": "Tea", "unit_price_cents": 1000, "qty": 1 },
+        ] }));
+        backdate(&app, app.bill_id(&bill), "2026-03-01 10:00:00");
+
+        let csv = app.ok("GET", "/bills/export-items?start=2026-03-01&end=2026-03-01", Value::Null)["csv"].as_str().unwrap().to_string();
+        let lines: Vec<&str> = csv.split("\r\n").filter(|l| !l.is_empty()).collect();
+        assert_eq!(lines, [
+            "bill_no,date,product_name,qty,unit_price,discount,tax_rate,tax,line_total",
+            "MNE-000001,2026-03-01 10:00:00,\"Dosa, Masala\",2,60.00,0.00,5.00,6.00,120.00",
+            "MNE-000001,2026-03-01 10:00:00,Tea,1,10.00,0.00,0.00,0.00,10.00",
+        ]);
+    }
+
+    #[test]
+    fn cut_mode_setting_picks_the_gs_v_command() {
+        let app = TestApp::new();
+        for (mode, cut) in [("full", &[0x1D, 0x56, 0x41, 0x03][..]), ("partial", &[0x1D, 0x56, 0x42, 0x03][..]), ("none", &[][..])] {
+            app.ok("POST", "/settings/import", json!({ "settings": { "cut_mode": mode } }));
+            let raw = build_receipt_bytes(&payload(json!([])), &app.db(load_print_config));
+            assert!(raw.ends_with(&[&b"\r\n\r\n\r\n"[..], cut].concat()), "{}", mode);
+            assert_eq!(position(&raw, &[0x1D, 0x56]).is_some(), !cut.is_empty(), "{}", mode);
+        }
+    }
+
+    #[test]
+    fn currency_symbol_and_separator_format_every_amount() {
+        let dollars = Currency { symbol: "$".to_string(), separator: ",".to_string() };
+        assert_eq!(format_money(123450, &dollars), "$1,234.50");
+        assert_eq!(format_money(-123450, &dollars), "-$1,234.50");
+        assert_eq!(format_money(123450, &Currency { symbol: "Rs".to_string(), separator: String::new() }), "Rs 1234.50");
+
+        let app = TestApp::new();
+        app.ok("POST", "/settings/import", json!({ "settings": { "currency_symbol": "₹", "thousands_separator": "," } }));
+        let cfg = app.db(load_print_config);
+        assert_eq!(cfg.currency.symbol, "Rs.");
+        let mut p = payload(json!([{ "name": "Family Feast", "qty": 1, "unitPriceCents": 123450, "lineTotalCents": 123450 }]));
+        p.subtotal_cents = 123450;
+        p.total_cents = 123450;
+        let raw = build_receipt_bytes(&p, &cfg);
+        assert_eq!(position(&raw, "₹".as_bytes()), None);
+        let text = String::from_utf8_lossy(&raw).into_owned();
+        let item = text.split("\r\n").find(|l| l.starts_with("Family Feast")).unwrap();
+        assert!(item.ends_with(" 1  1,234.50     1,234.50"), "{:?}", item);
+        assert!(text.split("\r\n").any(|l| l.starts_with("TOTAL") && l.ends_with("Rs.1,234.50")), "{}", text);
+    }
+
+    #[test]
+    fn logo_becomes_a_gs_v_0_raster_above_the_header() {
+        let app = TestApp::new();
+        // 10 x 2: left half black on the first row, all white on the second.
+        let mut img = image::GrayImage::from_pixel(10, 2, image::Luma([255]));
+        for x in 0..5 { img.put_pixel(x, 0, image::Luma([0])); }
+        let path = app.dir.join("logo.png");
+        img.save(&path).unwrap();
+        assert_eq!(raster_bytes(&img), [0x1D, 0x76, 0x30, 0x00, 0x02, 0x00, 0x02, 0x00, 0b1111_1000, 0x00, 0x00, 0x00]);
+
+        app.ok("POST", "/settings/import", json!({ "settings": { "receipt_logo_path": path.to_string_lossy() } }));
+        let raw = build_receipt_bytes(&payload(json!([])), &app.db(load_print_config));
+        let logo = position(&raw, &raster_bytes(&img)).expect("logo raster");
+        assert!(logo < position(&raw, b"Bill:").unwrap());
+
+        // A missing file falls back to the text header.
+        app.ok("POST", "/settings/import", json!({ "settings": { "receipt_logo_path": app.dir.join("gone.png").to_string_lossy() } }));
+        let raw = build_receipt_bytes(&payload(json!([])), &app.db(load_print_config));
+        assert_eq!(position(&raw, &[0x1D, 0x76, 0x30]), None);
+    }
+
+    #[test]
+    fn print_sends_the_requested_number_of_copies() {
+        let app = TestApp::new();
+        let body = |copies: Value| json!({ "printerName": "Counter", "copies": copies, "payload": {
+            "billNo": "MNE000007", "printedAt": "now", "subtotalCents": 1000, "discountRateBps": 0, "discountCents": 0, "totalCents": 1000, "items": [],
+        } });
+        app.ok("POST", "/print", body(json!(3)));
+        assert_eq!(app.printed.0.lock().unwrap().len(), 3);
+        app.printed.0.lock().unwrap().clear();
+
+        app.ok("POST", "/print", body(json!(50)));
+        assert_eq!(app.printed.0.lock().unwrap().len(), MAX_COPIES);
+        app.printed.0.lock().unwrap().clear();
+
+        app.ok("POST", "/print", body(Value::Null));
+        let sent = app.printed.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "Counter");
+        assert_eq!(app.db(|c| get_setting(c, "last_printer", "")), "Counter");
+    }
+
+    #[test]
+    fn do_print_sends_labelled_copies_through_the_transport() {
+        let mock = MockTransport::default();
+        let cfg = TestApp::new().db(load_print_config);
+        let p = payload(json!([{ "name": "Tea", "qty": 2, "unitPriceCents": 1000, "lineTotalCents": 2000 }]));
+        let receipt = build_receipt_bytes(&p, &cfg);
+
+        do_print(&mock, "Counter", &p, &cfg, 1, false).unwrap();
+        assert_eq!(*mock.0.lock().unwrap(), [("Counter".to_string(), receipt.clone())]);
+        mock.0.lock().unwrap().clear();
+
+        do_print(&mock, "Counter", &p, &cfg, 2, true).unwrap();
+        let sent = mock.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 2);
+        for ((printer, data), label) in sent.iter().zip(["CUSTOMER COPY", "MERCHANT COPY"]) {
+            assert_eq!(printer, "Counter");
+            let head = [&[0x1B, 0x40, 0x1B, 0x61, 0x01, 0x1B, 0x45, 0x01][..], label.as_bytes(), b"\r\n", &[0x1B, 0x45, 0x00, 0x1B, 0x61, 0x00]].concat();
+            assert_eq!(data, &[head, receipt.clone()].concat());
+        }
+    }
+
+    #[test]
+    fn kot_prints_quantities_without_prices() {
+        let app = TestApp::new();
+        app.ok("POST", "/print/kot", json!({ "printerName": "Kitchen", "table_no": "T3", "order_no": 42, "items": [
+            { "product_name": "Mutton Biryani", "qty": 2, "unit_price_cents": 30000, "line_total_cents": 60000 },
+            { "name": "Parotta", "qty": 4, "note": "extra salna" },
+            { "name": "Ghost", "qty": 0 },
+        ] }));
+        let sent = app.printed.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "Kitchen");
+        let raw = &sent[0].1;
+        assert!(position(raw, &[0x1D, 0x21, 0x11]).unwrap() < position(raw, b"2 x Mutton Biryani").unwrap());
+        let text = String::from_utf8_lossy(raw).into_owned();
+        for want in ["KITCHEN", "T3", "42", "2 x Mutton Biryani", "4 x Parotta", "(extra salna)"] {
+            assert!(text.contains(want), "missing {:?} in {}", want, text);
+        }
+        for unwanted in ["300.00", "600.00", "Rate", "Amount", "TOTAL", "Ghost"] {
+            assert!(!text.contains(unwanted), "unexpected {:?} in {}", unwanted, text);
+        }
+        assert_eq!(app.call("POST", "/print/kot", Some(json!({ "items": [] }))).unwrap_err().code(), "validation");
+    }
+
+    #[test]
+    fn product_prices_are_bounded_on_create_and_edit() {
+        let app = TestApp::new();
+        let neg = app.call("POST", "/products", Some(json!({ "name": "Tea", "price_cents": -1 }))).unwrap_err();
+        assert_eq!(neg.code(), "validation");
+        let over = app.call("POST", "/products", Some(json!({ "name": "Tea", "price_cents": MAX_PRICE_CENTS + 1 }))).unwrap_err();
+        assert_eq!(over, ApiError::Validation(format!("price_cents cannot exceed {}", MAX_PRICE_CENTS)));
+
+        let p = app.ok("POST", "/products", json!({ "name": "Wedding Platter", "price_cents": MAX_PRICE_CENTS }));
+        assert_eq!(p["price_cents"], json!(MAX_PRICE_CENTS));
+        let id = p["id"].as_i64().unwrap();
+        assert_eq!(app.call("PUT", &format!("/products/{}", id), Some(json!({ "name": "Wedding Platter", "price_cents": -500 }))).unwrap_err().code(), "validation");
+        assert_eq!(app.call("PUT", &format!("/products/{}", id), Some(json!({ "name": "Wedding Platter", "price_cents": MAX_PRICE_CENTS + 1 }))).unwrap_err().code(), "validation");
+        app.ok("PUT", &format!("/products/{}", id), json!({ "name": "Wedding Platter", "price_cents": 0, "item_no": p["item_no"] }));
+    }
+
+    #[test]
+    fn created_product_comes_back_with_id_and_item_no() {
+        let app = TestApp::new();
+        app.ok("POST", "/products", json!({ "name": "Tea", "price_cents": 1000, "item_no": 1 }));
+        let p = app.ok("POST", "/products", json!({ "name": "Lassi", "price_cents": 4000, "category": "Drinks" }));
+        let id = p["id"].as_i64().unwrap();
+        let stored: (String, i64) = app.db(|c| c.query_row("SELECT name, item_no FROM products WHERE id = ?1", params![id], |r| Ok((r.get(0)?, r.get(1)?)))).unwrap();
+        assert_eq!(stored, ("Lassi".to_string(), 2));
+        assert_eq!((p["item_no"].as_i64(), p["category"].as_str(), p["price_cents"].as_i64()), (Some(2), Some("Drinks"), Some(4000)));
+    }
+
+    #[test]
+    fn batch_update_skips_the_bad_row_and_commits_the_rest() {
+        let app = TestApp::new();
+        let tea = app.ok("POST", "/products", json!({ "name": "Tea", "price_cents": 1000, "item_no": 1 }))["id"].as_i64().unwrap();
+        let coffee = app.ok("POST", "/products", json!({ "name": "Coffee", "price_cents": 1500, "item_no": 2 }))["id"].as_i64().unwrap();
+        let lassi = app.ok("POST", "/products", json!({ "name": "Lassi", "price_cents": 4000, "item_no": 3 }))["id"].as_i64().unwrap();
+
+        // Each row commits on its own: the duplicate item_no fails alone.
+        let res = app.ok("PUT", "/products/batch", json!([
+            { "id": tea, "price_cents": 1200 },
+            { "id": coffee, "item_no": 1 },
+            { "id": lassi, "name": "Sweet Lassi" },
+        ]));
+        assert_eq!(res["updated"], json!(2));
+        assert_eq!(res["results"].as_array().unwrap().iter().map(|r| r["ok"].as_bool().unwrap()).collect::<Vec<_>>(), [true, false, true]);
+        assert_eq!(res["results"][1]["error"]["code"], json!("conflict"));
+
+        let row = |id: i64| app.db(|c| c.query_row("SELECT name, price_cents, item_no FROM products WHERE id = ?1", params![id], |r| Ok((r.get::<_, String>(0)?, r.get::<_, i64>(1)?, r.get::<_, i64>(2)?)))).unwrap();
+        assert_eq!(row(tea), ("Tea".to_string(), 1200, 1));
+        assert_eq!(row(coffee), ("Coffee".to_string(), 1500, 2));
+        // Fields left out of a patch stay as they were.
+        assert_eq!(row(lassi), ("Sweet Lassi".to_string(), 4000, 3));
+    }
+
+    fn search_names(app: &TestApp, q: &str) -> Vec<String> {
+        app.ok("GET", &format!("/products/search?{}", q), Value::Null).as_array().unwrap().iter().map(|r| r["name"].as_str().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn search_ranks_prefix_matches_first() {
+        let app = TestApp::new();
+        app.ok("POST", "/products", json!({ "name": "Butter Chicken Masala Chicken", "price_cents": 30000, "item_no": 1 }));
+        app.ok("POST", "/products", json!({ "name": "Tandoori Chicken", "price_cents": 28000, "item_no": 2 }));
+        app.ok("POST", "/products", json!({ "name": "Chicken Curry", "price_cents": 25000, "item_no": 3 }));
+        app.ok("POST", "/products", json!({ "name": "Chicken", "price_cents": 20000, "item_no": 4 }));
+        assert_eq!(search_names(&app, "q=chicken"), ["Chicken", "Chicken Curry", "Butter Chicken Masala Chicken", "Tandoori Chicken"]);
+        // An exact item number beats every name match.
+        assert_eq!(search_names(&app, "q=2")[0], "Tandoori Chicken");
+    }
+
+    #[test]
+    fn search_limit_is_clamped() {
+        let app = TestApp::new();
+        for i in 1..=30 {
+            app.product(&format!("Dosa {:02}", i), 5000);
+        }
+        assert_eq!(search_names(&app, "q=dosa&limit=5").len(), 5);
+        assert_eq!(search_names(&app, "q=dosa").len(), 20);
+        assert_eq!(search_names(&app, "q=dosa&limit=0").len(), 1);
+        assert_eq!(search_names(&app, "q=dosa&limit=500").len(), 30);
+        assert_eq!(search_names(&app, "q=dosa&limit=abc").len(), 20);
+        assert_eq!(search_names(&app, "q=dosa&limit=5"), search_names(&app, "q=dosa")[..5]);
+    }
+
+    #[test]
+    fn unavailable_products_are_searchable_only_on_request() {
+        let app = TestApp::new();
+        let pid = app.product("Seasonal Mango Lassi", 6000);
+        app.ok("PUT", &format!("/products/{}/availability", pid), json!({ "is_available": 0 }));
+
+        assert!(search_names(&app, "q=mango").is_empty());
+        let hits = app.ok("GET", "/products/search?q=mango&include_unavailable=1", Value::Null);
+        assert_eq!(hits.as_array().unwrap().len(), 1);
+        assert_eq!(hits[0]["is_available"], json!(0));
+    }
+
+    #[test]
+    fn json_export_round_trips_product_and_bill_counts() {
+        let app = TestApp::new();
+        let tea = app.product("Tea", 1000);
+        let coffee = app.product("Coffee", 1500);
+        app.sell(tea, 2);
+        app.sell(coffee, 1);
+        let split = app.ok("POST", "/bills", json!({
+            "items": [{ "product_id": tea, "product_name": "Tea", "unit_price_cents": 1000, "qty": 1 }],
+            "payments": [{ "method": "cash", "amount_cents": 400 }, { "method": "card", "amount_cents": 600 }],
+        }));
+        let split_id = app.bill_id(&split);
+
+        let text = serde_json::to_string(&app.ok("GET", "/export/json", Value::Null)).unwrap();
+        let doc: Value = serde_json::from_str(&text).unwrap();
+        let (products, bills): (i64, i64) = app.db(|c| c.query_row("SELECT (SELECT COUNT(*) FROM products), (SELECT COUNT(*) FROM bills)", [], |r| Ok((r.get(0)?, r.get(1)?)))).unwrap();
+        assert_eq!(doc["products"].as_array().unwrap().len() as i64, products);
+        assert_eq!(doc["bills"].as_array().unwrap().len() as i64, bills);
+        let exported = doc["bills"].as_array().unwrap().iter().find(|b| b["id"] == json!(split_id)).unwrap();
+        assert_eq!(exported["payments"].as_array().unwrap().len(), 2);
+        assert!(doc["customers"].is_array());
+        assert!(doc.get("held_orders").is_none());
+    }
+
+    #[test]
+    fn json_export_wipe_and_replace_import_restores_everything() {
+        let app = TestApp::new();
+        let tea = app.product("Tea", 1000);
+        app.sell(tea, 2);
+        let asha = app.ok("POST", "/customers", json!({ "name": "Asha", "phone": "9840012345" }));
+        let bill = app.ok("POST", "/bills", json!({
+            "items": [{ "product_id": tea, "product_name": "Tea", "unit_price_cents": 1000, "qty": 1 }],
+            "payments": [{ "method": "cash", "amount_cents": 400 }, { "method": "card", "amount_cents": 600 }],
+            "customer_id": asha["id"],
+        }));
+        let bill_id = app.bill_id(&bill);
+        let counts = |app: &TestApp| app.db(|c| {
+            ["categories", "products", "customers", "bills", "bill_items", "bill_payments"].map(|t| c.query_row(&format!("SELECT COUNT(*) FROM {t}"), [], |r| r.get::<_, i64>(0)).unwrap())
+        });
+        let before = counts(&app);
+        let doc = app.ok("GET", "/export/json", Value::Null);
+
+        app.db(|c| c.execute_batch("DELETE FROM bills; DELETE FROM stock_adjustments; DELETE FROM products; DELETE FROM categories; DELETE FROM customers;").unwrap());
+        assert_eq!(counts(&app), [0; 6]);
+        app.ok("POST", "/import/json", json!({ "mode": "replace", "data": doc }));
+        assert_eq!(counts(&app), before);
+        let detail = app.ok("GET", &format!("/bills/{}", bill_id), Value::Null);
+        assert_eq!(detail["payments"].as_array().unwrap().len(), 2);
+        assert_eq!(detail["customer"]["phone"], asha["phone"]);
+    }
+
+    #[test]
+    fn merge_import_rejects_items_for_unknown_products() {
+        let app = TestApp::new();
+        let doc = json!({
+            "format": JSON_EXPORT_FORMAT,
+            "bills": [{ "id": 1, "bill_no": "EXT1", "subtotal_cents": 1000, "total_cents": 1000,
+                "items": [{ "product_id": 99, "product_name": "Ghost", "unit_price_cents": 1000, "qty": 1, "line_total_cents": 1000 }] }],
+        });
+        let err = app.call("POST", "/import/json", Some(json!({ "mode": "merge", "data": doc }))).unwrap_err();
+        assert_eq!(err.message(), "Bill No EXT1 references product 99 which is not in the import");
+        assert_eq!(app.db(|c| c.query_row("SELECT COUNT(*) FROM bills", [], |r| r.get::<_, i64>(0))).unwrap(), 0);
+    }
+
+    #[test]
+    fn bills_since_skips_training_and_flags_voids() {
+        let app = TestApp::new();
+        let pid = app.product("Tea", 1000);
+        let sold = app.sell(pid, 1);
+        app.ok("POST", "/bills", json!({ "items": [{ "product_id": pid, "product_name": "Tea", "unit_price_cents": 1000, "qty": 1 }], "is_training": true }));
+        let voided = app.sell(pid, 2);
+        app.ok("POST", &format!("/bills/{}/void", voided), json!({ "reason": "wrong table" }));
+
+        let feed = app.ok("GET", "/bills/since?id=0", Value::Null);
+        let ids: Vec<i64> = feed["bills"].as_array().unwrap().iter().map(|b| b["id"].as_i64().unwrap()).collect();
+        assert_eq!(ids, vec![sold, voided]);
+        assert_eq!(feed["bills"][0]["voided_at"], Value::Null);
+        assert!(feed["bills"][1]["voided_at"].is_string());
+    }
+
+    #[test]
+    fn database_failures_surface_as_internal_errors() {
+        let conn = Connection::open_in_memory().unwrap();
+        assert_eq!(repair_item_nos(&conn).unwrap_err().code(), "internal");
+        assert_eq!(ApiError::from("Invalid id").code(), "validation");
+    }
+
+    #[test]
+    fn search_treats_percent_and_underscore_literally() {
+        let app = TestApp::new();
+        app.product("100% Juice", 8000);
+        app.product("Tea", 1000);
+        app.product("Cold_Coffee", 9000);
+        assert_eq!(search_names(&app, "q=%25"), vec!["100% Juice".to_string()]);
+        assert_eq!(search_names(&app, "q=_"), vec!["Cold_Coffee".to_string()]);
+        assert_eq!(search_names(&app, "q=T_a"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn json_import_checks_settings_and_matches_products_by_name() {
+        let app = TestApp::new();
+        let doc = json!({
+            "format": JSON_EXPORT_FORMAT,
+            "settings": { "bill_seq": "999", "discount_rate_bps": "99999", "bill_prefix": "XYZ" },
+            "products": [{ "id": 7, "item_no": null, "name": "Masala Chai", "price_cents": 1500 }],
+        });
+        let first = app.ok("POST", "/import/json", json!({ "mode": "merge", "data": doc }));
+        assert_eq!(first["imported"]["products"], json!(1));
+        let again = app.ok("POST", "/import/json", json!({ "mode": "merge", "data": doc }));
+        assert_eq!(again["imported"]["products"], json!(0));
+        assert_eq!(app.db(|c| c.query_row("SELECT COUNT(*) FROM products WHERE name = 'Masala Chai'", [], |r| r.get::<_, i64>(0))).unwrap(), 1);
+
+        app.ok("POST", "/held-orders", json!({ "label": "T4", "items": [{ "product_id": 1, "qty": 1 }] }));
+        let replaced = app.ok("POST", "/import/json", json!({ "mode": "replace", "data": doc }));
+        let skipped: Vec<&str> = replaced["skipped_settings"].as_array().unwrap().iter().map(|s| s["key"].as_str().unwrap()).collect();
+        assert_eq!(skipped, vec!["bill_seq", "discount_rate_bps"]);
+        app.db(|c| {
+            assert_eq!(get_setting(c, "bill_seq", ""), "0");
+            assert_eq!(get_setting(c, "discount_rate_bps", ""), "0");
+            assert_eq!(get_setting(c, "bill_prefix", ""), "XYZ");
+            assert_eq!(c.query_row("SELECT COUNT(*) FROM held_orders", [], |r| r.get::<_, i64>(0)).unwrap(), 0);
+        });
+    }
+}
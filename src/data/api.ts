@@ -15,10 +15,34 @@ function getInvoke(): InvokeFn | null {
 
 const HTTP_BASE = "http://127.0.0.1:7777";
 
+export type ApiErrorCode = "not_found" | "validation" | "conflict" | "internal";
+
+// The backend rejects with `{ code, message }`; wrap it so callers can keep using `error.message`.
+export class ApiError extends Error {
+  code: ApiErrorCode;
+  constructor(code: ApiErrorCode, message: string) {
+    super(message);
+    this.name = "ApiError";
+    this.code = code;
+  }
+}
+
+function toApiError(raw: unknown): Error {
+  if (raw && typeof raw === "object" && "code" in raw && "message" in raw) {
+    const e = raw as { code: ApiErrorCode; message: string };
+    return new ApiError(e.code, e.message);
+  }
+  return raw instanceof Error ? raw : new Error(String(raw));
+}
+
 async function call<T>(method: string, path: string, body?: unknown): Promise<T> {
   const invoke = getInvoke();
   if (invoke) {
-    return invoke("api_call", { method, path, body: body ?? null }) as Promise<T>;
+    try {
+      return (await invoke("api_call", { method, path, body: body ?? null })) as T;
+    } catch (raw) {
+      throw toApiError(raw);
+    }
   }
   // Fallback: direct HTTP (for dev without tauri)
   const opts: RequestInit = { method, headers: { "Content-Type": "application/json" } };
@@ -15,7 +15,7 @@ export type BillItem = {
   line_total_cents: number;
 };
 
-export type PaymentMode = "cash" | "online" | "split";
+export type PaymentMode = "cash" | "card" | "upi" | "other" | "online" | "split";
 
 export type Bill = {
   id: number;
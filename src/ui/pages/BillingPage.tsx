@@ -15,6 +15,7 @@ type ReceiptPayload = {
   discountRateBps: number;
   discountCents: number;
   totalCents: number;
+  paymentMode?: PaymentMode;
   items: Array<{
     name: string;
     qty: number;
@@ -107,6 +108,7 @@ const BillingPage: React.FC = () => {
       discountRateBps,
       discountCents,
       totalCents: total,
+      paymentMode,
       items: billItems.map((item) => ({
         name: item.product_name,
         qty: item.qty,
@@ -114,7 +116,7 @@ const BillingPage: React.FC = () => {
         lineTotalCents: item.line_total_cents,
       })),
     };
-  }, [discountCents, discountRateBps, subtotal, total]);
+  }, [discountCents, discountRateBps, paymentMode, subtotal, total]);
 
   const printReceipt = useCallback(async (payload: ReceiptPayload) => {
     setPrinting(true);